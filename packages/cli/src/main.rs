@@ -1,6 +1,6 @@
 use std::{
     error::Error,
-    io::{self},
+    io::{self, Write},
     process::ExitCode,
     result,
     time::Duration,
@@ -175,6 +175,91 @@ impl Runnable for Verify {
     }
 }
 
+/// Reads a password from stdin if one was not given on the command line.
+fn read_password(password: &Option<String>) -> io::Result<String> {
+    if let Some(password) = password {
+        return Ok(password.clone());
+    }
+
+    print!("Password: ");
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+#[derive(Subcommand)]
+enum UserAction {
+    /// Creates a new verified user.
+    Create {
+        email: String,
+        #[clap(long)]
+        fullname: Option<String>,
+        /// The password, read from stdin if not given.
+        #[clap(long)]
+        password: Option<String>,
+        #[clap(long)]
+        administrator: bool,
+    },
+    /// Sets a user's password.
+    Password {
+        email: String,
+        /// The password, read from stdin if not given.
+        #[clap(long)]
+        password: Option<String>,
+    },
+    /// Prevents a user from logging in.
+    Disable { email: String },
+    /// Allows a disabled user to log in again.
+    Enable { email: String },
+    /// Makes a user an administrator.
+    Promote { email: String },
+    /// Removes a user's administrator access.
+    Demote { email: String },
+    /// Turns off two-factor authentication for a user who has lost access to
+    /// their authenticator and recovery codes.
+    DisableTotp { email: String },
+}
+
+#[derive(Args)]
+struct User {
+    #[clap(subcommand)]
+    action: UserAction,
+}
+
+impl Runnable for User {
+    fn span(&self) -> Span {
+        span!(Level::INFO, "user")
+    }
+
+    async fn run(&self, store: &Store) -> Result {
+        let mut conn = store.pooled();
+
+        match &self.action {
+            UserAction::Create {
+                email,
+                fullname,
+                password,
+                administrator,
+            } => {
+                let password = read_password(password)?;
+                conn.create_user(email, &password, fullname.as_deref(), *administrator)
+                    .await
+            }
+            UserAction::Password { email, password } => {
+                let password = read_password(password)?;
+                conn.set_user_password(email, &password).await
+            }
+            UserAction::Disable { email } => conn.set_user_disabled(email, true).await,
+            UserAction::Enable { email } => conn.set_user_disabled(email, false).await,
+            UserAction::Promote { email } => conn.set_user_administrator(email, true).await,
+            UserAction::Demote { email } => conn.set_user_administrator(email, false).await,
+            UserAction::DisableTotp { email } => conn.disable_user_totp(email).await,
+        }
+    }
+}
+
 #[derive(Args)]
 struct Serve;
 
@@ -240,7 +325,8 @@ enum Command {
     Worker,
     /// List some basic stats about objects in the database.
     Stats,
-    /// Reprocesses media where necessary.
+    /// Reprocesses media where necessary, including hashing files stored
+    /// before hashes were recorded.
     Reprocess,
     /// Verifies database and storage consistency.
     Verify,
@@ -248,6 +334,8 @@ enum Command {
     ProcessSubscriptions,
     /// Sends test emails.
     SendMail,
+    /// Manages user accounts.
+    User,
 }
 
 #[enum_dispatch(Command)]
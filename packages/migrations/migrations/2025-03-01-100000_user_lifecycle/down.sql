@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "user_verification";
+ALTER TABLE "user" DROP COLUMN IF EXISTS "disabled";
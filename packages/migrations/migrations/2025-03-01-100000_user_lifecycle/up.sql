@@ -0,0 +1,12 @@
+ALTER TABLE "user" ADD COLUMN IF NOT EXISTS "disabled" boolean NOT NULL DEFAULT FALSE;
+
+-- Logging in now requires a verified email address. Accounts that already
+-- have a password were set up by hand so are trusted.
+UPDATE "user" SET "verified"=TRUE WHERE "password" IS NOT NULL;
+
+CREATE TABLE IF NOT EXISTS "user_verification" (
+    token character varying(30) NOT NULL PRIMARY KEY,
+    email text NOT NULL,
+    request timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS "api_token_idx_email";
+DROP INDEX IF EXISTS "api_token_idx_token";
+DROP TABLE IF EXISTS "api_token";
@@ -0,0 +1,15 @@
+CREATE TABLE IF NOT EXISTS "api_token" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    email text NOT NULL,
+    name text NOT NULL,
+    token character varying(30) NOT NULL,
+    scope character varying(20) NOT NULL,
+    catalog character varying(30),
+    created timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used timestamp with time zone,
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT "foreign_catalog" FOREIGN KEY (catalog) REFERENCES "catalog"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS "api_token_idx_token" ON "api_token" USING btree (token);
+CREATE INDEX IF NOT EXISTS "api_token_idx_email" ON "api_token" USING btree (email);
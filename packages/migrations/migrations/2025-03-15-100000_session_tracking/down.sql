@@ -0,0 +1,7 @@
+ALTER TABLE "auth_token" DROP COLUMN IF EXISTS "user_agent";
+ALTER TABLE "auth_token" DROP COLUMN IF EXISTS "client_ip";
+ALTER TABLE "auth_token" DROP COLUMN IF EXISTS "last_used";
+ALTER TABLE "auth_token" DROP COLUMN IF EXISTS "created";
+ALTER TABLE "auth_token" ALTER COLUMN "expiry" DROP NOT NULL;
+DROP INDEX IF EXISTS "auth_token_idx_id";
+ALTER TABLE "auth_token" DROP COLUMN IF EXISTS "id";
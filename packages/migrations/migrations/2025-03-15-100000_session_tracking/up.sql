@@ -0,0 +1,12 @@
+ALTER TABLE "auth_token" ADD COLUMN IF NOT EXISTS "id" character varying(30);
+UPDATE "auth_token" SET "id"='N:' || substr(md5("token"), 1, 10) WHERE "id" IS NULL;
+ALTER TABLE "auth_token" ALTER COLUMN "id" SET NOT NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS "auth_token_idx_id" ON "auth_token" USING btree (id);
+
+UPDATE "auth_token" SET "expiry"=CURRENT_TIMESTAMP + INTERVAL '90 days' WHERE "expiry" IS NULL;
+ALTER TABLE "auth_token" ALTER COLUMN "expiry" SET NOT NULL;
+
+ALTER TABLE "auth_token" ADD COLUMN IF NOT EXISTS "created" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP;
+ALTER TABLE "auth_token" ADD COLUMN IF NOT EXISTS "last_used" timestamp with time zone;
+ALTER TABLE "auth_token" ADD COLUMN IF NOT EXISTS "client_ip" text;
+ALTER TABLE "auth_token" ADD COLUMN IF NOT EXISTS "user_agent" text;
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS "oidc_login" (
+    state character varying(30) NOT NULL PRIMARY KEY,
+    nonce character varying(30) NOT NULL,
+    request timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,5 @@
+DROP TABLE IF EXISTS "login_challenge";
+DROP TABLE IF EXISTS "user_recovery_code";
+ALTER TABLE "user" DROP COLUMN IF EXISTS "totp_step";
+ALTER TABLE "user" DROP COLUMN IF EXISTS "totp";
+ALTER TABLE "user" DROP COLUMN IF EXISTS "totp_secret";
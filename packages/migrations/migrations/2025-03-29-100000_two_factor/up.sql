@@ -0,0 +1,18 @@
+ALTER TABLE "user" ADD COLUMN IF NOT EXISTS "totp_secret" text;
+ALTER TABLE "user" ADD COLUMN IF NOT EXISTS "totp" boolean NOT NULL DEFAULT FALSE;
+ALTER TABLE "user" ADD COLUMN IF NOT EXISTS "totp_step" bigint;
+
+CREATE TABLE IF NOT EXISTS "user_recovery_code" (
+    email text NOT NULL,
+    code character varying(64) NOT NULL,
+    CONSTRAINT "user_recovery_code_pkey" PRIMARY KEY (email, code),
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS "login_challenge" (
+    challenge character varying(30) NOT NULL PRIMARY KEY,
+    email text NOT NULL,
+    attempts integer NOT NULL DEFAULT 0,
+    request timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE
+);
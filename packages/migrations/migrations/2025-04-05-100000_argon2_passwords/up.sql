@@ -0,0 +1,2 @@
+-- Argon2 hashes are longer than bcrypt hashes.
+ALTER TABLE "user" ALTER COLUMN "password" TYPE text;
@@ -0,0 +1,2 @@
+DROP VIEW IF EXISTS "album_share_media";
+DROP TABLE IF EXISTS "album_share";
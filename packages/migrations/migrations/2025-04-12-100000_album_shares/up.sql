@@ -0,0 +1,24 @@
+CREATE TABLE IF NOT EXISTS "album_share" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    -- The credential used to access the shared media. The same as the id
+    -- unless the share is password protected.
+    access character varying(30) NOT NULL,
+    album character varying(30) NOT NULL,
+    recursive boolean NOT NULL DEFAULT FALSE,
+    password text,
+    expiry timestamp with time zone,
+    created timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "foreign_album" FOREIGN KEY (album) REFERENCES "album"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS "idx_album_share_access" ON "album_share" USING btree (access);
+CREATE INDEX IF NOT EXISTS "idx_album_share_album" ON "album_share" USING btree (album);
+
+CREATE OR REPLACE VIEW "album_share_media" AS
+    SELECT "album_share"."access", "media_album"."media"
+    FROM "album_share"
+        JOIN "album_descendent" ON "album_descendent"."id"="album_share"."album"
+        JOIN "media_album" ON "media_album"."album"="album_descendent"."descendent"
+    WHERE
+        ("album_share"."recursive" OR "album_descendent"."descendent"="album_share"."album") AND
+        ("album_share"."expiry" IS NULL OR "album_share"."expiry" > CURRENT_TIMESTAMP);
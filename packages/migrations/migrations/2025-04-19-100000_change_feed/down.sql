@@ -0,0 +1,6 @@
+DROP TABLE IF EXISTS "media_tombstone";
+ALTER TABLE "media_album" DROP COLUMN IF EXISTS "added";
+DROP TRIGGER IF EXISTS "touch_media_item" ON "media_item";
+DROP FUNCTION IF EXISTS touch_media_item();
+DROP INDEX IF EXISTS "media_item_idx_updated";
+ALTER TABLE "media_item" DROP COLUMN IF EXISTS "updated";
@@ -0,0 +1,37 @@
+ALTER TABLE "media_item" ADD COLUMN IF NOT EXISTS "updated" timestamp with time zone;
+UPDATE "media_item" SET "updated"=GREATEST(
+    "created",
+    (SELECT "uploaded" FROM "media_file" WHERE "media_file"."id"="media_item"."media_file")
+);
+ALTER TABLE "media_item"
+    ALTER COLUMN "updated" SET DEFAULT CURRENT_TIMESTAMP,
+    ALTER COLUMN "updated" SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS "media_item_idx_updated" ON "media_item" USING btree (catalog, updated);
+
+CREATE OR REPLACE FUNCTION touch_media_item() RETURNS trigger
+    LANGUAGE plpgsql
+    AS $$
+          BEGIN
+            IF NEW IS DISTINCT FROM OLD THEN
+              NEW.updated := CURRENT_TIMESTAMP;
+            END IF;
+            RETURN NEW;
+          END; $$;
+
+CREATE OR REPLACE TRIGGER "touch_media_item"
+  BEFORE UPDATE ON "media_item" FOR EACH ROW
+  EXECUTE FUNCTION touch_media_item();
+
+ALTER TABLE "media_album" ADD COLUMN IF NOT EXISTS "added" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP;
+
+-- Records media deleted from a catalog so that clients syncing changes can
+-- remove them. The media item itself is pruned later.
+CREATE TABLE IF NOT EXISTS "media_tombstone" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    catalog character varying(30) NOT NULL,
+    deleted timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "foreign_catalog" FOREIGN KEY (catalog) REFERENCES "catalog"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idx_media_tombstone_catalog" ON "media_tombstone" USING btree (catalog, deleted);
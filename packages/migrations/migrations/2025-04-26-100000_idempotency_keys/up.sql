@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS "idempotency_key" (
+    email text NOT NULL,
+    route text NOT NULL,
+    key text NOT NULL,
+    response jsonb,
+    created timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (email, route, key),
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idempotency_key_idx_created" ON "idempotency_key" USING btree (created);
@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS "media_upload" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    email text NOT NULL,
+    media_item character varying(30) NOT NULL,
+    file_name text,
+    created timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    CONSTRAINT "foreign_user" FOREIGN KEY (email) REFERENCES "user"(email) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT "foreign_media_item" FOREIGN KEY (media_item) REFERENCES "media_item"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
@@ -0,0 +1,16 @@
+DROP VIEW IF EXISTS "latest_media_file";
+
+DROP INDEX IF EXISTS "media_file_idx_sha256";
+ALTER TABLE "media_file" DROP COLUMN IF EXISTS "sha256";
+
+CREATE VIEW "latest_media_file" AS
+  SELECT DISTINCT ON ("media_item") "media_file".*
+    FROM "media_file"
+    WHERE
+      NOT "needs_metadata" AND
+      "id" NOT IN (
+        SELECT DISTINCT "media_file"
+        FROM "alternate_file"
+        WHERE "stored" IS NULL AND "required"
+      )
+    ORDER BY "media_item", "uploaded" DESC;
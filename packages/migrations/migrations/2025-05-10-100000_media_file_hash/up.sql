@@ -0,0 +1,16 @@
+ALTER TABLE "media_file" ADD COLUMN IF NOT EXISTS "sha256" character varying(64);
+
+CREATE INDEX IF NOT EXISTS "media_file_idx_sha256" ON "media_file" USING btree (sha256);
+
+-- Picks up the new column.
+CREATE OR REPLACE VIEW "latest_media_file" AS
+  SELECT DISTINCT ON ("media_item") "media_file".*
+    FROM "media_file"
+    WHERE
+      NOT "needs_metadata" AND
+      "id" NOT IN (
+        SELECT DISTINCT "media_file"
+        FROM "alternate_file"
+        WHERE "stored" IS NULL AND "required"
+      )
+    ORDER BY "media_item", "uploaded" DESC;
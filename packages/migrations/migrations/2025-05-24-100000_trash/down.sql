@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS "album_trash";
+
+ALTER TABLE "media_item" DROP COLUMN IF EXISTS "trashed";
@@ -0,0 +1,21 @@
+ALTER TABLE "media_item" ADD COLUMN IF NOT EXISTS "trashed" timestamp with time zone;
+UPDATE "media_item" SET "trashed"=COALESCE(
+    (SELECT "deleted" FROM "media_tombstone" WHERE "media_tombstone"."id"="media_item"."id"),
+    CURRENT_TIMESTAMP
+)
+WHERE "deleted";
+
+-- Deleted albums along with their descendents, the media in them and their
+-- shares so that they can be restored.
+CREATE TABLE IF NOT EXISTS "album_trash" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    catalog character varying(30) NOT NULL,
+    name text NOT NULL,
+    trashed timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    albums jsonb NOT NULL,
+    media jsonb NOT NULL,
+    shares jsonb NOT NULL,
+    CONSTRAINT "foreign_catalog" FOREIGN KEY (catalog) REFERENCES "catalog"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idx_album_trash_catalog" ON "album_trash" USING btree (catalog, trashed);
@@ -0,0 +1,15 @@
+-- When the media item was last reverted to this file.
+ALTER TABLE "media_file" ADD COLUMN IF NOT EXISTS "reverted" timestamp with time zone;
+
+-- The latest file is the one most recently uploaded or reverted to.
+CREATE OR REPLACE VIEW "latest_media_file" AS
+  SELECT DISTINCT ON ("media_item") "media_file".*
+    FROM "media_file"
+    WHERE
+      NOT "needs_metadata" AND
+      "id" NOT IN (
+        SELECT DISTINCT "media_file"
+        FROM "alternate_file"
+        WHERE "stored" IS NULL AND "required"
+      )
+    ORDER BY "media_item", COALESCE("reverted", "uploaded") DESC;
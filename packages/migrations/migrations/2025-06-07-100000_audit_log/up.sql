@@ -0,0 +1,16 @@
+-- Changes made to the media, albums, tags and people in a catalog.
+CREATE TABLE IF NOT EXISTS "audit_log" (
+    id character varying(30) NOT NULL PRIMARY KEY,
+    catalog character varying(30) NOT NULL,
+    -- Not a foreign key so that entries outlive the user that made them.
+    email text NOT NULL,
+    created timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    action text NOT NULL,
+    -- The media item, album, tag or person that was changed.
+    target character varying(30) NOT NULL,
+    changes jsonb NOT NULL,
+    CONSTRAINT "foreign_catalog" FOREIGN KEY (catalog) REFERENCES "catalog"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idx_audit_log_catalog" ON "audit_log" USING btree (catalog, created);
+CREATE INDEX IF NOT EXISTS "idx_audit_log_target" ON "audit_log" USING btree (target, created);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "similar_media_file";
+ALTER TABLE "media_file" DROP COLUMN IF EXISTS "similar_pending";
@@ -0,0 +1,17 @@
+-- Pairs of media files in the same catalog whose perceptual hashes are close,
+-- recorded in both directions when a hash is computed.
+CREATE TABLE IF NOT EXISTS "similar_media_file" (
+    media_file character varying(30) NOT NULL,
+    "similar" character varying(30) NOT NULL,
+    distance integer NOT NULL,
+    PRIMARY KEY (media_file, "similar"),
+    CONSTRAINT "foreign_media_file" FOREIGN KEY (media_file) REFERENCES "media_file"(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT "foreign_similar" FOREIGN KEY ("similar") REFERENCES "media_file"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idx_similar_media_file_similar" ON "similar_media_file" USING btree ("similar");
+
+-- Hashes computed before this table existed are compared to the rest of
+-- their catalog by the media processing task.
+ALTER TABLE "media_file" ADD COLUMN IF NOT EXISTS "similar_pending" boolean NOT NULL DEFAULT FALSE;
+UPDATE "media_file" SET "similar_pending"=TRUE WHERE "phash" IS NOT NULL;
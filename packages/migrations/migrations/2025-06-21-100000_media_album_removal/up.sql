@@ -0,0 +1,14 @@
+-- Records media removed from an album so that clients syncing the album's
+-- changes can remove them.
+CREATE TABLE IF NOT EXISTS "media_album_removal" (
+    catalog character varying(30) NOT NULL,
+    album character varying(30) NOT NULL,
+    media character varying(30) NOT NULL,
+    removed timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (album, media),
+    CONSTRAINT "foreign_catalog" FOREIGN KEY (catalog) REFERENCES "catalog"(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT "foreign_album" FOREIGN KEY (album) REFERENCES "album"(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    CONSTRAINT "foreign_media" FOREIGN KEY (media) REFERENCES "media_item"(id) ON UPDATE CASCADE ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "idx_media_album_removal_album" ON "media_album_removal" USING btree (album, removed);
@@ -0,0 +1,3 @@
+-- Identifies the request that claimed a key so that a different request
+-- reusing the key is rejected rather than given the wrong response.
+ALTER TABLE "idempotency_key" ADD COLUMN IF NOT EXISTS "request_hash" text;
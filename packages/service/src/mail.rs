@@ -9,7 +9,7 @@ use mime::Mime;
 use pixelbin_shared::{Config, MailServer};
 use tracing::{error, trace, warn};
 
-use crate::store::models::SavedSearch;
+use crate::store::models::{Catalog, SavedSearch};
 
 const LOGO_IMAGE: &[u8; 10913] = include_bytes!("../templates/logo.png");
 
@@ -131,6 +131,46 @@ impl MessageTemplate for SavedSearchUpdate<'_> {
     }
 }
 
+#[derive(Template)]
+#[template(path = "catalog_invitation.html")]
+pub(crate) struct CatalogInvitation<'a> {
+    pub(crate) base_url: String,
+    pub(crate) catalog: &'a Catalog,
+    pub(crate) inviter: &'a str,
+    pub(crate) email: &'a str,
+}
+
+impl MessageTemplate for CatalogInvitation<'_> {
+    fn address(&self) -> String {
+        self.email.to_owned()
+    }
+
+    fn subject(&self) -> String {
+        format!(
+            "📷 PixelBin: {} shared \"{}\" with you",
+            self.inviter, self.catalog.name
+        )
+    }
+}
+
+#[derive(Template)]
+#[template(path = "verify_email.html")]
+pub(crate) struct VerifyEmail<'a> {
+    pub(crate) base_url: String,
+    pub(crate) email: &'a str,
+    pub(crate) token: &'a str,
+}
+
+impl MessageTemplate for VerifyEmail<'_> {
+    fn address(&self) -> String {
+        self.email.to_owned()
+    }
+
+    fn subject(&self) -> String {
+        "📷 PixelBin: Verify your email address".to_string()
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
 enum TlsType {
     None,
@@ -80,6 +80,41 @@ pub(crate) async fn crop_image(
     .await
 }
 
+/// Computes a difference hash of the image. Each bit records whether a pixel
+/// of a shrunk greyscale copy is brighter than its neighbour to the right so
+/// visually similar images produce hashes that differ in only a few bits.
+fn difference_hash(source_image: &DynamicImage) -> i64 {
+    let pixels = source_image
+        .resize_exact(9, 8, FilterType::Triangle)
+        .into_luma8();
+
+    let mut hash = 0_u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if pixels.get_pixel(x, y)[0] > pixels.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+
+    // Stored as a signed bigint in the database.
+    hash as i64
+}
+
+pub(crate) async fn perceptual_hash(source_image: DynamicImage) -> i64 {
+    spawn_blocking(
+        span!(
+            Level::TRACE,
+            "perceptual hash",
+            "source_width" = source_image.width(),
+            "source_height" = source_image.height(),
+        ),
+        move || difference_hash(&source_image),
+    )
+    .await
+}
+
 fn encode_image(
     source_image: &DynamicImage,
     target_mime: &Mime,
@@ -292,3 +327,30 @@ pub(super) async fn load_data(
         _ => Err(Error::UnsupportedMedia { mime: mime.clone() }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::difference_hash;
+
+    fn gradient(width: u32, height: u32, reverse: bool) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, _| {
+            let value = (x * 255 / (width - 1)) as u8;
+            Luma([if reverse { 255 - value } else { value }])
+        }))
+    }
+
+    #[test]
+    fn difference_hashes() {
+        // Brightness increases to the right so no pixel is brighter than its neighbour.
+        assert_eq!(difference_hash(&gradient(90, 80, false)), 0);
+        assert_eq!(difference_hash(&gradient(90, 80, true)), -1);
+
+        // Resizing an image barely changes the hash.
+        let original = gradient(900, 600, true);
+        let resized = original.resize_exact(300, 200, image::imageops::FilterType::Nearest);
+        let distance = (difference_hash(&original) ^ difference_hash(&resized)).count_ones();
+        assert!(distance <= 2);
+    }
+}
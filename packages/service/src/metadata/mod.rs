@@ -5,7 +5,7 @@ use chrono_tz::Tz;
 use exif::ExifData;
 use image::DynamicImage;
 use lazy_static::lazy_static;
-pub(crate) use media::{crop_image, load_source_image, resize_image};
+pub(crate) use media::{crop_image, load_source_image, perceptual_hash, resize_image};
 use mime::Mime;
 use serde::{Deserialize, Serialize};
 use serde_json::from_str;
@@ -0,0 +1,106 @@
+use actix_web::{get, post, web};
+use serde::Deserialize;
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::{
+    server::{auth::AdminSession, ApiErrorCode, ApiResult, AppState},
+    store::{db::Isolation, models},
+};
+
+#[utoipa::path(responses((status = OK, body = Vec<models::User>), ApiErrorCode))]
+#[get("/admin/users")]
+#[instrument(err, skip(app_state, _session))]
+async fn list_users(
+    app_state: web::Data<AppState>,
+    _session: AdminSession,
+) -> ApiResult<web::Json<Vec<models::User>>> {
+    let mut conn = app_state.store.connect().await?;
+    Ok(web::Json(models::User::list(&mut conn).await?))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct CreateUserRequest {
+    email: String,
+    password: String,
+    fullname: Option<String>,
+    #[serde(default)]
+    administrator: bool,
+}
+
+#[utoipa::path(responses((status = OK, body = models::User), ApiErrorCode))]
+#[post("/admin/user/create")]
+#[instrument(err, skip(app_state, _session, request))]
+async fn create_user(
+    app_state: web::Data<AppState>,
+    _session: AdminSession,
+    request: web::Json<CreateUserRequest>,
+) -> ApiResult<web::Json<models::User>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let user = models::User::create(
+        &mut conn,
+        request.email.trim(),
+        &request.password,
+        request.fullname.as_deref(),
+        request.administrator,
+        true,
+    )
+    .await?;
+    conn.commit().await?;
+
+    Ok(web::Json(user))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct DisableUserRequest {
+    email: String,
+    disabled: bool,
+}
+
+#[utoipa::path(responses((status = OK, body = models::User), ApiErrorCode))]
+#[post("/admin/user/disable")]
+#[instrument(err, skip(app_state, session))]
+async fn disable_user(
+    app_state: web::Data<AppState>,
+    session: AdminSession,
+    request: web::Json<DisableUserRequest>,
+) -> ApiResult<web::Json<models::User>> {
+    if request.disabled && request.email == session.0.user.email {
+        return Err(ApiErrorCode::InvalidData(
+            "You cannot disable your own account".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let user = models::User::set_disabled(&mut conn, &request.email, request.disabled).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(user))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct PromoteUserRequest {
+    email: String,
+    administrator: bool,
+}
+
+#[utoipa::path(responses((status = OK, body = models::User), ApiErrorCode))]
+#[post("/admin/user/promote")]
+#[instrument(err, skip(app_state, session))]
+async fn promote_user(
+    app_state: web::Data<AppState>,
+    session: AdminSession,
+    request: web::Json<PromoteUserRequest>,
+) -> ApiResult<web::Json<models::User>> {
+    if !request.administrator && request.email == session.0.user.email {
+        return Err(ApiErrorCode::InvalidData(
+            "You cannot remove your own administrator access".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.connect().await?;
+    let user =
+        models::User::set_administrator(&mut conn, &request.email, request.administrator).await?;
+
+    Ok(web::Json(user))
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use actix_web::{get, web};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tracing::instrument;
+use utoipa::IntoParams;
+
+use crate::{
+    server::{auth::Session, ApiErrorCode, ApiResult, AppState},
+    store::{
+        db::DbConnection,
+        models::{self, AuditAction, AuditEntry},
+    },
+    Result,
+};
+
+const DEFAULT_AUDIT_COUNT: u32 = 100;
+const MAX_AUDIT_COUNT: u32 = 500;
+
+#[derive(Deserialize, Clone, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct AuditOptions {
+    /// Only returns entries older than the entry with this id. Pass the id of
+    /// the last entry in a response to fetch the next page.
+    before: Option<String>,
+    /// The maximum number of entries to return. Defaults to 100.
+    count: Option<u32>,
+}
+
+impl AuditOptions {
+    fn count(&self) -> i64 {
+        self.count
+            .unwrap_or(DEFAULT_AUDIT_COUNT)
+            .min(MAX_AUDIT_COUNT)
+            .into()
+    }
+}
+
+/// The audited fields of a set of media items, keyed by media id.
+type MediaStates = HashMap<String, Map<String, Value>>;
+
+async fn media_states(
+    conn: &mut DbConnection<'_>,
+    media: &[models::MediaItem],
+) -> Result<MediaStates> {
+    let ids: Vec<String> = media.iter().map(|m| m.id.clone()).collect();
+    let mut relations = models::MediaItem::list_relation_names(conn, &ids).await?;
+
+    let mut states = MediaStates::new();
+    for media_item in media {
+        let mut state = match serde_json::to_value(&media_item.metadata)? {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        };
+
+        let (tags, people) = relations.remove(&media_item.id).unwrap_or_default();
+        state.insert("public".to_owned(), media_item.public.into());
+        state.insert("tags".to_owned(), tags.into());
+        state.insert("people".to_owned(), people.into());
+
+        states.insert(media_item.id.clone(), state);
+    }
+
+    Ok(states)
+}
+
+/// Captures the state of media items before they are changed so the changes
+/// can be recorded afterwards.
+pub(super) struct MediaAudit {
+    states: MediaStates,
+}
+
+impl MediaAudit {
+    pub(super) async fn capture(
+        conn: &mut DbConnection<'_>,
+        media: &[models::MediaItem],
+    ) -> Result<Self> {
+        Ok(Self {
+            states: media_states(conn, media).await?,
+        })
+    }
+
+    /// Records an entry for each media item that has changed since it was
+    /// captured. Actions other than edits are always recorded.
+    pub(super) async fn record(
+        mut self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+        action: AuditAction,
+        media: &[models::MediaItem],
+    ) -> Result {
+        let mut states = media_states(conn, media).await?;
+
+        let mut entries = Vec::new();
+        for media_item in media {
+            let old = self.states.remove(&media_item.id).unwrap_or_default();
+            let new = states.remove(&media_item.id).unwrap_or_default();
+
+            let mut entry = AuditEntry::new(
+                &media_item.catalog,
+                &session.user.email,
+                action,
+                &media_item.id,
+            );
+
+            for (field, value) in new.iter() {
+                entry = entry.change(field, old.get(field).unwrap_or(&Value::Null), value);
+            }
+
+            if action != AuditAction::EditMedia || !entry.changes.is_empty() {
+                entries.push(entry);
+            }
+        }
+
+        AuditEntry::insert(conn, &entries).await
+    }
+}
+
+/// Lists the changes made to the media item, newest first.
+#[utoipa::path(params(AuditOptions), responses((status = OK, body = Vec<models::AuditEntry>), ApiErrorCode))]
+#[get("/media/{media_id}/audit")]
+#[instrument(err, skip(app_state, session))]
+async fn list_media_audit(
+    app_state: web::Data<AppState>,
+    session: Session,
+    media_id: web::Path<String>,
+    options: web::Query<AuditOptions>,
+) -> ApiResult<web::Json<Vec<models::AuditEntry>>> {
+    let mut conn = app_state.store.connect().await?;
+    let mut entries = AuditEntry::list_for_target(
+        &mut conn,
+        &session.user.email,
+        &media_id,
+        options.before.as_deref(),
+        options.count(),
+    )
+    .await?;
+    entries.retain(|entry| session.check_catalog(&entry.catalog).is_ok());
+
+    Ok(web::Json(entries))
+}
+
+/// Lists the changes made to the media, albums, tags and people in the
+/// catalog, newest first.
+#[utoipa::path(params(AuditOptions), responses((status = OK, body = Vec<models::AuditEntry>), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/audit")]
+#[instrument(err, skip(app_state, session))]
+async fn list_catalog_audit(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+    options: web::Query<AuditOptions>,
+) -> ApiResult<web::Json<Vec<models::AuditEntry>>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+
+    Ok(web::Json(
+        AuditEntry::list_for_catalog(
+            &mut conn,
+            &user_catalog.catalog.id,
+            options.before.as_deref(),
+            options.count(),
+        )
+        .await?,
+    ))
+}
@@ -1,14 +1,22 @@
-use actix_web::{dev::Payload, get, http::header, post, web, FromRequest, HttpRequest};
+use actix_web::{
+    dev::Payload,
+    get,
+    http::{header, Method},
+    post, web, FromRequest, HttpRequest,
+};
 use futures::{future::LocalBoxFuture, join};
 use pixelbin_shared::Ignorable;
 use serde::{Deserialize, Serialize};
 use tracing::{instrument, trace, warn, Instrument};
+use utoipa::ToSchema;
 
 use crate::{
-    server::{ApiErrorCode, ApiResult, AppState},
+    mail::{send_messages, VerifyEmail},
+    server::{middleware::client_addr, ApiErrorCode, ApiResponse, ApiResult, AppState},
+    shared::totp,
     store::{
         db::Isolation,
-        models::{self, AlbumWithCount, SavedSearchWithCount, UserCatalogWithCount},
+        models::{self, AlbumWithCount, SavedSearchWithCount, TokenScope, UserCatalogWithCount},
     },
     Error,
 };
@@ -16,6 +24,66 @@ use crate::{
 #[derive(Clone)]
 pub(super) struct Session {
     pub(crate) user: models::User,
+    /// The login session in use, if not using an API token.
+    pub(crate) session_id: Option<String>,
+    pub(crate) api_token: Option<models::ApiToken>,
+}
+
+/// Returned when an API token is used for a catalog it is not limited to.
+/// Reported as `NotFound` so as not to reveal the catalog exists.
+pub(super) struct CatalogDenied;
+
+impl From<CatalogDenied> for ApiErrorCode {
+    fn from(_: CatalogDenied) -> Self {
+        ApiErrorCode::NotFound
+    }
+}
+
+impl Session {
+    /// API tokens may be limited to a single catalog. Fails if this session
+    /// cannot access the given catalog.
+    pub(crate) fn check_catalog(&self, catalog: &str) -> Result<(), CatalogDenied> {
+        match self.api_token.as_ref().and_then(|t| t.catalog.as_deref()) {
+            Some(allowed) if allowed != catalog => Err(CatalogDenied),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The scope an API token needs for a request, or `None` if the request can
+/// only be made with a full login session.
+fn required_scope(req: &HttpRequest) -> Option<TokenScope> {
+    let path = req.path().strip_prefix("/api").unwrap_or(req.path());
+
+    if path == "/password"
+        || path.starts_with("/session")
+        || path.starts_with("/token")
+        || path.starts_with("/totp/")
+        || path.starts_with("/admin/")
+        || path.starts_with("/storage/")
+        || matches!(
+            path,
+            "/catalog/create"
+                | "/catalog/edit"
+                | "/catalog/delete"
+                | "/catalog/share"
+                | "/catalog/unshare"
+        )
+    {
+        return None;
+    }
+
+    if req.method() == Method::GET {
+        return Some(TokenScope::Read);
+    }
+
+    match path {
+        "/search" | "/media/sign" => Some(TokenScope::Read),
+        "/media/create" | "/media/upload" | "/media/edit" | "/media/bulk-edit" | "/source"
+        | "/album/create" | "/album/media" => Some(TokenScope::Upload),
+        path if path.starts_with("/media/upload/") => Some(TokenScope::Upload),
+        _ => Some(TokenScope::Write),
+    }
 }
 
 impl FromRequest for Session {
@@ -37,6 +105,42 @@ impl FromRequest for Session {
     }
 }
 
+/// A session for a user with the administrator flag set.
+pub(super) struct AdminSession(pub(super) Session);
+
+impl FromRequest for AdminSession {
+    type Error = ApiErrorCode;
+    type Future = LocalBoxFuture<'static, ApiResult<Self>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            let session = Session::extract(&req).await?;
+            if session.user.administrator {
+                Ok(AdminSession(session))
+            } else {
+                warn!(
+                    email = session.user.email,
+                    "Non-administrator attempted admin access"
+                );
+                Err(ApiErrorCode::NotAuthorized)
+            }
+        })
+    }
+}
+
+pub(super) fn session_client(req: &HttpRequest) -> models::SessionClient {
+    models::SessionClient {
+        ip: client_addr(req),
+        user_agent: req
+            .headers()
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|h| h.to_owned()),
+    }
+}
+
 struct AuthToken(Option<String>);
 
 impl FromRequest for AuthToken {
@@ -75,6 +179,13 @@ impl MaybeSession {
     pub(crate) fn session(&self) -> Option<&Session> {
         self.0.as_ref()
     }
+
+    pub(crate) fn check_catalog(&self, catalog: &str) -> Result<(), CatalogDenied> {
+        match self.0 {
+            Some(ref session) => session.check_catalog(catalog),
+            None => Ok(()),
+        }
+    }
 }
 
 impl FromRequest for MaybeSession {
@@ -93,37 +204,106 @@ impl FromRequest for MaybeSession {
 
             let data = web::Data::<AppState>::extract(&req).await.unwrap();
             let mut conn = data.store.connect().await?;
-            let user = models::User::verify_token(&mut conn, &token).await?;
 
-            Ok(MaybeSession(user.map(|user| Session { user })))
+            if !token.starts_with("K:") {
+                let client = session_client(&req);
+                let found = models::User::verify_token(&mut conn, &token, &client).await?;
+
+                return Ok(MaybeSession(found.map(|(user, session_id)| Session {
+                    user,
+                    session_id: Some(session_id),
+                    api_token: None,
+                })));
+            }
+
+            let (user, api_token) = match models::ApiToken::verify(&mut conn, &token).await? {
+                Some(found) => found,
+                None => return Ok(MaybeSession(None)),
+            };
+
+            match required_scope(&req) {
+                Some(scope) if api_token.scope >= scope => (),
+                _ => {
+                    warn!(
+                        token = api_token.id,
+                        path = req.path(),
+                        "API token does not have the required scope"
+                    );
+                    return Err(ApiErrorCode::NotAuthorized);
+                }
+            }
+
+            let session = Session {
+                user,
+                session_id: None,
+                api_token: Some(api_token),
+            };
+
+            // Catalogs named in the path are checked here so that no handler
+            // can miss them. Handlers must still check any other catalogs
+            // they access.
+            if let Some(catalog) = req.match_info().get("catalog_id") {
+                session.check_catalog(catalog)?;
+            }
+
+            Ok(MaybeSession(Some(session)))
         })
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct LoginRequest {
     email: String,
     password: String,
 }
 
-#[derive(Serialize)]
-struct LoginResponse {
-    token: Option<String>,
+#[derive(Serialize, ToSchema)]
+pub(super) struct LoginResponse {
+    pub(super) token: Option<String>,
+    /// Set when a one-time password must be posted to `/login/totp` to
+    /// complete the login.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(super) challenge: Option<String>,
+}
+
+impl From<models::Login> for LoginResponse {
+    fn from(result: models::Login) -> Self {
+        match result {
+            models::Login::Session(token) => LoginResponse {
+                token: Some(token),
+                challenge: None,
+            },
+            models::Login::SecondFactor(challenge) => LoginResponse {
+                token: None,
+                challenge: Some(challenge),
+            },
+        }
+    }
 }
 
+#[utoipa::path(security(()), responses((status = OK, body = LoginResponse), ApiErrorCode))]
 #[post("/login")]
-#[instrument(err, skip(app_state, credentials))]
+#[instrument(err, skip(app_state, request, credentials))]
 async fn login(
     app_state: web::Data<AppState>,
+    request: HttpRequest,
     credentials: web::Json<LoginRequest>,
 ) -> ApiResult<web::Json<LoginResponse>> {
+    let client = session_client(&request);
+
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
-    match models::User::verify_credentials(&mut conn, &credentials.email, &credentials.password)
-        .await
+    match models::User::verify_credentials(
+        &mut conn,
+        &credentials.email,
+        &credentials.password,
+        &client,
+    )
+    .await
     {
-        Ok((_, token)) => {
+        Ok((_, login)) => {
             conn.commit().await?;
-            Ok(web::Json(LoginResponse { token: Some(token) }))
+
+            Ok(web::Json(login.into()))
         }
         Err(Error::NotFound) => {
             conn.rollback().await.warn();
@@ -136,6 +316,51 @@ async fn login(
     }
 }
 
+#[derive(Deserialize, ToSchema)]
+struct SecondFactorRequest {
+    challenge: String,
+    code: String,
+}
+
+#[utoipa::path(security(()), responses((status = OK, body = LoginResponse), ApiErrorCode))]
+#[post("/login/totp")]
+#[instrument(err, skip(app_state, request, credentials))]
+async fn login_totp(
+    app_state: web::Data<AppState>,
+    request: HttpRequest,
+    credentials: web::Json<SecondFactorRequest>,
+) -> ApiResult<web::Json<LoginResponse>> {
+    let client = session_client(&request);
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    match models::User::verify_second_factor(
+        &mut conn,
+        &credentials.challenge,
+        &credentials.code,
+        &client,
+    )
+    .await
+    {
+        Ok((_, token)) => {
+            conn.commit().await?;
+            Ok(web::Json(LoginResponse {
+                token: Some(token),
+                challenge: None,
+            }))
+        }
+        Err(Error::NotFound) => {
+            // Keep the record of the failed attempt.
+            conn.commit().await?;
+            Err(ApiErrorCode::NotLoggedIn)
+        }
+        Err(e) => {
+            conn.rollback().await.warn();
+            Err(e.into())
+        }
+    }
+}
+
+#[utoipa::path(responses((status = OK, body = LoginResponse), ApiErrorCode))]
 #[post("/logout")]
 #[instrument(err, skip(app_state, token))]
 async fn logout(
@@ -147,10 +372,357 @@ async fn logout(
         models::User::delete_token(&mut conn, &token).await?;
     }
 
-    Ok(web::Json(LoginResponse { token: None }))
+    Ok(web::Json(LoginResponse {
+        token: None,
+        challenge: None,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RegisterRequest {
+    email: String,
+    password: String,
+    fullname: Option<String>,
+}
+
+#[utoipa::path(security(()), responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/register")]
+#[instrument(err, skip(app_state, request))]
+async fn register(
+    app_state: web::Data<AppState>,
+    request: web::Json<RegisterRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let email = request.email.trim();
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let (_, token) = models::User::register(
+        &mut conn,
+        email,
+        &request.password,
+        request.fullname.as_deref(),
+    )
+    .await?;
+    conn.commit().await?;
+
+    send_messages(
+        app_state.store.config(),
+        &[VerifyEmail {
+            base_url: app_state.store.config().base_url.to_string(),
+            email,
+            token: &token,
+        }],
+    )
+    .await;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct VerifyEmailRequest {
+    token: String,
+}
+
+#[utoipa::path(security(()), responses((status = OK, body = models::User), ApiErrorCode))]
+#[post("/register/verify")]
+#[instrument(err, skip(app_state, request))]
+async fn verify_email(
+    app_state: web::Data<AppState>,
+    request: web::Json<VerifyEmailRequest>,
+) -> ApiResult<web::Json<models::User>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let user = models::User::verify_email(&mut conn, &request.token).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(user))
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct ChangePasswordRequest {
+    current_password: String,
+    password: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/password")]
+#[instrument(err, skip(app_state, session, token, request))]
+async fn change_password(
+    app_state: web::Data<AppState>,
+    session: Session,
+    token: AuthToken,
+    request: web::Json<ChangePasswordRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    if let Err(Error::NotFound) = session.user.check_password(&request.current_password).await {
+        return Err(ApiErrorCode::InvalidData(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    models::User::set_password(
+        &mut conn,
+        &session.user.email,
+        &request.password,
+        token.0.as_deref(),
+    )
+    .await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct PasswordRequest {
+    password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TotpEnrolment {
+    secret: String,
+    /// A provisioning URI to display as a QR code.
+    uri: String,
+}
+
+#[utoipa::path(responses((status = OK, body = TotpEnrolment), ApiErrorCode))]
+#[post("/totp/enrol")]
+#[instrument(err, skip(app_state, session, request))]
+async fn enrol_totp(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<PasswordRequest>,
+) -> ApiResult<web::Json<TotpEnrolment>> {
+    if let Err(Error::NotFound) = session.user.check_password(&request.password).await {
+        return Err(ApiErrorCode::InvalidData(
+            "Password is incorrect".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let secret = models::User::enrol_totp(&mut conn, &session.user.email).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(TotpEnrolment {
+        uri: totp::provisioning_uri(&secret, &session.user.email),
+        secret,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct ConfirmTotpRequest {
+    code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[utoipa::path(responses((status = OK, body = RecoveryCodes), ApiErrorCode))]
+#[post("/totp/confirm")]
+#[instrument(err, skip(app_state, session, request))]
+async fn confirm_totp(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<ConfirmTotpRequest>,
+) -> ApiResult<web::Json<RecoveryCodes>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let recovery_codes =
+        models::User::confirm_totp(&mut conn, &session.user.email, &request.code).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(RecoveryCodes { recovery_codes }))
+}
+
+#[utoipa::path(responses((status = OK, body = RecoveryCodes), ApiErrorCode))]
+#[post("/totp/recovery")]
+#[instrument(err, skip(app_state, session, request))]
+async fn reset_recovery_codes(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<PasswordRequest>,
+) -> ApiResult<web::Json<RecoveryCodes>> {
+    if !session.user.totp {
+        return Err(ApiErrorCode::InvalidData(
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+
+    if let Err(Error::NotFound) = session.user.check_password(&request.password).await {
+        return Err(ApiErrorCode::InvalidData(
+            "Password is incorrect".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let recovery_codes = models::User::reset_recovery_codes(&mut conn, &session.user.email).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(RecoveryCodes { recovery_codes }))
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/totp/disable")]
+#[instrument(err, skip(app_state, session, request))]
+async fn disable_totp(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<PasswordRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    if let Err(Error::NotFound) = session.user.check_password(&request.password).await {
+        return Err(ApiErrorCode::InvalidData(
+            "Password is incorrect".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    models::User::disable_totp(&mut conn, &session.user.email).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Serialize, ToSchema)]
+struct SessionInfo {
+    #[serde(flatten)]
+    session: models::AuthSession,
+    current: bool,
+}
+
+#[utoipa::path(responses((status = OK, body = Vec<SessionInfo>), ApiErrorCode))]
+#[get("/sessions")]
+#[instrument(err, skip(app_state, session))]
+async fn list_sessions(
+    app_state: web::Data<AppState>,
+    session: Session,
+) -> ApiResult<web::Json<Vec<SessionInfo>>> {
+    let mut conn = app_state.store.connect().await?;
+    let sessions = models::User::list_sessions(&mut conn, &session.user.email).await?;
+
+    Ok(web::Json(
+        sessions
+            .into_iter()
+            .map(|s| SessionInfo {
+                current: session.session_id.as_ref() == Some(&s.id),
+                session: s,
+            })
+            .collect(),
+    ))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct DeleteSessionRequest {
+    id: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/session/delete")]
+#[instrument(err, skip(app_state, session))]
+async fn delete_session(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<DeleteSessionRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.connect().await?;
+    models::User::delete_session(&mut conn, &session.user.email, &request.id).await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+/// Logs out of every session, including the current one.
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/session/delete_all")]
+#[instrument(err, skip(app_state, session))]
+async fn delete_all_sessions(
+    app_state: web::Data<AppState>,
+    session: Session,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.connect().await?;
+    models::User::delete_all_sessions(&mut conn, &session.user.email).await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[utoipa::path(responses((status = OK, body = Vec<models::ApiToken>), ApiErrorCode))]
+#[get("/tokens")]
+#[instrument(err, skip(app_state, session))]
+async fn list_tokens(
+    app_state: web::Data<AppState>,
+    session: Session,
+) -> ApiResult<web::Json<Vec<models::ApiToken>>> {
+    let mut conn = app_state.store.connect().await?;
+    let tokens = models::ApiToken::list_for_user(&mut conn, &session.user.email).await?;
+
+    Ok(web::Json(tokens))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct CreateTokenRequest {
+    name: String,
+    scope: TokenScope,
+    catalog: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CreateTokenResponse {
+    #[serde(flatten)]
+    api_token: models::ApiToken,
+    token: String,
+}
+
+#[utoipa::path(responses((status = OK, body = CreateTokenResponse), ApiErrorCode))]
+#[post("/token/create")]
+#[instrument(err, skip(app_state, session, request))]
+async fn create_token(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<CreateTokenRequest>,
+) -> ApiResult<web::Json<CreateTokenResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+
+    if let Some(ref catalog) = request.catalog {
+        models::Catalog::get_for_user(
+            &mut conn,
+            &session.user.email,
+            catalog,
+            request.scope > TokenScope::Read,
+        )
+        .await?;
+    }
+
+    let (api_token, token) = models::ApiToken::create(
+        &mut conn,
+        &session.user.email,
+        &request.name,
+        request.scope,
+        request.catalog.as_deref(),
+    )
+    .await?;
+    conn.commit().await?;
+
+    Ok(web::Json(CreateTokenResponse { api_token, token }))
 }
 
-#[derive(Serialize)]
+#[derive(Deserialize, Debug, ToSchema)]
+struct DeleteTokenRequest {
+    id: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/token/delete")]
+#[instrument(err, skip(app_state, session))]
+async fn delete_token(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<DeleteTokenRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.connect().await?;
+    models::ApiToken::delete(&mut conn, &session.user.email, &request.id).await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct UserState {
     #[serde(flatten)]
@@ -163,6 +735,7 @@ struct UserState {
     searches: Vec<SavedSearchWithCount>,
 }
 
+#[utoipa::path(responses((status = OK, body = UserState), ApiErrorCode))]
 #[get("/state")]
 #[instrument(err, skip(app_state, session))]
 async fn state(
@@ -183,7 +756,7 @@ async fn state(
         models::SavedSearch::list_for_user_with_count(store.clone(), email).in_current_span(),
     );
 
-    Ok(web::Json(UserState {
+    let mut state = UserState {
         user,
         storage: storage?,
         catalogs: catalogs?,
@@ -191,5 +764,30 @@ async fn state(
         tags: tags?,
         albums: albums?,
         searches: searches?,
-    }))
+    };
+
+    if session
+        .api_token
+        .as_ref()
+        .is_some_and(|t| t.catalog.is_some())
+    {
+        state.storage.clear();
+        state
+            .catalogs
+            .retain(|c| session.check_catalog(&c.catalog.id).is_ok());
+        state
+            .people
+            .retain(|p| session.check_catalog(&p.catalog).is_ok());
+        state
+            .tags
+            .retain(|t| session.check_catalog(&t.catalog).is_ok());
+        state
+            .albums
+            .retain(|a| session.check_catalog(&a.album.catalog).is_ok());
+        state
+            .searches
+            .retain(|s| session.check_catalog(&s.search.catalog).is_ok());
+    }
+
+    Ok(web::Json(state))
 }
@@ -0,0 +1,197 @@
+//! Runs a list of operations in a single transaction so that either all of
+//! them take effect or none do.
+use std::{collections::HashSet, result};
+
+use actix_web::{post, web};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+use utoipa::ToSchema;
+
+use crate::{
+    server::{
+        auth::Session,
+        media::{self, MediaUploadMetadata},
+        relations::{AlbumMediaChange, CreateAlbumRequest, EditAlbumRequest},
+        ApiErrorCode, ApiResponse, ApiResult, AppState,
+    },
+    store::{
+        db::{DbConnection, Isolation},
+        models,
+    },
+    Error, Task,
+};
+
+/// Returned when an operation refers to an id that no earlier operation
+/// created.
+struct InvalidReference(String);
+
+impl From<InvalidReference> for ApiErrorCode {
+    fn from(InvalidReference(reference): InvalidReference) -> Self {
+        ApiErrorCode::InvalidData(format!(
+            "{reference} does not refer to an earlier operation that created an item"
+        ))
+    }
+}
+
+/// The ids created by the operations run so far. Later operations can use
+/// `$n` in place of the id created by the operation at index `n`.
+#[derive(Default)]
+struct References {
+    created: Vec<Option<String>>,
+}
+
+impl References {
+    fn resolve(&self, id: &mut String) -> result::Result<(), InvalidReference> {
+        let Some(index) = id.strip_prefix('$') else {
+            return Ok(());
+        };
+
+        match index
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| self.created.get(index))
+        {
+            Some(Some(created)) => {
+                created.clone_into(id);
+                Ok(())
+            }
+            _ => Err(InvalidReference(id.clone())),
+        }
+    }
+
+    fn resolve_all<'a>(
+        &self,
+        ids: impl IntoIterator<Item = &'a mut String>,
+    ) -> result::Result<(), InvalidReference> {
+        ids.into_iter().try_for_each(|id| self.resolve(id))
+    }
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(tag = "type")]
+enum BatchOperation {
+    #[serde(rename = "album/create")]
+    CreateAlbum(CreateAlbumRequest),
+    #[serde(rename = "album/edit")]
+    EditAlbum(EditAlbumRequest),
+    #[serde(rename = "album/media")]
+    AlbumMedia(AlbumMediaChange),
+    #[serde(rename = "media/edit")]
+    EditMedia(Box<MediaUploadMetadata>),
+    #[serde(rename = "media/delete")]
+    DeleteMedia { media: Vec<String> },
+}
+
+impl BatchOperation {
+    fn resolve(&mut self, references: &References) -> result::Result<(), InvalidReference> {
+        match self {
+            BatchOperation::CreateAlbum(request) => {
+                references.resolve_all(request.album.parent.as_mut())
+            }
+            BatchOperation::EditAlbum(request) => {
+                references.resolve(&mut request.id)?;
+                references.resolve_all(request.album.parent.as_mut())
+            }
+            BatchOperation::AlbumMedia(change) => {
+                references.resolve(&mut change.album)?;
+                references.resolve_all(change.media.iter_mut())
+            }
+            BatchOperation::EditMedia(request) => references.resolve(&mut request.id),
+            BatchOperation::DeleteMedia { media } => references.resolve_all(media.iter_mut()),
+        }
+    }
+
+    /// Returns the result along with the id of any item created. The catalogs
+    /// whose searches need updating are added to `catalogs`.
+    async fn apply(
+        self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+        catalogs: &mut HashSet<String>,
+    ) -> ApiResult<(BatchResult, Option<String>)> {
+        Ok(match self {
+            BatchOperation::CreateAlbum(request) => {
+                let album = request.apply(conn, session).await?;
+                (BatchResult::Album(album.clone()), Some(album.id))
+            }
+            BatchOperation::EditAlbum(request) => {
+                let album = request.apply(conn, session).await?;
+                catalogs.insert(album.catalog.clone());
+                (BatchResult::Album(album), None)
+            }
+            BatchOperation::AlbumMedia(change) => {
+                let album = change.apply(conn, session).await?;
+                catalogs.insert(album.catalog);
+                (BatchResult::Response(Default::default()), None)
+            }
+            BatchOperation::EditMedia(request) => {
+                let media_item = request.apply(conn, session).await?;
+                catalogs.insert(media_item.catalog);
+                (BatchResult::Response(Default::default()), None)
+            }
+            BatchOperation::DeleteMedia { media } => {
+                media::mark_media_deleted(conn, session, &media).await?;
+                (BatchResult::Response(Default::default()), None)
+            }
+        })
+    }
+}
+
+/// Identifies the failed operation in errors caused by the request.
+fn operation_error(index: usize, error: ApiErrorCode) -> ApiErrorCode {
+    match error {
+        ApiErrorCode::InvalidData(message) => {
+            ApiErrorCode::InvalidData(format!("operation {index}: {message}"))
+        }
+        ApiErrorCode::NotFound => {
+            ApiErrorCode::InvalidData(format!("operation {index}: {}", Error::NotFound))
+        }
+        error => error,
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+enum BatchResult {
+    Album(models::Album),
+    Response(ApiResponse),
+}
+
+#[utoipa::path(responses((status = OK, body = Vec<BatchResult>), ApiErrorCode))]
+#[post("/batch")]
+#[instrument(err, skip_all, fields(operations = operations.len()))]
+async fn run_batch(
+    app_state: web::Data<AppState>,
+    session: Session,
+    operations: web::Json<Vec<BatchOperation>>,
+) -> ApiResult<web::Json<Vec<BatchResult>>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut references = References::default();
+    let mut catalogs: HashSet<String> = HashSet::new();
+    let mut results = Vec::new();
+
+    for (index, mut operation) in operations.into_inner().into_iter().enumerate() {
+        operation
+            .resolve(&references)
+            .map_err(|e| operation_error(index, e.into()))?;
+
+        let (result, created) = operation
+            .apply(&mut conn, &session, &mut catalogs)
+            .await
+            .map_err(|e| operation_error(index, e))?;
+
+        results.push(result);
+        references.created.push(created);
+    }
+
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
+    Ok(web::Json(results))
+}
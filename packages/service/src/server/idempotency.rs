@@ -0,0 +1,312 @@
+//! Support for the `Idempotency-Key` header. Clients that retry a request
+//! after a network failure send the same key and receive the response to the
+//! original request rather than running the operation a second time.
+use std::{
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    dev::Payload, error::PayloadError, web::Bytes, FromRequest, HttpMessage, HttpRequest,
+};
+use data_encoding::HEXLOWER;
+use futures::{
+    future::{ready, Ready},
+    Stream, StreamExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{from_value, to_value};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    server::{auth::Session, ApiErrorCode, ApiResult},
+    store::{db::DbConnection, models},
+    Error,
+};
+
+const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+const MAX_KEY_LENGTH: usize = 255;
+
+/// Hashes the request body as it is read by the handler's other extractors.
+/// Clients pick a new multipart boundary for each attempt so delimiters are
+/// left out of the hash. Multipart extractors stop reading at the closing
+/// delimiter so the hash is complete once that is seen.
+struct HashingPayload {
+    payload: Payload,
+    hasher: Sha256,
+    delimiter: Option<Vec<u8>>,
+    /// Data held back in case it is the start of a delimiter.
+    pending: Vec<u8>,
+    hash: Arc<OnceLock<String>>,
+}
+
+impl HashingPayload {
+    fn update(&mut self, bytes: &[u8]) {
+        if self.hash.get().is_some() {
+            return;
+        }
+
+        let Some(ref delimiter) = self.delimiter else {
+            self.hasher.update(bytes);
+            return;
+        };
+
+        self.pending.extend_from_slice(bytes);
+        let mut closed = false;
+        while let Some(pos) = self
+            .pending
+            .windows(delimiter.len())
+            .position(|window| window == delimiter.as_slice())
+        {
+            self.hasher.update(&self.pending[..pos]);
+            self.pending.drain(..pos);
+
+            // The closing delimiter is followed by "--".
+            let end = delimiter.len() + 2;
+            if self.pending.len() < end {
+                return;
+            }
+
+            if &self.pending[delimiter.len()..end] == b"--" {
+                self.pending.clear();
+                closed = true;
+                break;
+            }
+
+            self.pending.drain(..delimiter.len());
+        }
+
+        if closed {
+            self.finish();
+            return;
+        }
+
+        let keep = self.pending.len().min(delimiter.len() - 1);
+        let hashed = self.pending.len() - keep;
+        self.hasher.update(&self.pending[..hashed]);
+        self.pending.drain(..hashed);
+    }
+
+    fn finish(&mut self) {
+        self.hasher.update(&self.pending);
+        let hash = HEXLOWER.encode(&self.hasher.finalize_reset());
+        let _ = self.hash.set(hash);
+    }
+}
+
+impl Stream for HashingPayload {
+    type Item = Result<Bytes, PayloadError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let next = self.payload.poll_next_unpin(cx);
+
+        match next {
+            Poll::Ready(Some(Ok(ref bytes))) => self.update(bytes),
+            Poll::Ready(None) if self.hash.get().is_none() => self.finish(),
+            _ => {}
+        }
+
+        next
+    }
+}
+
+/// The idempotency key sent with a request, if any.
+pub(super) struct Idempotency {
+    route: String,
+    key: Option<String>,
+    /// A hash of the query string and body, known once the body is read.
+    request_hash: Arc<OnceLock<String>>,
+}
+
+impl FromRequest for Idempotency {
+    type Error = ApiErrorCode;
+    type Future = Ready<ApiResult<Self>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let key = match req.headers().get(IDEMPOTENCY_KEY) {
+            Some(header) => match header.to_str() {
+                Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LENGTH => {
+                    Some(key.to_owned())
+                }
+                _ => {
+                    return ready(Err(ApiErrorCode::InvalidData(format!(
+                        "{IDEMPOTENCY_KEY} must be between 1 and {MAX_KEY_LENGTH} visible ASCII characters"
+                    ))))
+                }
+            },
+            None => None,
+        };
+
+        let request_hash = Arc::new(OnceLock::new());
+        if key.is_some() {
+            let mut hasher = Sha256::new();
+            hasher.update(req.query_string());
+            hasher.update([0]);
+
+            let delimiter = req
+                .mime_type()
+                .ok()
+                .flatten()
+                .and_then(|mime| mime.get_param(mime::BOUNDARY).map(|b| b.to_string()))
+                .filter(|boundary| !boundary.is_empty())
+                .map(|boundary| format!("--{boundary}").into_bytes());
+
+            *payload = Payload::Stream {
+                payload: Box::pin(HashingPayload {
+                    payload: payload.take(),
+                    hasher,
+                    delimiter,
+                    pending: Vec::new(),
+                    hash: request_hash.clone(),
+                }),
+            };
+        }
+
+        ready(Ok(Idempotency {
+            route: req.path().to_owned(),
+            key,
+            request_hash,
+        }))
+    }
+}
+
+impl Idempotency {
+    /// Claims the key within the current transaction. Returns the response to
+    /// an earlier request that used the same key, in which case the operation
+    /// must not be run again. Fails if the earlier request was different. The
+    /// request body must have been read first.
+    pub(super) async fn replay<T: DeserializeOwned>(
+        &self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+    ) -> ApiResult<Option<T>> {
+        let Some(ref key) = self.key else {
+            return Ok(None);
+        };
+
+        // Without a hash a different request could be given this response.
+        let Some(request_hash) = self.request_hash.get() else {
+            return Err(ApiErrorCode::InvalidData(format!(
+                "The request body must be sent in full when using {IDEMPOTENCY_KEY}"
+            )));
+        };
+
+        match models::IdempotencyKey::claim(
+            conn,
+            &session.user.email,
+            &self.route,
+            key,
+            request_hash,
+        )
+        .await?
+        {
+            Some(response) => Ok(Some(from_value(response).map_err(Error::from)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Stores the response so that retries can replay it. Must be called in
+    /// the same transaction as `replay`.
+    pub(super) async fn record<T: Serialize>(
+        &self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+        response: &T,
+    ) -> ApiResult<()> {
+        let Some(ref key) = self.key else {
+            return Ok(());
+        };
+
+        let response = to_value(response).map_err(Error::from)?;
+        models::IdempotencyKey::complete(conn, &session.user.email, &self.route, key, response)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, OnceLock};
+
+    use actix_multipart::form::{text::Text, MultipartForm};
+    use actix_web::{dev::Payload, test::TestRequest, web::Bytes, FromRequest};
+    use futures::{stream, StreamExt};
+    use sha2::{Digest, Sha256};
+
+    use super::{HashingPayload, Idempotency, IDEMPOTENCY_KEY};
+
+    #[derive(MultipartForm)]
+    struct Form {
+        json: Text<String>,
+    }
+
+    fn hash(boundary: &str, chunk_size: usize) -> String {
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"json\"\r\n\r\n{{}}\r\n--{boundary}--\r\n"
+        );
+
+        let hash = Arc::new(OnceLock::new());
+        let mut payload = HashingPayload {
+            payload: Payload::None,
+            hasher: Sha256::new(),
+            delimiter: Some(format!("--{boundary}").into_bytes()),
+            pending: Vec::new(),
+            hash: hash.clone(),
+        };
+
+        for chunk in body.as_bytes().chunks(chunk_size) {
+            payload.update(chunk);
+        }
+        payload.finish();
+
+        hash.get().unwrap().clone()
+    }
+
+    #[test]
+    fn multipart_boundaries() {
+        let expected = hash("first", 1000);
+
+        assert_eq!(hash("first", 3), expected);
+        assert_eq!(hash("a-longer-boundary", 1), expected);
+        assert_eq!(hash("a-longer-boundary", 7), expected);
+    }
+
+    async fn extract(boundary: &str) -> String {
+        let body = format!(
+            "--{boundary}\r\nContent-Disposition: form-data; name=\"json\"\r\n\r\n{{}}\r\n--{boundary}--\r\n"
+        );
+
+        let req = TestRequest::post()
+            .insert_header((IDEMPOTENCY_KEY, "key"))
+            .insert_header((
+                "Content-Type",
+                format!("multipart/form-data; boundary={boundary}"),
+            ))
+            .to_http_request();
+
+        // The connection stays open after the body so the end of the payload
+        // is never seen.
+        let chunks = body
+            .as_bytes()
+            .chunks(5)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let mut payload =
+            Payload::from(stream::iter(chunks).chain(stream::pending()).boxed_local());
+
+        let idempotency = Idempotency::from_request(&req, &mut payload).await.unwrap();
+        let form = MultipartForm::<Form>::from_request(&req, &mut payload)
+            .await
+            .unwrap();
+        assert_eq!(form.json.as_str(), "{}");
+
+        idempotency.request_hash.get().unwrap().clone()
+    }
+
+    #[actix_web::test]
+    async fn multipart_form() {
+        assert_eq!(extract("first").await, extract("second").await);
+    }
+}
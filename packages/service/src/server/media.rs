@@ -1,29 +1,44 @@
-use std::str::FromStr;
+use std::{collections::HashSet, path::Path, result, slice, str::FromStr};
 
 use actix_multipart::form::{json::Json as MultipartJson, tempfile::TempFile, MultipartForm};
 use actix_web::{
     get,
     http::{header, StatusCode},
-    post, web, Either, HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
+    patch, post, web, Either, HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
 };
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, TimeDelta, Utc};
 use file_format::FileFormat;
+use futures::StreamExt;
+use itertools::Itertools;
 use mime::Mime;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use pixelbin_shared::Ignorable;
 use serde::{Deserialize, Serialize};
-use tokio::fs::File;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
 use tokio_util::io::ReaderStream;
 use tracing::{instrument, warn};
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
     metadata::{alternates_for_media_file, ISO_FORMAT},
     server::{
+        audit::MediaAudit,
         auth::{MaybeSession, Session},
-        ApiResponse, ApiResult, AppState,
+        idempotency::Idempotency,
+        signed::{InvalidSignature, SignedFile},
+        ApiErrorCode, ApiResponse, ApiResult, AppState,
     },
+    shared::file_sha256,
     store::{
-        db::{search::SearchQuery, DbConnection, Isolation},
+        db::{page::MediaPage, search::SearchQuery, DbConnection, Isolation, MediaCredentials},
         file::DiskStore,
-        models::{self, AlternateFile, AlternateFileType, Location, MediaViewStream, Orientation},
+        models::{
+            self, AlternateFile, AlternateFileType, AuditAction, AuditEntry, Location,
+            MediaViewStream, Orientation,
+        },
     },
     Error, Result, Task,
 };
@@ -62,6 +77,50 @@ async fn social_handler(
         .streaming(stream))
 }
 
+/// Media can be accessed through an album share link by including the share's
+/// access credential, or through a signed URL.
+#[derive(Debug, Deserialize)]
+struct MediaQuery {
+    share: Option<String>,
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+impl MediaQuery {
+    fn credentials<'a>(
+        &'a self,
+        app_state: &AppState,
+        session: &'a MaybeSession,
+        item: &str,
+        file: &str,
+        target: SignedFile<'_>,
+    ) -> result::Result<MediaCredentials<'a>, InvalidSignature> {
+        let signed = match (self.expires, self.signature.as_deref()) {
+            (Some(expires), Some(signature)) => {
+                if !app_state.url_signer.verify(
+                    item,
+                    file,
+                    target,
+                    expires,
+                    signature,
+                    Utc::now().timestamp(),
+                ) {
+                    return Err(InvalidSignature);
+                }
+
+                true
+            }
+            _ => false,
+        };
+
+        Ok(MediaCredentials {
+            email: session.session().map(|s| s.user.email.as_str()),
+            share: self.share.as_deref(),
+            signed,
+        })
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct DownloadPath {
     item: String,
@@ -75,13 +134,22 @@ async fn download_handler(
     app_state: web::Data<AppState>,
     session: MaybeSession,
     path: web::Path<DownloadPath>,
+    query: web::Query<MediaQuery>,
 ) -> ApiResult<impl Responder> {
-    let email = session.session().map(|s| s.user.email.as_str());
+    let credentials = query.credentials(
+        &app_state,
+        &session,
+        &path.item,
+        &path.file,
+        SignedFile::Original,
+    )?;
     let filename = path.filename.clone();
 
     let mut conn = app_state.store.connect().await?;
     let (media_file, media_file_store) =
-        models::MediaFile::get_for_user_media(&mut conn, email, &path.item, &path.file).await?;
+        models::MediaFile::get_for_user_media(&mut conn, credentials, &path.item, &path.file)
+            .await?;
+    session.check_catalog(&media_file_store.catalog)?;
 
     let storage = models::Storage::get_for_catalog(&mut conn, &media_file_store.catalog).await?;
 
@@ -115,15 +183,25 @@ async fn thumbnail_handler(
     app_state: web::Data<AppState>,
     session: MaybeSession,
     path: web::Path<ThumbnailPath>,
+    query: web::Query<MediaQuery>,
 ) -> ApiResult<impl Responder> {
-    let email = session.session().map(|s| s.user.email.as_str());
+    let credentials = query.credentials(
+        &app_state,
+        &session,
+        &path.item,
+        &path.file,
+        SignedFile::Thumbnail {
+            size: path.size,
+            mimetype: &path.mimetype,
+        },
+    )?;
     let mimetype = Mime::from_str(&path.mimetype.replace('-', "/"))?;
     let target_size = path.size as i32;
 
     let mut conn = app_state.store.connect().await?;
     let alternates = models::AlternateFile::list_for_user_media(
         &mut conn,
-        email,
+        credentials,
         &path.item,
         &path.file,
         &mimetype,
@@ -133,6 +211,8 @@ async fn thumbnail_handler(
 
     match models::AlternateFile::choose_alternate(alternates, target_size) {
         Some((alternate, file_path)) => {
+            session.check_catalog(&file_path.catalog)?;
+
             let path = DiskStore::local_store(app_state.store.config()).local_path(&file_path);
             let file = File::open(&path).await?;
             let stream = ReaderStream::new(file);
@@ -167,8 +247,17 @@ async fn encoding_handler(
     session: MaybeSession,
     request: HttpRequest,
     path: web::Path<EncodingPath>,
+    query: web::Query<MediaQuery>,
 ) -> ApiResult<Either<HttpResponse, web::Json<EncodingResponse>>> {
-    let email = session.session().map(|s| s.user.email.as_str());
+    let credentials = query.credentials(
+        &app_state,
+        &session,
+        &path.item,
+        &path.file,
+        SignedFile::Encoding {
+            mimetype: &path.mimetype,
+        },
+    )?;
     let mimetype = Mime::from_str(&path.mimetype.replace('-', "/"))?;
     let tx_mime = mimetype.clone();
 
@@ -176,7 +265,7 @@ async fn encoding_handler(
 
     let (_, file_path) = models::AlternateFile::list_for_user_media(
         &mut conn,
-        email,
+        credentials,
         &path.item,
         &path.file,
         &tx_mime,
@@ -186,6 +275,7 @@ async fn encoding_handler(
     .into_iter()
     .next()
     .ok_or_else(|| Error::NotFound)?;
+    session.check_catalog(&file_path.catalog)?;
 
     let storage = models::Storage::get_for_catalog(&mut conn, &file_path.catalog).await?;
 
@@ -206,18 +296,21 @@ async fn encoding_handler(
     ))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
 struct MediaRequest {
     #[serde(default)]
     search: Option<String>,
+    #[serde(default)]
+    share: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 pub(crate) struct GetMediaResponse<T> {
     pub(crate) total: i64,
     pub(crate) media: Vec<T>,
 }
 
+#[utoipa::path(params(MediaRequest), security((), ("bearer" = [])), responses((status = OK, body = GetMediaResponse<models::MediaRelations>), ApiErrorCode))]
 #[get("/media/{media_ids}")]
 #[instrument(err, skip(app_state, session, media_ids))]
 async fn get_media(
@@ -230,9 +323,15 @@ async fn get_media(
     let email = session.session().map(|s| s.user.email.as_str());
 
     let mut conn = app_state.store.connect().await?;
-    let media =
-        models::MediaRelations::get_for_user(&mut conn, email, request.search.as_deref(), &ids)
-            .await?;
+    let mut media = models::MediaRelations::get_for_user(
+        &mut conn,
+        email,
+        request.search.as_deref(),
+        request.share.as_deref(),
+        &ids,
+    )
+    .await?;
+    media.retain(|m| session.check_catalog(&m.media.catalog).is_ok());
 
     let response = GetMediaResponse {
         total: media.len() as i64,
@@ -242,6 +341,298 @@ async fn get_media(
     Ok(web::Json(response))
 }
 
+const DEFAULT_SIMILARITY_DISTANCE: u32 = 10;
+
+#[derive(Deserialize, Clone, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(super) struct SimilarityOptions {
+    /// The largest number of bits, out of 64, that may differ between the
+    /// perceptual hashes of two similar media items. Defaults to 10 and is
+    /// limited to 16.
+    distance: Option<u32>,
+}
+
+impl SimilarityOptions {
+    pub(super) fn distance(&self) -> i64 {
+        i64::from(self.distance.unwrap_or(DEFAULT_SIMILARITY_DISTANCE))
+            .min(models::MAX_SIMILARITY_DISTANCE)
+    }
+}
+
+/// Lists the media items in the same catalog that look similar to this one,
+/// closest first.
+#[utoipa::path(params(SimilarityOptions), responses((status = OK, body = Vec<models::SimilarMedia>), ApiErrorCode))]
+#[get("/media/{media_id}/similar")]
+#[instrument(err, skip(app_state, session))]
+async fn list_similar_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    media_id: web::Path<String>,
+    options: web::Query<SimilarityOptions>,
+) -> ApiResult<web::Json<Vec<models::SimilarMedia>>> {
+    let mut conn = app_state.store.connect().await?;
+    let media = models::MediaRelations::get_for_user(
+        &mut conn,
+        Some(&session.user.email),
+        None,
+        None,
+        &[media_id.to_string()],
+    )
+    .await?;
+
+    let Some(media) = media.first() else {
+        return Err(Error::NotFound.into());
+    };
+    session.check_catalog(&media.media.catalog)?;
+
+    Ok(web::Json(
+        models::MediaFile::list_similar(&mut conn, &media.media.id, options.distance()).await?,
+    ))
+}
+
+/// Lists the files kept for the media item. The media item can be reverted to
+/// any of them that are not current.
+#[utoipa::path(responses((status = OK, body = Vec<models::MediaFileVersion>), ApiErrorCode))]
+#[get("/media/{media_id}/files")]
+#[instrument(err, skip(app_state, session))]
+async fn list_media_files(
+    app_state: web::Data<AppState>,
+    session: Session,
+    media_id: web::Path<String>,
+) -> ApiResult<web::Json<Vec<models::MediaFileVersion>>> {
+    let mut conn = app_state.store.connect().await?;
+    let media = models::MediaRelations::get_for_user(
+        &mut conn,
+        Some(&session.user.email),
+        None,
+        None,
+        &[media_id.to_string()],
+    )
+    .await?;
+
+    let Some(media) = media.first() else {
+        return Err(Error::NotFound.into());
+    };
+    session.check_catalog(&media.media.catalog)?;
+
+    Ok(web::Json(
+        models::MediaFile::list_versions(&mut conn, &media.media.id).await?,
+    ))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct RevertMediaRequest {
+    id: String,
+    file: String,
+}
+
+/// Makes an earlier file the media item's current file again.
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/media/revert")]
+#[instrument(err, skip(app_state, session))]
+async fn revert_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<RevertMediaRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+
+    let media_item = uploadable_media_item(&mut conn, &session, &request.id).await?;
+    models::MediaFile::revert(&mut conn, &media_item.id, &request.file).await?;
+    models::MediaItem::update_media_files(&mut conn, &media_item.catalog).await?;
+
+    let entry = AuditEntry::new(
+        &media_item.catalog,
+        &session.user.email,
+        AuditAction::RevertMedia,
+        &media_item.id,
+    )
+    .change(
+        "file",
+        media_item.media_file.as_deref(),
+        Some(&request.file),
+    );
+    AuditEntry::insert(&mut conn, &[entry]).await?;
+
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: media_item.catalog,
+        })
+        .await;
+
+    Ok(web::Json(ApiResponse::default()))
+}
+
+/// The characters to escape in a file name used as a URL path segment.
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Signed URLs last a day unless the caller asks otherwise.
+const DEFAULT_SIGNED_URL_LIFETIME: TimeDelta = TimeDelta::days(1);
+
+#[derive(Debug, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase", tag = "type")]
+enum SignedAlternate {
+    Thumbnail {
+        size: u32,
+        #[serde(with = "crate::shared::mime")]
+        #[schema(value_type = String)]
+        mimetype: Mime,
+    },
+    Encoding {
+        #[serde(with = "crate::shared::mime")]
+        #[schema(value_type = String)]
+        mimetype: Mime,
+    },
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+struct SignMediaRequest {
+    item: String,
+    file: String,
+    /// The alternate to sign a URL for, or the original file if not given.
+    alternate: Option<SignedAlternate>,
+    expiry: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SignMediaResponse {
+    url: String,
+    expiry: DateTime<Utc>,
+}
+
+#[utoipa::path(responses((status = OK, body = SignMediaResponse), ApiErrorCode))]
+#[post("/media/sign")]
+#[instrument(err, skip(app_state, session))]
+async fn sign_media_url(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<SignMediaRequest>,
+) -> ApiResult<web::Json<SignMediaResponse>> {
+    let expiry = request
+        .expiry
+        .unwrap_or_else(|| Utc::now() + DEFAULT_SIGNED_URL_LIFETIME);
+    if expiry <= Utc::now() {
+        return Err(ApiErrorCode::InvalidData(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let credentials = MediaCredentials {
+        email: Some(&session.user.email),
+        ..Default::default()
+    };
+    let url_mimetype = match request.alternate {
+        Some(SignedAlternate::Thumbnail { ref mimetype, .. })
+        | Some(SignedAlternate::Encoding { ref mimetype }) => {
+            mimetype.to_string().replace('/', "-")
+        }
+        None => String::new(),
+    };
+
+    let mut conn = app_state.store.connect().await?;
+
+    let (target, catalog, file_name) = match request.alternate {
+        None => {
+            let (media_file, media_file_store) = models::MediaFile::get_for_user_media(
+                &mut conn,
+                credentials,
+                &request.item,
+                &request.file,
+            )
+            .await?;
+
+            (
+                SignedFile::Original,
+                media_file_store.catalog,
+                media_file.file_name,
+            )
+        }
+        Some(SignedAlternate::Thumbnail { size, ref mimetype }) => {
+            let alternates = models::AlternateFile::list_for_user_media(
+                &mut conn,
+                credentials,
+                &request.item,
+                &request.file,
+                mimetype,
+                AlternateFileType::Thumbnail,
+            )
+            .await?;
+            let (alternate, file_path) =
+                models::AlternateFile::choose_alternate(alternates, size as i32)
+                    .ok_or(Error::NotFound)?;
+
+            (
+                SignedFile::Thumbnail {
+                    size,
+                    mimetype: &url_mimetype,
+                },
+                file_path.catalog,
+                alternate.file_name,
+            )
+        }
+        Some(SignedAlternate::Encoding { ref mimetype }) => {
+            let (alternate, file_path) = models::AlternateFile::list_for_user_media(
+                &mut conn,
+                credentials,
+                &request.item,
+                &request.file,
+                mimetype,
+                AlternateFileType::Reencode,
+            )
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(Error::NotFound)?;
+
+            (
+                SignedFile::Encoding {
+                    mimetype: &url_mimetype,
+                },
+                file_path.catalog,
+                alternate.file_name,
+            )
+        }
+    };
+    session.check_catalog(&catalog)?;
+
+    let path = match target {
+        SignedFile::Original => format!("download/{}/{}", request.item, request.file),
+        SignedFile::Thumbnail { size, mimetype } => {
+            format!("thumb/{}/{}/{size}/{mimetype}", request.item, request.file)
+        }
+        SignedFile::Encoding { mimetype } => {
+            format!("encoding/{}/{}/{mimetype}", request.item, request.file)
+        }
+    };
+
+    let expires = expiry.timestamp();
+    let signature = app_state
+        .url_signer
+        .sign(&request.item, &request.file, target, expires);
+
+    let url = format!(
+        "{}media/{path}/{}?{}",
+        app_state.store.config().api_url,
+        utf8_percent_encode(&file_name, PATH_SEGMENT),
+        serde_urlencoded::to_string([("expires", expires.to_string()), ("signature", signature),])
+            .unwrap()
+    );
+
+    Ok(web::Json(SignMediaResponse {
+        url,
+        expiry: DateTime::from_timestamp(expires, 0).unwrap(),
+    }))
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/media/delete")]
 #[instrument(err, skip(app_state, session))]
 async fn delete_media(
@@ -250,17 +641,93 @@ async fn delete_media(
     media_ids: web::Json<Vec<String>>,
 ) -> ApiResult<web::Json<ApiResponse>> {
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
-    let media = models::MediaItem::get_for_user(&mut conn, &session.user.email, &media_ids).await?;
+    mark_media_deleted(&mut conn, &session, &media_ids).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(ApiResponse::default()))
+}
+
+/// Restores deleted media from the trash.
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/media/restore")]
+#[instrument(err, skip(app_state, session, media_ids))]
+async fn restore_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    media_ids: web::Json<Vec<String>>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
 
-    let media_ids: Vec<String> = media.into_iter().map(|m| m.id).collect();
+    let mut media =
+        models::MediaItem::get_for_user(&mut conn, &session.user.email, &media_ids).await?;
+    for media_item in media.iter() {
+        session.check_catalog(&media_item.catalog)?;
+    }
+
+    media.retain(|media_item| media_item.deleted);
+    let media_ids: Vec<String> = media.iter().map(|m| m.id.clone()).collect();
+    let catalogs = models::MediaItem::restore(&mut conn, &media_ids).await?;
+
+    let entries = media
+        .iter()
+        .map(|media_item| {
+            AuditEntry::new(
+                &media_item.catalog,
+                &session.user.email,
+                AuditAction::RestoreMedia,
+                &media_item.id,
+            )
+            .change("deleted", true, false)
+        })
+        .collect_vec();
+    AuditEntry::insert(&mut conn, &entries).await?;
 
-    models::MediaItem::mark_deleted(&mut conn, &media_ids).await?;
     conn.commit().await?;
 
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
     Ok(web::Json(ApiResponse::default()))
 }
 
-#[derive(Default, Clone, Debug, Deserialize)]
+/// Marks the media that the user can write to as deleted.
+pub(super) async fn mark_media_deleted(
+    conn: &mut DbConnection<'_>,
+    session: &Session,
+    media_ids: &[String],
+) -> ApiResult<()> {
+    let media = models::MediaItem::get_for_user(conn, &session.user.email, media_ids).await?;
+    for media_item in media.iter() {
+        session.check_catalog(&media_item.catalog)?;
+    }
+
+    let media_ids: Vec<String> = media.iter().map(|m| m.id.clone()).collect();
+
+    models::MediaItem::mark_deleted(conn, &media_ids).await?;
+
+    let entries = media
+        .iter()
+        .filter(|media_item| !media_item.deleted)
+        .map(|media_item| {
+            AuditEntry::new(
+                &media_item.catalog,
+                &session.user.email,
+                AuditAction::DeleteMedia,
+                &media_item.id,
+            )
+            .change("deleted", false, true)
+        })
+        .collect_vec();
+    AuditEntry::insert(conn, &entries).await?;
+
+    Ok(())
+}
+
+#[derive(Default, Clone, Debug, Deserialize, ToSchema)]
 #[serde(from = "Option<T>")]
 enum MetadataValue<T> {
     #[default]
@@ -278,7 +745,7 @@ impl<T> From<Option<T>> for MetadataValue<T> {
     }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct MediaMetadata {
     #[serde(default)]
@@ -377,13 +844,13 @@ impl MediaMetadata {
     }
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
 struct PersonInfo {
     name: String,
     location: Option<Location>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
 struct MediaData {
     media: Option<MediaMetadata>,
     tags: Option<Vec<Vec<String>>>,
@@ -392,26 +859,61 @@ struct MediaData {
     source: Option<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
 struct MediaCreateMetadata {
     catalog: String,
     #[serde(flatten)]
     metadata: MediaData,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-struct MediaUploadMetadata {
-    id: String,
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub(super) struct MediaUploadMetadata {
+    pub(super) id: String,
     #[serde(flatten)]
     metadata: MediaData,
 }
 
-#[derive(Serialize, Clone, Debug)]
+impl MediaUploadMetadata {
+    /// Applies the changes to an existing media item.
+    pub(super) async fn apply(
+        &self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+    ) -> ApiResult<models::MediaItem> {
+        let mut media =
+            models::MediaItem::get_for_user(conn, &session.user.email, slice::from_ref(&self.id))
+                .await?;
+
+        if media.is_empty() {
+            return Err(Error::NotFound.into());
+        }
+
+        let mut media_item = media.remove(0);
+        session.check_catalog(&media_item.catalog)?;
+        if media_item.deleted {
+            return Err(Error::NotFound.into());
+        }
+
+        update_media_item(
+            conn,
+            session,
+            AuditAction::EditMedia,
+            &mut media_item,
+            &self.metadata,
+        )
+        .await?;
+
+        Ok(media_item)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 struct MediaUploadResponse {
     id: String,
 }
 
-async fn update_media_item(
+/// Applies changes to the fields of the media item itself without saving it.
+async fn apply_media_data(
     conn: &mut DbConnection<'_>,
     media_item: &mut models::MediaItem,
     data: &MediaData,
@@ -455,97 +957,216 @@ async fn update_media_item(
         }
     }
 
+    Ok(())
+}
+
+/// Finds or creates the tags for a list of tag hierarchies.
+async fn resolve_tags(
+    conn: &mut DbConnection<'_>,
+    catalog: &str,
+    tag_list: &[Vec<String>],
+) -> Result<Vec<models::Tag>> {
+    let mut tags = Vec::new();
+    for tag_name in tag_list {
+        tags.push(models::Tag::get_or_create_hierarchy(conn, catalog, tag_name).await?);
+    }
+
+    Ok(tags)
+}
+
+/// Finds or creates the people in a list.
+async fn resolve_people(
+    conn: &mut DbConnection<'_>,
+    catalog: &str,
+    person_list: &[PersonInfo],
+) -> Result<Vec<(models::Person, Option<Location>)>> {
+    let mut people = Vec::new();
+    for person_info in person_list {
+        let person = models::Person::get_or_create(conn, catalog, &person_info.name).await?;
+        people.push((person, person_info.location));
+    }
+
+    Ok(people)
+}
+
+fn media_tags(media_item: &models::MediaItem, tags: &[models::Tag]) -> Vec<models::MediaTag> {
+    tags.iter()
+        .map(|tag| models::MediaTag {
+            catalog: media_item.catalog.clone(),
+            tag: tag.id.clone(),
+            media: media_item.id.clone(),
+        })
+        .collect()
+}
+
+fn media_people(
+    media_item: &models::MediaItem,
+    people: &[(models::Person, Option<Location>)],
+) -> Vec<models::MediaPerson> {
+    people
+        .iter()
+        .map(|(person, location)| models::MediaPerson {
+            catalog: media_item.catalog.clone(),
+            media: media_item.id.clone(),
+            person: person.id.clone(),
+            location: *location,
+        })
+        .collect()
+}
+
+/// Applies changes to the media item and saves it, recording the changes made
+/// in the audit log.
+async fn update_media_item(
+    conn: &mut DbConnection<'_>,
+    session: &Session,
+    action: AuditAction,
+    media_item: &mut models::MediaItem,
+    data: &MediaData,
+) -> Result {
+    let audit = MediaAudit::capture(conn, slice::from_ref(media_item)).await?;
+
+    apply_media_data(conn, media_item, data).await?;
+
     models::MediaItem::upsert(conn, &[media_item.clone()]).await?;
 
     if let Some(ref tag_list) = data.tags {
-        let mut tags = Vec::new();
-        for tag_name in tag_list {
-            let tag =
-                models::Tag::get_or_create_hierarchy(conn, &media_item.catalog, tag_name).await?;
-            tags.push(models::MediaTag {
-                catalog: media_item.catalog.clone(),
-                tag: tag.id,
-                media: media_item.id.clone(),
-            });
-        }
-
-        models::MediaTag::replace_for_media(conn, &media_item.id, &tags).await?;
+        let tags = resolve_tags(conn, &media_item.catalog, tag_list).await?;
+        models::MediaTag::replace_for_media(conn, &media_item.id, &media_tags(media_item, &tags))
+            .await?;
     }
 
     if let Some(ref person_list) = data.people {
-        let mut people: Vec<models::MediaPerson> = Vec::new();
-        for person_info in person_list {
-            let person =
-                models::Person::get_or_create(conn, &media_item.catalog, &person_info.name).await?;
-            people.push(models::MediaPerson {
-                catalog: media_item.catalog.clone(),
-                media: media_item.id.clone(),
-                person: person.id.clone(),
-                location: person_info.location,
-            });
-        }
-
-        models::MediaPerson::replace_for_media(conn, &media_item.id, &people).await?;
+        let people = resolve_people(conn, &media_item.catalog, person_list).await?;
+        models::MediaPerson::replace_for_media(
+            conn,
+            &media_item.id,
+            &media_people(media_item, &people),
+        )
+        .await?;
     }
 
-    Ok(())
+    audit
+        .record(conn, session, action, slice::from_ref(media_item))
+        .await
 }
 
+#[utoipa::path(params(("Idempotency-Key" = Option<String>, Header, description = "Replays the response to an earlier request made with the same key.")), responses((status = OK, body = MediaUploadResponse), ApiErrorCode))]
 #[post("/media/create")]
 #[instrument(err, skip_all, fields(catalog))]
 async fn create_media(
     app_state: web::Data<AppState>,
     session: Session,
+    idempotency: Idempotency,
     data: web::Json<MediaCreateMetadata>,
 ) -> ApiResult<web::Json<MediaUploadResponse>> {
     tracing::Span::current().record("catalog", &data.catalog);
+    session.check_catalog(&data.catalog)?;
 
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    if let Some(response) = idempotency.replay(&mut conn, &session).await? {
+        return Ok(web::Json(response));
+    }
+
     let user_catalog =
         models::Catalog::get_for_user(&mut conn, &session.user.email, &data.catalog, true).await?;
     let mut media_item = models::MediaItem::new(&user_catalog.catalog.id);
 
-    update_media_item(&mut conn, &mut media_item, &data.metadata).await?;
-    conn.commit().await?;
+    update_media_item(
+        &mut conn,
+        &session,
+        AuditAction::CreateMedia,
+        &mut media_item,
+        &data.metadata,
+    )
+    .await?;
 
-    Ok(web::Json(MediaUploadResponse { id: media_item.id }))
-}
+    let response = MediaUploadResponse { id: media_item.id };
+    idempotency.record(&mut conn, &session, &response).await?;
+    conn.commit().await?;
 
-#[derive(MultipartForm)]
-struct MediaUpload {
-    json: MultipartJson<MediaUploadMetadata>,
-    file: TempFile,
+    Ok(web::Json(response))
 }
 
-#[post("/media/upload")]
-#[instrument(err, skip_all, fields(id, catalog))]
-async fn upload_media(
-    app_state: web::Data<AppState>,
-    session: Session,
-    data: MultipartForm<MediaUpload>,
-) -> ApiResult<web::Json<MediaUploadResponse>> {
-    let data = data.into_inner();
-
-    tracing::Span::current().record("id", &data.json.id);
-
-    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+/// Fetches a media item that the session can upload a new file for.
+async fn uploadable_media_item(
+    conn: &mut DbConnection<'_>,
+    session: &Session,
+    id: &str,
+) -> ApiResult<models::MediaItem> {
     let mut media_items =
-        models::MediaItem::get_for_user(&mut conn, &session.user.email, &[data.json.id.clone()])
-            .await?;
+        models::MediaItem::get_for_user(conn, &session.user.email, &[id.to_owned()]).await?;
 
     if media_items.is_empty() {
         return Err(Error::NotFound.into());
     }
 
-    let mut media_item = media_items.remove(0);
+    let media_item = media_items.remove(0);
+    session.check_catalog(&media_item.catalog)?;
 
     if media_item.deleted {
         return Err(Error::NotFound.into());
     }
 
-    update_media_item(&mut conn, &mut media_item, &data.json.metadata).await?;
+    Ok(media_item)
+}
+
+/// How to handle an upload of a file that is already in the catalog.
+#[derive(Deserialize, Clone, Copy, Debug, Default, ToSchema)]
+#[serde(rename_all = "camelCase")]
+enum DuplicateHandling {
+    /// Stores the file regardless.
+    #[default]
+    Allow,
+    /// Fails the upload.
+    Reject,
+    /// Leaves the media item unchanged and returns the id of the media item
+    /// that already has the file.
+    Link,
+}
+
+impl DuplicateHandling {
+    /// Returns the media item to link the upload to instead of storing it.
+    async fn check(
+        self,
+        conn: &mut DbConnection<'_>,
+        media_item: &models::MediaItem,
+        sha256: &str,
+    ) -> ApiResult<Option<String>> {
+        if matches!(self, DuplicateHandling::Allow) {
+            return Ok(None);
+        }
+
+        match models::MediaFile::find_duplicate(conn, &media_item.catalog, sha256).await? {
+            Some(existing) if matches!(self, DuplicateHandling::Reject) => {
+                Err(ApiErrorCode::InvalidData(format!(
+                    "This file has already been uploaded to {existing}"
+                )))
+            }
+            existing => Ok(existing),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct UploadOptions {
+    #[serde(default)]
+    #[param(inline)]
+    duplicates: DuplicateHandling,
+}
 
-    let base_name = if let Some(ref name) = data.file.file_name {
+/// Stores an uploaded file as a new media file for the media item and returns
+/// the new media file's id. The caller must queue the file for processing once
+/// the transaction is committed.
+async fn store_media_file(
+    conn: &mut DbConnection<'_>,
+    media_item: &models::MediaItem,
+    original_name: Option<&str>,
+    source: &Path,
+    size: usize,
+    sha256: &str,
+) -> ApiResult<String> {
+    let base_name = if let Some(name) = original_name {
         if let Some((name, _)) = name.rsplit_once('.') {
             name
         } else {
@@ -555,7 +1176,7 @@ async fn upload_media(
         "original"
     };
 
-    let format = FileFormat::from_reader(data.file.file.as_file())?;
+    let format = FileFormat::from_file(source)?;
     let media_type: Mime = format.media_type().parse()?;
     if !matches!(media_type.type_(), mime::IMAGE | mime::VIDEO) {
         return Err(Error::UnsupportedMedia { mime: media_type }.into());
@@ -566,7 +1187,8 @@ async fn upload_media(
     let media_file = models::MediaFile::new(
         &media_item.id,
         &file_name,
-        data.file.size as i64,
+        size as i64,
+        sha256,
         &Mime::from_str(format.media_type())?,
     );
 
@@ -576,7 +1198,7 @@ async fn upload_media(
         .file(&file_name);
 
     DiskStore::temp_store(conn.config())
-        .copy_from_temp(data.file.file.into_temp_path(), &path)
+        .copy_from_temp(source, &path)
         .await?;
 
     let alternate_files: Vec<AlternateFile> =
@@ -587,43 +1209,339 @@ async fn upload_media(
 
     let media_file_id = media_file.id.clone();
 
-    models::MediaFile::upsert(&mut conn, &[media_file]).await?;
-    models::AlternateFile::upsert(&mut conn, &alternate_files).await?;
+    models::MediaFile::upsert(conn, &[media_file]).await?;
+    models::AlternateFile::upsert(conn, &alternate_files).await?;
+
+    Ok(media_file_id)
+}
+
+#[derive(MultipartForm, ToSchema)]
+struct MediaUpload {
+    #[schema(value_type = MediaUploadMetadata)]
+    json: MultipartJson<MediaUploadMetadata>,
+    #[schema(value_type = String, format = Binary)]
+    file: TempFile,
+}
+
+#[utoipa::path(request_body(content = MediaUpload, content_type = "multipart/form-data"), params(UploadOptions, ("Idempotency-Key" = Option<String>, Header, description = "Replays the response to an earlier request made with the same key.")), responses((status = OK, body = MediaUploadResponse), ApiErrorCode))]
+#[post("/media/upload")]
+#[instrument(err, skip_all, fields(id, catalog))]
+async fn upload_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    idempotency: Idempotency,
+    options: web::Query<UploadOptions>,
+    data: MultipartForm<MediaUpload>,
+) -> ApiResult<web::Json<MediaUploadResponse>> {
+    let data = data.into_inner();
+
+    tracing::Span::current().record("id", &data.json.id);
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    if let Some(response) = idempotency.replay(&mut conn, &session).await? {
+        return Ok(web::Json(response));
+    }
+
+    let mut media_item = uploadable_media_item(&mut conn, &session, &data.json.id).await?;
+
+    let temp_path = data.file.file.into_temp_path();
+    let sha256 = file_sha256(&temp_path).await?;
+
+    let (response, media_file_id) = match options
+        .duplicates
+        .check(&mut conn, &media_item, &sha256)
+        .await?
+    {
+        Some(existing) => (MediaUploadResponse { id: existing }, None),
+        None => {
+            update_media_item(
+                &mut conn,
+                &session,
+                AuditAction::EditMedia,
+                &mut media_item,
+                &data.json.metadata,
+            )
+            .await?;
+
+            let media_file_id = store_media_file(
+                &mut conn,
+                &media_item,
+                data.file.file_name.as_deref(),
+                &temp_path,
+                data.file.size,
+                &sha256,
+            )
+            .await?;
+
+            (
+                MediaUploadResponse { id: media_item.id },
+                Some(media_file_id),
+            )
+        }
+    };
+
+    idempotency.record(&mut conn, &session, &response).await?;
     conn.commit().await?;
 
-    app_state
-        .store
-        .queue_task(Task::ProcessMediaFile {
-            media_file: media_file_id,
-        })
-        .await;
+    if let Some(media_file) = media_file_id {
+        app_state
+            .store
+            .queue_task(Task::ProcessMediaFile { media_file })
+            .await;
+    }
+
+    Ok(web::Json(response))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct StartUploadRequest {
+    /// The media item to upload a new file for.
+    id: String,
+    file_name: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+struct MediaUploadState {
+    id: String,
+    /// The amount of data received so far. The next chunk must start here.
+    offset: u64,
+}
+
+/// The header giving the position in the file that a chunk starts at.
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+
+#[utoipa::path(responses((status = OK, body = MediaUploadState), ApiErrorCode))]
+#[post("/media/upload/start")]
+#[instrument(err, skip_all, fields(id))]
+async fn start_media_upload(
+    app_state: web::Data<AppState>,
+    session: Session,
+    data: web::Json<StartUploadRequest>,
+) -> ApiResult<web::Json<MediaUploadState>> {
+    tracing::Span::current().record("id", &data.id);
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let media_item = uploadable_media_item(&mut conn, &session, &data.id).await?;
+
+    let upload = models::MediaUpload::create(
+        &mut conn,
+        &session.user.email,
+        &media_item.id,
+        data.file_name.as_deref(),
+    )
+    .await?;
+
+    fs::create_dir_all(models::MediaUpload::directory(conn.config())).await?;
+    File::create(upload.file_path(conn.config())).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(MediaUploadState {
+        id: upload.id,
+        offset: 0,
+    }))
+}
+
+#[utoipa::path(responses((status = OK, body = MediaUploadState), ApiErrorCode))]
+#[get("/media/upload/{upload_id}")]
+#[instrument(err, skip_all, fields(upload = upload_id.as_str()))]
+async fn get_media_upload(
+    app_state: web::Data<AppState>,
+    session: Session,
+    upload_id: web::Path<String>,
+) -> ApiResult<web::Json<MediaUploadState>> {
+    let mut conn = app_state.store.connect().await?;
+    let upload =
+        models::MediaUpload::get_for_user(&mut conn, &session.user.email, &upload_id).await?;
+    let offset = fs::metadata(upload.file_path(conn.config())).await?.len();
+
+    Ok(web::Json(MediaUploadState {
+        id: upload.id,
+        offset,
+    }))
+}
+
+/// Appends the payload to the file, refusing any chunk that would take the
+/// file beyond `max_size`.
+async fn append_payload(
+    file: &mut File,
+    mut size: u64,
+    max_size: u64,
+    mut payload: web::Payload,
+) -> Result {
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(actix_web::Error::from)?;
+
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(Error::InvalidData {
+                message: format!("Uploads cannot be larger than {max_size} bytes"),
+            });
+        }
+
+        file.write_all(&chunk).await?;
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+    params((
+        "Upload-Offset" = u64,
+        Header,
+        description = "The offset returned by the previous request for this upload."
+    )),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
+    responses((status = OK, body = MediaUploadState), ApiErrorCode)
+)]
+#[patch("/media/upload/{upload_id}")]
+#[instrument(err, skip_all, fields(upload = upload_id.as_str(), offset))]
+async fn append_media_upload(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: HttpRequest,
+    upload_id: web::Path<String>,
+    payload: web::Payload,
+) -> ApiResult<web::Json<MediaUploadState>> {
+    let offset: u64 = request
+        .headers()
+        .get(UPLOAD_OFFSET)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.parse().ok())
+        .ok_or_else(|| ApiErrorCode::InvalidData(format!("A valid {UPLOAD_OFFSET} is required")))?;
+    tracing::Span::current().record("offset", offset);
+
+    // The payload is streamed without holding a database connection so only
+    // this lock prevents concurrent writes to the file.
+    let lock = app_state.store.locks().upload(&upload_id);
+    let _guard = lock.lock().await;
+
+    let upload = {
+        let mut conn = app_state.store.connect().await?;
+        models::MediaUpload::get_for_user(&mut conn, &session.user.email, &upload_id).await?
+    };
+
+    let config = app_state.store.config();
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(upload.file_path(config))
+        .await?;
+
+    let current = file.metadata().await?.len();
+    if offset != current {
+        return Err(ApiErrorCode::InvalidData(format!(
+            "{UPLOAD_OFFSET} must be {current}"
+        )));
+    }
+
+    // Whatever was received before a failure is kept so the client can
+    // resume from there.
+    let appended = append_payload(&mut file, current, config.max_upload_size, payload).await;
+    file.flush().await?;
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    upload.touch(&mut conn).await?;
+    conn.commit().await?;
+    appended?;
+
+    Ok(web::Json(MediaUploadState {
+        id: upload.id,
+        offset: file.metadata().await?.len(),
+    }))
+}
+
+#[utoipa::path(params(UploadOptions), responses((status = OK, body = MediaUploadResponse), ApiErrorCode))]
+#[post("/media/upload/{upload_id}/complete")]
+#[instrument(err, skip_all, fields(upload = upload_id.as_str()))]
+async fn complete_media_upload(
+    app_state: web::Data<AppState>,
+    session: Session,
+    upload_id: web::Path<String>,
+    options: web::Query<UploadOptions>,
+    data: web::Json<MediaData>,
+) -> ApiResult<web::Json<MediaUploadResponse>> {
+    // Waits for any chunk still being written.
+    let lock = app_state.store.locks().upload(&upload_id);
+    let _guard = lock.lock().await;
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let upload =
+        models::MediaUpload::get_for_user(&mut conn, &session.user.email, &upload_id).await?;
+
+    let mut media_item = uploadable_media_item(&mut conn, &session, &upload.media_item).await?;
+
+    let file_path = upload.file_path(conn.config());
+    let sha256 = file_sha256(&file_path).await?;
+
+    let (response, media_file_id) = match options
+        .duplicates
+        .check(&mut conn, &media_item, &sha256)
+        .await?
+    {
+        Some(existing) => (MediaUploadResponse { id: existing }, None),
+        None => {
+            update_media_item(
+                &mut conn,
+                &session,
+                AuditAction::EditMedia,
+                &mut media_item,
+                &data,
+            )
+            .await?;
+
+            let size = fs::metadata(&file_path).await?.len();
+            let media_file_id = store_media_file(
+                &mut conn,
+                &media_item,
+                upload.file_name.as_deref(),
+                &file_path,
+                size as usize,
+                &sha256,
+            )
+            .await?;
 
-    Ok(web::Json(MediaUploadResponse { id: media_item.id }))
+            (
+                MediaUploadResponse { id: media_item.id },
+                Some(media_file_id),
+            )
+        }
+    };
+
+    models::MediaUpload::delete(&mut conn, &upload.id).await?;
+    conn.commit().await?;
+
+    fs::remove_file(&file_path).await.warn();
+
+    if let Some(media_file) = media_file_id {
+        app_state
+            .store
+            .queue_task(Task::ProcessMediaFile { media_file })
+            .await;
+    }
+
+    Ok(web::Json(response))
 }
 
+#[utoipa::path(params(("Idempotency-Key" = Option<String>, Header, description = "Replays the response to an earlier request made with the same key.")), responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/media/edit")]
 #[instrument(err, skip_all, fields(id))]
 async fn edit_media(
     app_state: web::Data<AppState>,
     session: Session,
+    idempotency: Idempotency,
     data: web::Json<MediaUploadMetadata>,
 ) -> ApiResult<web::Json<ApiResponse>> {
     tracing::Span::current().record("id", &data.id);
 
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
-    let mut media =
-        models::MediaItem::get_for_user(&mut conn, &session.user.email, &[data.id.clone()]).await?;
-
-    if media.is_empty() {
-        return Err(Error::NotFound.into());
+    if let Some(response) = idempotency.replay(&mut conn, &session).await? {
+        return Ok(web::Json(response));
     }
 
-    let mut media_item = media.remove(0);
-    if media_item.deleted {
-        return Err(Error::NotFound.into());
-    }
+    let media_item = data.apply(&mut conn, &session).await?;
 
-    update_media_item(&mut conn, &mut media_item, &data.metadata).await?;
+    let response = ApiResponse::default();
+    idempotency.record(&mut conn, &session, &response).await?;
     conn.commit().await?;
 
     app_state
@@ -633,22 +1551,145 @@ async fn edit_media(
         })
         .await;
 
+    Ok(web::Json(response))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct BulkEditRequest {
+    ids: Vec<String>,
+    /// Applied to every media item. Any tags or people given replace those
+    /// already on the media.
+    #[serde(flatten)]
+    metadata: MediaData,
+    /// Tag hierarchies to add to the media.
+    #[serde(default)]
+    add_tags: Vec<Vec<String>>,
+    /// The ids of tags to remove from the media.
+    #[serde(default)]
+    remove_tags: Vec<String>,
+    #[serde(default)]
+    add_people: Vec<PersonInfo>,
+    /// The ids of people to remove from the media.
+    #[serde(default)]
+    remove_people: Vec<String>,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/media/bulk-edit")]
+#[instrument(err, skip_all, fields(count = data.ids.len()))]
+async fn bulk_edit_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    data: web::Json<BulkEditRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let ids: Vec<String> = data.ids.iter().unique().cloned().collect();
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut media = models::MediaItem::get_for_user(&mut conn, &session.user.email, &ids).await?;
+
+    if media.len() != ids.len() || media.iter().any(|media_item| media_item.deleted) {
+        return Err(Error::NotFound.into());
+    }
+
+    for media_item in media.iter() {
+        session.check_catalog(&media_item.catalog)?;
+    }
+
+    let audit = MediaAudit::capture(&mut conn, &media).await?;
+
+    for media_item in media.iter_mut() {
+        apply_media_data(&mut conn, media_item, &data.metadata).await?;
+    }
+
+    models::MediaItem::upsert(&mut conn, &media).await?;
+
+    let catalogs: HashSet<String> = media
+        .iter()
+        .map(|media_item| media_item.catalog.clone())
+        .collect();
+
+    for catalog in catalogs.iter() {
+        let catalog_media = media
+            .iter()
+            .filter(|media_item| &media_item.catalog == catalog)
+            .collect_vec();
+
+        if let Some(ref tag_list) = data.metadata.tags {
+            let tags = resolve_tags(&mut conn, catalog, tag_list).await?;
+            for media_item in catalog_media.iter() {
+                models::MediaTag::replace_for_media(
+                    &mut conn,
+                    &media_item.id,
+                    &media_tags(media_item, &tags),
+                )
+                .await?;
+            }
+        }
+
+        if let Some(ref person_list) = data.metadata.people {
+            let people = resolve_people(&mut conn, catalog, person_list).await?;
+            for media_item in catalog_media.iter() {
+                models::MediaPerson::replace_for_media(
+                    &mut conn,
+                    &media_item.id,
+                    &media_people(media_item, &people),
+                )
+                .await?;
+            }
+        }
+
+        let tags = resolve_tags(&mut conn, catalog, &data.add_tags).await?;
+        let media_tags = catalog_media
+            .iter()
+            .flat_map(|media_item| media_tags(media_item, &tags))
+            .collect_vec();
+        models::MediaTag::upsert(&mut conn, &media_tags).await?;
+
+        let people = resolve_people(&mut conn, catalog, &data.add_people).await?;
+        let media_people = catalog_media
+            .iter()
+            .flat_map(|media_item| media_people(media_item, &people))
+            .collect_vec();
+        models::MediaPerson::upsert(&mut conn, &media_people).await?;
+    }
+
+    models::MediaTag::remove(&mut conn, &ids, &data.remove_tags).await?;
+    models::MediaPerson::remove(&mut conn, &ids, &data.remove_people).await?;
+
+    audit
+        .record(&mut conn, &session, AuditAction::EditMedia, &media)
+        .await?;
+
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
     Ok(web::Json(Default::default()))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 struct SearchRequest {
     catalog: String,
     query: SearchQuery,
 }
 
+#[utoipa::path(params(MediaPage), responses((status = OK, content_type = "application/x-ndjson", body = models::MediaStreamItem), ApiErrorCode))]
 #[post("/search")]
 #[instrument(err, skip_all)]
 async fn search_media(
     app_state: web::Data<AppState>,
     session: Session,
     data: web::Json<SearchRequest>,
+    page: web::Query<MediaPage>,
 ) -> ApiResult<HttpResponse> {
+    session.check_catalog(&data.catalog)?;
+
     let mut conn = app_state.store.connect().await?;
     let user_catalog =
         models::Catalog::get_for_user(&mut conn, &session.user.email, &data.catalog, false).await?;
@@ -656,7 +1697,12 @@ async fn search_media(
     let (stream, sender) = MediaViewStream::new();
 
     let query = data.query.clone();
-    tokio::spawn(query.stream_media(conn, user_catalog.catalog.id.clone(), sender));
+    tokio::spawn(query.stream_media(
+        conn,
+        user_catalog.catalog.id.clone(),
+        page.into_inner(),
+        sender,
+    ));
 
     Ok(HttpResponseBuilder::new(StatusCode::OK)
         .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
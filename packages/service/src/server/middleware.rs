@@ -346,7 +346,7 @@ fn is_safe(addr: SocketAddr) -> bool {
     }
 }
 
-fn client_addr(req: &HttpRequest) -> Option<String> {
+pub(super) fn client_addr(req: &HttpRequest) -> Option<String> {
     if let Some(addr) = req.peer_addr() {
         if is_safe(addr) {
             if let Some(ip) = req.connection_info().realip_remote_addr() {
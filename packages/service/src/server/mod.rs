@@ -1,4 +1,9 @@
-use std::{collections::HashMap, env, fmt::Display, result};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    fmt::Display,
+    result,
+};
 
 use actix_multipart::form::MultipartFormConfig;
 use actix_web::{
@@ -17,9 +22,16 @@ use actix_web::{
     web, App, HttpRequest, HttpResponse, HttpServer, ResponseError,
 };
 use pixelbin_shared::ThumbnailConfig;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPoolOptions;
 use tracing::{instrument, trace};
+use utoipa::{
+    openapi::{
+        schema::{ObjectBuilder, Schema, Type},
+        ContentBuilder, Ref, RefOr, Response, ResponseBuilder, ResponsesBuilder,
+    },
+    IntoResponses, PartialSchema, ToSchema,
+};
 
 use crate::{
     store::{db::DbPool, Store},
@@ -27,10 +39,17 @@ use crate::{
     Error, Result,
 };
 
+mod admin;
+mod audit;
 mod auth;
+mod batch;
+mod idempotency;
 mod media;
 mod middleware;
+mod oidc;
+mod openapi;
 mod relations;
+mod signed;
 mod util;
 
 #[derive(Debug)]
@@ -38,6 +57,7 @@ enum ApiErrorCode {
     // UnknownException,
     // BadMethod,
     NotLoggedIn,
+    NotAuthorized,
     // LoginFailed,
     InvalidData(String),
     NotFound,
@@ -45,7 +65,7 @@ enum ApiErrorCode {
     InternalError(Error),
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 struct ApiResponse {
     message: String,
 }
@@ -65,6 +85,7 @@ impl Serialize for ApiErrorCode {
     {
         let (error, message) = match self {
             ApiErrorCode::NotLoggedIn => ("NotLoggedIn", None),
+            ApiErrorCode::NotAuthorized => ("NotAuthorized", None),
             ApiErrorCode::InvalidData(message) => ("InvalidData", Some(message.clone())),
             ApiErrorCode::NotFound => ("NotFound", None),
             ApiErrorCode::InternalError(error) => ("InternalError", Some(error.to_string())),
@@ -81,10 +102,53 @@ impl Serialize for ApiErrorCode {
     }
 }
 
+impl PartialSchema for ApiErrorCode {
+    fn schema() -> RefOr<Schema> {
+        ObjectBuilder::new()
+            .property(
+                "error",
+                ObjectBuilder::new()
+                    .schema_type(Type::String)
+                    .enum_values(Some([
+                        "NotLoggedIn",
+                        "NotAuthorized",
+                        "InvalidData",
+                        "NotFound",
+                        "InternalError",
+                    ])),
+            )
+            .required("error")
+            .property("message", ObjectBuilder::new().schema_type(Type::String))
+            .into()
+    }
+}
+
+impl ToSchema for ApiErrorCode {}
+
+impl IntoResponses for ApiErrorCode {
+    fn responses() -> BTreeMap<String, RefOr<Response>> {
+        ResponsesBuilder::new()
+            .response(
+                "default",
+                ResponseBuilder::new()
+                    .description("The request failed.")
+                    .content(
+                        "application/json",
+                        ContentBuilder::new()
+                            .schema(Some(Ref::from_schema_name(ApiErrorCode::name())))
+                            .build(),
+                    ),
+            )
+            .build()
+            .into()
+    }
+}
+
 impl Display for ApiErrorCode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ApiErrorCode::NotLoggedIn => f.write_str("APIError: NotLoggedIn"),
+            ApiErrorCode::NotAuthorized => f.write_str("APIError: NotAuthorized"),
             ApiErrorCode::InvalidData(message) => {
                 f.write_fmt(format_args!("APIError: InternalError: {}", message))
             }
@@ -102,6 +166,7 @@ impl ResponseError for ApiErrorCode {
             // ApiErrorCode::UnknownException => 500,
             // ApiErrorCode::BadMethod => 405,
             ApiErrorCode::NotLoggedIn => StatusCode::UNAUTHORIZED,
+            ApiErrorCode::NotAuthorized => StatusCode::FORBIDDEN,
             // ApiErrorCode::LoginFailed => 401,
             ApiErrorCode::InvalidData(_) => StatusCode::NOT_ACCEPTABLE,
             ApiErrorCode::NotFound => StatusCode::NOT_FOUND,
@@ -136,9 +201,11 @@ where
 struct AppState {
     store: Store,
     request_tracker: middleware::RequestTracker,
+    oidc: Option<oidc::OidcClient>,
+    url_signer: signed::UrlSigner,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 struct ApiConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -146,8 +213,10 @@ struct ApiConfig {
     api_url: String,
     base_url: String,
     thumbnails: ThumbnailConfig,
+    oidc_login: bool,
 }
 
+#[utoipa::path(security(()), responses((status = OK, body = ApiConfig), ApiErrorCode))]
 #[get("/config")]
 #[instrument(err, skip(app_state))]
 async fn config(app_state: web::Data<AppState>, uri: Uri) -> ApiResult<web::Json<ApiConfig>> {
@@ -158,6 +227,7 @@ async fn config(app_state: web::Data<AppState>, uri: Uri) -> ApiResult<web::Json
         api_url: config.api_url.to_string(),
         base_url: config.base_url.to_string(),
         thumbnails: config.thumbnails.clone(),
+        oidc_login: app_state.oidc.is_some(),
     }))
 }
 
@@ -230,6 +300,8 @@ async fn init_server(store: &Store) -> Result<web::Data<AppState>> {
     let state = AppState {
         store: store.with_pool(pool),
         request_tracker: middleware::RequestTracker::new(store.clone()).await,
+        oidc: oidc::OidcClient::new(store.config()),
+        url_signer: signed::UrlSigner::new(store.config()),
     };
 
     spawn_cron(store.clone());
@@ -259,30 +331,92 @@ pub async fn serve(store: Store) -> Result {
             .service(
                 web::scope("/api")
                     .service(config)
+                    .service(openapi::openapi_document)
                     .service(auth::login)
+                    .service(auth::login_totp)
                     .service(auth::logout)
                     .service(auth::state)
+                    .service(auth::register)
+                    .service(auth::verify_email)
+                    .service(auth::change_password)
+                    .service(auth::list_sessions)
+                    .service(auth::delete_session)
+                    .service(auth::delete_all_sessions)
+                    .service(auth::list_tokens)
+                    .service(auth::create_token)
+                    .service(auth::delete_token)
+                    .service(auth::enrol_totp)
+                    .service(auth::confirm_totp)
+                    .service(auth::reset_recovery_codes)
+                    .service(auth::disable_totp)
+                    .service(oidc::oidc_login)
+                    .service(oidc::oidc_callback)
+                    .service(admin::list_users)
+                    .service(admin::create_user)
+                    .service(admin::disable_user)
+                    .service(admin::promote_user)
                     .service(relations::get_album_media)
+                    .service(relations::share_album)
+                    .service(relations::list_album_shares)
+                    .service(relations::unshare_album)
+                    .service(relations::unlock_share)
+                    .service(relations::get_shared_album)
+                    .service(relations::get_shared_album_media)
                     .service(relations::get_search_media)
                     .service(relations::get_catalog_media)
+                    .service(relations::list_catalog_duplicates)
+                    .service(relations::list_catalog_similar)
+                    .service(relations::list_catalog_trash)
                     .service(relations::get_album)
                     .service(relations::get_search)
                     .service(relations::get_catalog)
                     .service(relations::set_source)
                     .service(relations::list_source)
                     .service(media::get_media)
+                    .service(media::list_similar_media)
+                    .service(media::list_media_files)
+                    .service(media::revert_media)
+                    .service(audit::list_media_audit)
+                    .service(audit::list_catalog_audit)
                     .service(media::create_media)
                     .service(media::upload_media)
+                    .service(media::start_media_upload)
+                    .service(media::get_media_upload)
+                    .service(media::append_media_upload)
+                    .service(media::complete_media_upload)
                     .service(media::edit_media)
+                    .service(media::bulk_edit_media)
                     .service(media::delete_media)
+                    .service(media::restore_media)
                     .service(media::search_media)
+                    .service(media::sign_media_url)
                     .service(relations::create_album)
                     .service(relations::edit_album)
                     .service(relations::delete_album)
+                    .service(relations::restore_album)
                     .service(relations::album_media_change)
+                    .service(relations::edit_tag)
+                    .service(relations::merge_tags)
+                    .service(relations::delete_tag)
+                    .service(relations::edit_person)
+                    .service(relations::merge_people)
+                    .service(relations::delete_person)
+                    .service(relations::create_search)
+                    .service(relations::edit_search)
+                    .service(relations::delete_search)
+                    .service(relations::create_storage)
+                    .service(relations::edit_storage)
+                    .service(relations::delete_storage)
+                    .service(relations::create_catalog)
+                    .service(relations::edit_catalog)
+                    .service(relations::delete_catalog)
+                    .service(relations::list_catalog_shares)
+                    .service(relations::share_catalog)
+                    .service(relations::unshare_catalog)
                     .service(relations::subscribe)
                     .service(relations::verify_subscription)
-                    .service(relations::unsubscribe),
+                    .service(relations::unsubscribe)
+                    .service(batch::run_batch),
             )
             .service(
                 web::scope("/media")
@@ -0,0 +1,538 @@
+//! Logging in through an OpenID Connect provider using the authorization code
+//! flow.
+use std::{result, time::Duration};
+
+use actix_web::{get, http::header, web, HttpRequest, HttpResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hyper::{body, client::HttpConnector, Body, Client, Method, Request};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use pixelbin_shared::{Ignorable, OidcConfig};
+use serde::{de::DeserializeOwned, Deserialize};
+use tokio::{sync::OnceCell, time::timeout};
+use tracing::{instrument, warn};
+use utoipa::IntoParams;
+
+use crate::{
+    server::{
+        auth::{session_client, LoginResponse},
+        ApiErrorCode, ApiResult, AppState,
+    },
+    store::{db::Isolation, models},
+    Config, Error, Result,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The parts of the provider's discovery document that are used.
+#[derive(Deserialize, Debug)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Audience {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, client_id: &str) -> bool {
+        match self {
+            Audience::Single(aud) => aud == client_id,
+            Audience::Multiple(auds) => auds.iter().any(|aud| aud == client_id),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct IdClaims {
+    iss: String,
+    aud: Audience,
+    exp: i64,
+    nonce: Option<String>,
+    email: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    name: Option<String>,
+}
+
+impl IdClaims {
+    /// Decodes the claims from an ID token.
+    ///
+    /// The token is received directly from the provider's token endpoint over
+    /// a TLS connection so, as permitted by OpenID Connect Core 3.1.3.7, its
+    /// signature is not checked. Plain HTTP is only allowed in testing.
+    fn decode(id_token: &str) -> result::Result<Self, String> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .ok_or_else(|| "ID token is not a JWT".to_owned())?;
+        let json = URL_SAFE_NO_PAD
+            .decode(payload.trim_end_matches('='))
+            .map_err(|e| format!("ID token is not valid base64: {e}"))?;
+
+        serde_json::from_slice(&json).map_err(|e| format!("ID token claims are invalid: {e}"))
+    }
+
+    /// Checks the claims were issued for this login and returns the verified
+    /// email address.
+    fn validate(
+        &self,
+        issuer: &str,
+        client_id: &str,
+        nonce: &str,
+        now: i64,
+    ) -> result::Result<&str, String> {
+        if self.iss != issuer {
+            return Err(format!("ID token was issued by {}", self.iss));
+        }
+
+        if !self.aud.contains(client_id) {
+            return Err("ID token was issued for a different client".to_owned());
+        }
+
+        if self.exp <= now {
+            return Err("ID token has expired".to_owned());
+        }
+
+        if self.nonce.as_deref() != Some(nonce) {
+            return Err("ID token nonce does not match".to_owned());
+        }
+
+        match self.email {
+            Some(ref email) if self.email_verified => Ok(email),
+            Some(_) => Err("Email address has not been verified by the provider".to_owned()),
+            None => Err("ID token does not include an email address".to_owned()),
+        }
+    }
+}
+
+pub(super) struct OidcClient {
+    config: OidcConfig,
+    redirect_url: String,
+    client: Client<HttpsConnector<HttpConnector>>,
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+impl OidcClient {
+    pub(super) fn new(config: &Config) -> Option<Self> {
+        let oidc = config.oidc.clone()?;
+
+        // The webapp receives the provider's redirect and completes the login
+        // through `/oidc/callback`.
+        let redirect_url = format!("{}login/oidc/callback", config.base_url);
+
+        Some(Self::build(oidc, redirect_url, config.testing))
+    }
+
+    fn build(config: OidcConfig, redirect_url: String, allow_http: bool) -> Self {
+        let builder = HttpsConnectorBuilder::new().with_native_roots();
+        let builder = if allow_http {
+            builder.https_or_http()
+        } else {
+            builder.https_only()
+        };
+        let connector = builder.enable_http1().build();
+
+        Self {
+            config,
+            redirect_url,
+            client: Client::builder().build(connector),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    async fn fetch_json<T: DeserializeOwned>(&self, request: Request<Body>) -> Result<T> {
+        let uri = request.uri().clone();
+
+        let response = timeout(REQUEST_TIMEOUT, self.client.request(request))
+            .await
+            .map_err(|_| Error::Unknown {
+                message: format!("Request to {uri} timed out"),
+            })?
+            .map_err(|e| Error::Unknown {
+                message: format!("Request to {uri} failed: {e}"),
+            })?;
+
+        let status = response.status();
+        let bytes = body::to_bytes(response.into_body())
+            .await
+            .map_err(|e| Error::Unknown {
+                message: format!("Request to {uri} failed: {e}"),
+            })?;
+
+        if !status.is_success() {
+            return Err(Error::Unknown {
+                message: format!(
+                    "Request to {uri} returned {status}: {}",
+                    String::from_utf8_lossy(&bytes)
+                ),
+            });
+        }
+
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn metadata(&self) -> Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+
+                let request =
+                    Request::get(url)
+                        .body(Body::empty())
+                        .map_err(|e| Error::Unknown {
+                            message: e.to_string(),
+                        })?;
+
+                self.fetch_json(request).await
+            })
+            .await
+    }
+
+    async fn authorization_url(&self, login: &models::OidcLogin) -> Result<String> {
+        let metadata = self.metadata().await?;
+
+        let query = serde_urlencoded::to_string([
+            ("response_type", "code"),
+            ("client_id", &self.config.client_id),
+            ("redirect_uri", &self.redirect_url),
+            ("scope", "openid email profile"),
+            ("state", &login.state),
+            ("nonce", &login.nonce),
+        ])
+        .map_err(|e| Error::Unknown {
+            message: e.to_string(),
+        })?;
+
+        let separator = if metadata.authorization_endpoint.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+
+        Ok(format!(
+            "{}{separator}{query}",
+            metadata.authorization_endpoint
+        ))
+    }
+
+    /// Exchanges the authorization code for the user's ID token claims.
+    async fn exchange_code(&self, code: &str) -> Result<(&ProviderMetadata, IdClaims)> {
+        let metadata = self.metadata().await?;
+
+        let form = serde_urlencoded::to_string([
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.redirect_url),
+            ("client_id", &self.config.client_id),
+            ("client_secret", &self.config.client_secret),
+        ])
+        .map_err(|e| Error::Unknown {
+            message: e.to_string(),
+        })?;
+
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(&metadata.token_endpoint)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json")
+            .body(Body::from(form))
+            .map_err(|e| Error::Unknown {
+                message: e.to_string(),
+            })?;
+
+        let response: TokenResponse = self.fetch_json(request).await?;
+
+        let claims =
+            IdClaims::decode(&response.id_token).map_err(|message| Error::Unknown { message })?;
+
+        Ok((metadata, claims))
+    }
+}
+
+#[utoipa::path(security(()), responses((status = FOUND, description = "Redirects to the OpenID Connect provider."), ApiErrorCode))]
+#[get("/oidc/login")]
+#[instrument(err, skip(app_state))]
+async fn oidc_login(app_state: web::Data<AppState>) -> ApiResult<HttpResponse> {
+    let oidc = app_state.oidc.as_ref().ok_or(ApiErrorCode::NotFound)?;
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let login = models::OidcLogin::create(&mut conn).await?;
+    let url = oidc.authorization_url(&login).await?;
+    conn.commit().await?;
+
+    Ok(HttpResponse::Found()
+        .insert_header((header::LOCATION, url))
+        .finish())
+}
+
+#[derive(Deserialize, Debug, IntoParams)]
+struct CallbackQuery {
+    state: String,
+    code: Option<String>,
+    error: Option<String>,
+}
+
+/// Completes a login. The provider redirects the browser to the webapp, which
+/// passes on the query parameters it received.
+#[utoipa::path(params(CallbackQuery), security(()), responses((status = OK, body = LoginResponse), ApiErrorCode))]
+#[get("/oidc/callback")]
+#[instrument(err, skip(app_state, request, query))]
+async fn oidc_callback(
+    app_state: web::Data<AppState>,
+    request: HttpRequest,
+    query: web::Query<CallbackQuery>,
+) -> ApiResult<web::Json<LoginResponse>> {
+    let oidc = app_state.oidc.as_ref().ok_or(ApiErrorCode::NotFound)?;
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+
+    let login = match models::OidcLogin::take(&mut conn, &query.state).await {
+        Ok(login) => login,
+        Err(Error::NotFound) => {
+            conn.rollback().await.warn();
+            warn!("Unknown or expired OpenID Connect login");
+            return Err(ApiErrorCode::NotLoggedIn);
+        }
+        Err(e) => {
+            conn.rollback().await.warn();
+            return Err(e.into());
+        }
+    };
+
+    let code = match (&query.code, &query.error) {
+        (Some(code), _) => code,
+        (None, error) => {
+            // The login has been used up so remove it.
+            conn.commit().await?;
+            warn!(error, "Provider rejected the login");
+            return Err(ApiErrorCode::NotLoggedIn);
+        }
+    };
+
+    let (metadata, claims) = oidc.exchange_code(code).await?;
+
+    let email = match claims.validate(
+        &metadata.issuer,
+        &oidc.config.client_id,
+        &login.nonce,
+        Utc::now().timestamp(),
+    ) {
+        Ok(email) => email,
+        Err(reason) => {
+            conn.commit().await?;
+            warn!(reason, "Rejected OpenID Connect login");
+            return Err(ApiErrorCode::NotLoggedIn);
+        }
+    };
+
+    match models::User::external_login(
+        &mut conn,
+        email,
+        claims.name.as_deref(),
+        oidc.config.auto_provision,
+        &session_client(&request),
+    )
+    .await
+    {
+        Ok((_, login)) => {
+            conn.commit().await?;
+            Ok(web::Json(login.into()))
+        }
+        Err(Error::NotFound) => {
+            conn.rollback().await.warn();
+            warn!(email, "No account available for OpenID Connect login");
+            Err(ApiErrorCode::NotLoggedIn)
+        }
+        Err(e) => {
+            conn.rollback().await.warn();
+            Err(e.into())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, net::TcpListener};
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use pixelbin_shared::OidcConfig;
+    use serde_json::json;
+
+    use super::{IdClaims, OidcClient};
+    use crate::store::models::OidcLogin;
+
+    fn token(claims: serde_json::Value) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        )
+    }
+
+    #[test]
+    fn validate_claims() {
+        let claims = |overrides: serde_json::Value| {
+            let mut claims = json!({
+                "iss": "https://idp.example.com",
+                "aud": "pixelbin",
+                "exp": 2000,
+                "nonce": "O:nonce",
+                "email": "user@example.com",
+                "email_verified": true,
+            });
+            claims
+                .as_object_mut()
+                .unwrap()
+                .extend(overrides.as_object().unwrap().clone());
+            IdClaims::decode(&token(claims)).unwrap()
+        };
+
+        let valid = claims(json!({}));
+        assert_eq!(
+            valid.validate("https://idp.example.com", "pixelbin", "O:nonce", 1000),
+            Ok("user@example.com")
+        );
+        assert!(valid
+            .validate("https://other.example.com", "pixelbin", "O:nonce", 1000)
+            .is_err());
+        assert!(valid
+            .validate("https://idp.example.com", "other", "O:nonce", 1000)
+            .is_err());
+        assert!(valid
+            .validate("https://idp.example.com", "pixelbin", "O:other", 1000)
+            .is_err());
+        assert!(valid
+            .validate("https://idp.example.com", "pixelbin", "O:nonce", 3000)
+            .is_err());
+
+        let multiple = claims(json!({ "aud": ["other", "pixelbin"] }));
+        assert!(multiple
+            .validate("https://idp.example.com", "pixelbin", "O:nonce", 1000)
+            .is_ok());
+
+        let unverified = claims(json!({ "email_verified": false }));
+        assert!(unverified
+            .validate("https://idp.example.com", "pixelbin", "O:nonce", 1000)
+            .is_err());
+
+        let no_nonce = claims(json!({ "nonce": null }));
+        assert!(no_nonce
+            .validate("https://idp.example.com", "pixelbin", "O:nonce", 1000)
+            .is_err());
+
+        assert!(IdClaims::decode("not-a-token").is_err());
+    }
+
+    /// Starts a provider that implements just enough of discovery and the
+    /// token endpoint for a login. Returns its issuer URL.
+    fn mock_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let issuer = format!("http://{}", listener.local_addr().unwrap());
+
+        let provider = issuer.clone();
+        let server = HttpServer::new(move || {
+            let issuer = provider.clone();
+
+            App::new()
+                .app_data(web::Data::new(issuer))
+                .route(
+                    "/.well-known/openid-configuration",
+                    web::get().to(|issuer: web::Data<String>| async move {
+                        HttpResponse::Ok().json(json!({
+                            "issuer": issuer.as_str(),
+                            "authorization_endpoint": format!("{}/authorize", issuer.as_str()),
+                            "token_endpoint": format!("{}/token", issuer.as_str()),
+                        }))
+                    }),
+                )
+                .route(
+                    "/token",
+                    web::post().to(
+                        |issuer: web::Data<String>,
+                         form: web::Form<HashMap<String, String>>| async move {
+                            if form.get("code").map(String::as_str) != Some("mock-code")
+                                || form.get("client_secret").map(String::as_str)
+                                    != Some("secret")
+                            {
+                                return HttpResponse::BadRequest().finish();
+                            }
+
+                            HttpResponse::Ok().json(json!({
+                                "id_token": token(json!({
+                                    "iss": issuer.as_str(),
+                                    "aud": "pixelbin",
+                                    "exp": 4000000000_i64,
+                                    "nonce": "O:nonce",
+                                    "email": "user@example.com",
+                                    "email_verified": true,
+                                })),
+                            }))
+                        },
+                    ),
+                )
+        })
+        .workers(1)
+        .listen(listener)
+        .unwrap()
+        .run();
+
+        actix_web::rt::spawn(server);
+
+        issuer
+    }
+
+    fn client(issuer: &str, allow_http: bool) -> OidcClient {
+        OidcClient::build(
+            OidcConfig {
+                issuer: issuer.to_owned(),
+                client_id: "pixelbin".to_owned(),
+                client_secret: "secret".to_owned(),
+                auto_provision: false,
+            },
+            "http://localhost/login/oidc/callback".to_owned(),
+            allow_http,
+        )
+    }
+
+    #[actix_web::test]
+    async fn login_with_mock_provider() {
+        let issuer = mock_provider();
+        let oidc = client(&issuer, true);
+
+        let login = OidcLogin {
+            state: "O:state".to_owned(),
+            nonce: "O:nonce".to_owned(),
+        };
+        let url = oidc.authorization_url(&login).await.unwrap();
+        assert!(url.starts_with(&format!("{issuer}/authorize?")));
+        assert!(url.contains("state=O%3Astate"));
+        assert!(url.contains("nonce=O%3Anonce"));
+
+        assert!(oidc.exchange_code("bad-code").await.is_err());
+
+        let (metadata, claims) = oidc.exchange_code("mock-code").await.unwrap();
+        assert_eq!(
+            claims.validate(&metadata.issuer, "pixelbin", &login.nonce, 1000),
+            Ok("user@example.com")
+        );
+
+        // Without TLS the ID token cannot be trusted.
+        let insecure = client(&issuer, false);
+        assert!(insecure.exchange_code("mock-code").await.is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! An OpenAPI description of the routes in the `/api` scope, generated from
+//! the handlers and the types they accept and return.
+use actix_web::{get, web};
+use utoipa::{
+    openapi::{
+        self,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    server::{admin, audit, auth, batch, media, oidc, relations, ApiErrorCode},
+    store::db::search::{CompoundItem, CompoundQuery},
+};
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut openapi::OpenApi) {
+        openapi
+            .components
+            .get_or_insert_with(Default::default)
+            .add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Pixelbin"),
+    servers((url = "/api")),
+    security(("bearer" = [])),
+    modifiers(&BearerAuth),
+    components(schemas(ApiErrorCode, CompoundItem, CompoundQuery<CompoundItem>)),
+    paths(
+        openapi_document,
+        super::config,
+        auth::login,
+        auth::login_totp,
+        auth::logout,
+        auth::state,
+        auth::register,
+        auth::verify_email,
+        auth::change_password,
+        auth::list_sessions,
+        auth::delete_session,
+        auth::delete_all_sessions,
+        auth::list_tokens,
+        auth::create_token,
+        auth::delete_token,
+        auth::enrol_totp,
+        auth::confirm_totp,
+        auth::reset_recovery_codes,
+        auth::disable_totp,
+        oidc::oidc_login,
+        oidc::oidc_callback,
+        admin::list_users,
+        admin::create_user,
+        admin::disable_user,
+        admin::promote_user,
+        relations::get_album_media,
+        relations::share_album,
+        relations::list_album_shares,
+        relations::unshare_album,
+        relations::unlock_share,
+        relations::get_shared_album,
+        relations::get_shared_album_media,
+        relations::get_search_media,
+        relations::get_catalog_media,
+        relations::list_catalog_duplicates,
+        relations::list_catalog_similar,
+        relations::list_catalog_trash,
+        relations::get_album,
+        relations::get_search,
+        relations::get_catalog,
+        relations::set_source,
+        relations::list_source,
+        media::get_media,
+        media::list_similar_media,
+        media::list_media_files,
+        media::revert_media,
+        audit::list_media_audit,
+        audit::list_catalog_audit,
+        media::create_media,
+        media::upload_media,
+        media::start_media_upload,
+        media::get_media_upload,
+        media::append_media_upload,
+        media::complete_media_upload,
+        media::edit_media,
+        media::bulk_edit_media,
+        media::delete_media,
+        media::restore_media,
+        media::search_media,
+        media::sign_media_url,
+        relations::create_album,
+        relations::edit_album,
+        relations::delete_album,
+        relations::restore_album,
+        relations::album_media_change,
+        relations::edit_tag,
+        relations::merge_tags,
+        relations::delete_tag,
+        relations::edit_person,
+        relations::merge_people,
+        relations::delete_person,
+        relations::create_search,
+        relations::edit_search,
+        relations::delete_search,
+        relations::create_storage,
+        relations::edit_storage,
+        relations::delete_storage,
+        relations::create_catalog,
+        relations::edit_catalog,
+        relations::delete_catalog,
+        relations::list_catalog_shares,
+        relations::share_catalog,
+        relations::unshare_catalog,
+        relations::subscribe,
+        relations::verify_subscription,
+        relations::unsubscribe,
+        batch::run_batch,
+    )
+)]
+struct ApiDoc;
+
+#[utoipa::path(
+    security(()),
+    responses((status = OK, description = "This document.", content_type = "application/json"))
+)]
+#[get("/openapi.json")]
+async fn openapi_document() -> web::Json<openapi::OpenApi> {
+    web::Json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use serde_json::Value;
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+
+    fn references(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => found.push(reference.clone()),
+                        _ => references(value, found),
+                    }
+                }
+            }
+            Value::Array(values) => values.iter().for_each(|value| references(value, found)),
+            _ => {}
+        }
+    }
+
+    /// The names of the handlers registered in the `/api` scope by `serve`.
+    fn registered_handlers() -> Vec<&'static str> {
+        let source = include_str!("mod.rs");
+        let scope = source
+            .split(r#"web::scope("/api")"#)
+            .nth(1)
+            .unwrap()
+            .split("web::scope(")
+            .next()
+            .unwrap();
+
+        scope
+            .split(".service(")
+            .skip(1)
+            .map(|service| {
+                let path = service.split(')').next().unwrap();
+                path.rsplit("::").next().unwrap().trim()
+            })
+            // The `.service(` wrapping the next scope.
+            .filter(|handler| !handler.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn documents_all_routes() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+
+        let operations: HashSet<&str> = document["paths"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|item| item.as_object().unwrap().values())
+            .filter_map(|operation| operation.get("operationId").and_then(Value::as_str))
+            .collect();
+
+        let handlers = registered_handlers();
+        assert!(handlers.len() > 50);
+
+        for handler in handlers {
+            assert!(
+                operations.contains(handler),
+                "{handler} is not in the OpenAPI document"
+            );
+        }
+    }
+
+    #[test]
+    fn references_resolve() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let schemas = document["components"]["schemas"].as_object().unwrap();
+
+        let mut found = Vec::new();
+        references(&document, &mut found);
+
+        for reference in found {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(name), "{reference} does not resolve");
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, slice};
 
 use actix_web::{
     get,
@@ -8,38 +8,76 @@ use actix_web::{
     HttpResponse, HttpResponseBuilder,
 };
 use chrono::{DateTime, Utc};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_with::{serde_as, OneOrMany};
 use tracing::instrument;
+use utoipa::{IntoParams, ToSchema};
 
 use crate::{
+    mail::{send_messages, CatalogInvitation},
     server::{
         auth::{MaybeSession, Session},
-        ApiResponse, ApiResult, AppState,
+        media::SimilarityOptions,
+        ApiErrorCode, ApiResponse, ApiResult, AppState,
     },
     shared::short_id,
     store::{
-        db::Isolation,
+        db::{page::MediaPage, search::SearchQuery, DbConnection, Isolation},
         models::{
-            self, AlbumWithCount, MediaViewStream, SavedSearchWithCount, SourceType,
-            UserCatalogWithCount,
+            self, AlbumWithCount, AuditAction, AuditEntry, MediaViewStream, SavedSearchWithCount,
+            SourceType, UserCatalogWithCount,
         },
     },
-    Task,
+    Error, Result, Task,
 };
 
-#[derive(Deserialize, Clone, Debug)]
-struct AlbumDetail {
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub(super) struct AlbumDetail {
     name: String,
-    parent: Option<String>,
+    pub(super) parent: Option<String>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-struct CreateAlbumRequest {
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub(super) struct CreateAlbumRequest {
     catalog: String,
-    album: AlbumDetail,
+    pub(super) album: AlbumDetail,
+}
+
+impl CreateAlbumRequest {
+    pub(super) async fn apply(
+        &self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+    ) -> ApiResult<models::Album> {
+        session.check_catalog(&self.catalog)?;
+
+        let user_catalog =
+            models::Catalog::get_for_user(conn, &session.user.email, &self.catalog, true).await?;
+
+        let album = models::Album {
+            id: short_id("A"),
+            catalog: user_catalog.catalog.id.clone(),
+            name: self.album.name.clone(),
+            parent: self.album.parent.clone(),
+        };
+
+        models::Album::upsert(conn, slice::from_ref(&album)).await?;
+
+        let entry = AuditEntry::new(
+            &album.catalog,
+            &session.user.email,
+            AuditAction::CreateAlbum,
+            &album.id,
+        )
+        .change("name", None, Some(&album.name))
+        .change("parent", None, album.parent.as_deref());
+        AuditEntry::insert(conn, &[entry]).await?;
+
+        Ok(album)
+    }
 }
 
+#[utoipa::path(responses((status = OK, body = models::Album), ApiErrorCode))]
 #[post("/album/create")]
 #[instrument(err, skip(app_state, session, request))]
 async fn create_album(
@@ -47,29 +85,52 @@ async fn create_album(
     session: Session,
     request: web::Json<CreateAlbumRequest>,
 ) -> ApiResult<web::Json<models::Album>> {
-    let mut conn = app_state.store.connect().await?;
-    let user_catalog =
-        models::Catalog::get_for_user(&mut conn, &session.user.email, &request.catalog, true)
-            .await?;
-
-    let album = models::Album {
-        id: short_id("A"),
-        catalog: user_catalog.catalog.id.clone(),
-        name: request.album.name.clone(),
-        parent: request.album.parent.clone(),
-    };
-
-    models::Album::upsert(&mut conn, &[album.clone()]).await?;
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let album = request.apply(&mut conn, &session).await?;
+    conn.commit().await?;
 
     Ok(web::Json(album))
 }
 
-#[derive(Deserialize, Clone, Debug)]
-struct EditAlbumRequest {
-    id: String,
-    album: AlbumDetail,
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub(super) struct EditAlbumRequest {
+    pub(super) id: String,
+    pub(super) album: AlbumDetail,
+}
+
+impl EditAlbumRequest {
+    pub(super) async fn apply(
+        &self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+    ) -> ApiResult<models::Album> {
+        let mut album =
+            models::Album::get_writable_for_user(conn, &session.user.email, &self.id).await?;
+        session.check_catalog(&album.catalog)?;
+
+        let entry = AuditEntry::new(
+            &album.catalog,
+            &session.user.email,
+            AuditAction::EditAlbum,
+            &album.id,
+        )
+        .change("name", &album.name, &self.album.name)
+        .change("parent", &album.parent, &self.album.parent);
+
+        album.name.clone_from(&self.album.name);
+        album.parent.clone_from(&self.album.parent);
+
+        models::Album::upsert(conn, slice::from_ref(&album)).await?;
+
+        if !entry.changes.is_empty() {
+            AuditEntry::insert(conn, &[entry]).await?;
+        }
+
+        Ok(album)
+    }
 }
 
+#[utoipa::path(responses((status = OK, body = models::Album), ApiErrorCode))]
 #[post("/album/edit")]
 #[instrument(err, skip(app_state, session, request))]
 async fn edit_album(
@@ -78,13 +139,7 @@ async fn edit_album(
     request: web::Json<EditAlbumRequest>,
 ) -> ApiResult<web::Json<models::Album>> {
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
-    let mut album =
-        models::Album::get_writable_for_user(&mut conn, &session.user.email, &request.id).await?;
-
-    album.name.clone_from(&request.album.name);
-    album.parent.clone_from(&request.album.parent);
-
-    models::Album::upsert(&mut conn, &[album.clone()]).await?;
+    let album = request.apply(&mut conn, &session).await?;
     conn.commit().await?;
 
     app_state
@@ -97,6 +152,7 @@ async fn edit_album(
     Ok(web::Json(album))
 }
 
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/album/delete")]
 #[instrument(err, skip(app_state, session, albums))]
 async fn delete_album(
@@ -107,15 +163,72 @@ async fn delete_album(
     let mut conn = app_state.store.isolated(Isolation::Committed).await?;
     let mut ids: Vec<String> = Vec::new();
     let mut catalogs: HashSet<String> = HashSet::new();
+    let mut entries: Vec<AuditEntry> = Vec::new();
 
     for id in albums.iter() {
         let album =
             models::Album::get_writable_for_user(&mut conn, &session.user.email, id).await?;
+        session.check_catalog(&album.catalog)?;
+        entries.push(
+            AuditEntry::new(
+                &album.catalog,
+                &session.user.email,
+                AuditAction::DeleteAlbum,
+                &album.id,
+            )
+            .change("name", Some(&album.name), None),
+        );
+        catalogs.insert(album.catalog);
+        ids.push(album.id);
+    }
+
+    models::Album::trash(&mut conn, &ids).await?;
+    AuditEntry::insert(&mut conn, &entries).await?;
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
+    Ok(web::Json(Default::default()))
+}
+
+/// Restores deleted albums from the trash.
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/album/restore")]
+#[instrument(err, skip(app_state, session, albums))]
+async fn restore_album(
+    app_state: web::Data<AppState>,
+    session: Session,
+    albums: web::Json<Vec<String>>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut ids: Vec<String> = Vec::new();
+    let mut catalogs: HashSet<String> = HashSet::new();
+    let mut entries: Vec<AuditEntry> = Vec::new();
+
+    for id in albums.iter() {
+        let album =
+            models::TrashedAlbum::get_writable_for_user(&mut conn, &session.user.email, id).await?;
+        session.check_catalog(&album.catalog)?;
+        entries.push(
+            AuditEntry::new(
+                &album.catalog,
+                &session.user.email,
+                AuditAction::RestoreAlbum,
+                &album.id,
+            )
+            .change("name", None, Some(&album.name)),
+        );
         catalogs.insert(album.catalog);
         ids.push(album.id);
     }
 
-    models::Album::delete(&mut conn, &ids).await?;
+    models::TrashedAlbum::restore(&mut conn, &ids).await?;
+    AuditEntry::insert(&mut conn, &entries).await?;
     conn.commit().await?;
 
     for catalog in catalogs {
@@ -128,7 +241,7 @@ async fn delete_album(
     Ok(web::Json(Default::default()))
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 enum RelationOperation {
     Add,
@@ -136,34 +249,46 @@ enum RelationOperation {
 }
 
 #[serde_as]
-#[derive(Deserialize, Clone, Debug)]
-struct AlbumMediaChange {
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+pub(super) struct AlbumMediaChange {
     operation: RelationOperation,
     #[serde_as(deserialize_as = "OneOrMany<_>")]
-    media: Vec<String>,
-    album: String,
+    pub(super) media: Vec<String>,
+    pub(super) album: String,
 }
 
-#[post("/album/media")]
-#[instrument(err, skip(app_state, session, updates))]
-async fn album_media_change(
-    app_state: web::Data<AppState>,
-    session: Session,
-    updates: web::Json<Vec<AlbumMediaChange>>,
-) -> ApiResult<web::Json<ApiResponse>> {
-    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
-    let mut catalogs: HashSet<String> = HashSet::new();
-
-    for update in updates.into_inner() {
+impl AlbumMediaChange {
+    /// Returns the album that was changed.
+    pub(super) async fn apply(
+        self,
+        conn: &mut DbConnection<'_>,
+        session: &Session,
+    ) -> ApiResult<models::Album> {
         let album =
-            models::Album::get_writable_for_user(&mut conn, &session.user.email, &update.album)
-                .await?;
-
-        catalogs.insert(album.catalog.clone());
-
-        match update.operation {
+            models::Album::get_writable_for_user(conn, &session.user.email, &self.album).await?;
+        session.check_catalog(&album.catalog)?;
+
+        let (old, new) = match self.operation {
+            RelationOperation::Add => (None, Some(&album.id)),
+            RelationOperation::Delete => (Some(&album.id), None),
+        };
+        let entries = self
+            .media
+            .iter()
+            .map(|media| {
+                AuditEntry::new(
+                    &album.catalog,
+                    &session.user.email,
+                    AuditAction::AlbumMedia,
+                    media,
+                )
+                .change("album", old, new)
+            })
+            .collect::<Vec<_>>();
+
+        match self.operation {
             RelationOperation::Add => {
-                let media_albums: Vec<models::MediaAlbum> = update
+                let media_albums: Vec<models::MediaAlbum> = self
                     .media
                     .into_iter()
                     .map(|m| models::MediaAlbum {
@@ -173,12 +298,33 @@ async fn album_media_change(
                     })
                     .collect();
 
-                models::MediaAlbum::upsert(&mut conn, &media_albums).await?;
+                models::MediaAlbum::upsert(conn, &media_albums).await?;
             }
             RelationOperation::Delete => {
-                models::MediaAlbum::remove_media(&mut conn, &album.id, &update.media).await?;
+                models::MediaAlbum::remove_media(conn, &album.id, &self.media).await?;
             }
         }
+
+        AuditEntry::insert(conn, &entries).await?;
+
+        Ok(album)
+    }
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/album/media")]
+#[instrument(err, skip(app_state, session, updates))]
+async fn album_media_change(
+    app_state: web::Data<AppState>,
+    session: Session,
+    updates: web::Json<Vec<AlbumMediaChange>>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut catalogs: HashSet<String> = HashSet::new();
+
+    for update in updates.into_inner() {
+        let album = update.apply(&mut conn, &session).await?;
+        catalogs.insert(album.catalog);
     }
     conn.commit().await?;
 
@@ -192,117 +338,1137 @@ async fn album_media_change(
     return Ok(web::Json(ApiResponse::default()));
 }
 
-fn default_true() -> bool {
-    true
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct TagDetail {
+    name: String,
+    parent: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-struct AlbumListRequest {
-    #[serde(default = "default_true")]
-    recursive: bool,
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct EditTagRequest {
+    id: String,
+    tag: TagDetail,
 }
 
-#[get("/album/{album_id}")]
-#[instrument(err, skip(app_state, session))]
-async fn get_album(
+#[utoipa::path(responses((status = OK, body = models::Tag), ApiErrorCode))]
+#[post("/tag/edit")]
+#[instrument(err, skip(app_state, session, request))]
+async fn edit_tag(
     app_state: web::Data<AppState>,
     session: Session,
-    album_id: web::Path<String>,
-    query: web::Query<AlbumListRequest>,
-) -> ApiResult<web::Json<AlbumWithCount>> {
-    let mut conn = app_state.store.connect().await?;
-    let album = models::Album::get_for_user_with_count(
-        &mut conn,
+    request: web::Json<EditTagRequest>,
+) -> ApiResult<web::Json<models::Tag>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut tag =
+        models::Tag::get_writable_for_user(&mut conn, &session.user.email, &request.id).await?;
+    session.check_catalog(&tag.catalog)?;
+
+    let entry = AuditEntry::new(
+        &tag.catalog,
         &session.user.email,
-        &album_id,
-        query.recursive,
+        AuditAction::EditTag,
+        &tag.id,
     )
-    .await?;
+    .change("name", &tag.name, &request.tag.name)
+    .change("parent", &tag.parent, &request.tag.parent);
 
-    Ok(web::Json(album))
+    tag.edit(&mut conn, &request.tag.name, request.tag.parent.as_deref())
+        .await?;
+    if !entry.changes.is_empty() {
+        AuditEntry::insert(&mut conn, &[entry]).await?;
+    }
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: tag.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(tag))
 }
 
-#[get("/search/{search_id}")]
-#[instrument(err, skip(app_state, session))]
-async fn get_search(
+#[serde_as]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct MergeTagsRequest {
+    target: String,
+    #[serde_as(deserialize_as = "OneOrMany<_>")]
+    tags: Vec<String>,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Tag), ApiErrorCode))]
+#[post("/tag/merge")]
+#[instrument(err, skip(app_state, session, request))]
+async fn merge_tags(
     app_state: web::Data<AppState>,
-    session: MaybeSession,
-    search_id: web::Path<String>,
-) -> ApiResult<web::Json<SavedSearchWithCount>> {
-    let email = session.session().map(|s| s.user.email.as_str());
+    session: Session,
+    request: web::Json<MergeTagsRequest>,
+) -> ApiResult<web::Json<models::Tag>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let tag =
+        models::Tag::get_writable_for_user(&mut conn, &session.user.email, &request.target).await?;
+    session.check_catalog(&tag.catalog)?;
 
-    let mut conn = app_state.store.connect().await?;
-    let search = models::SavedSearch::get_for_user_with_count(&mut conn, email, &search_id).await?;
+    tag.merge(&mut conn, &request.tags).await?;
 
-    Ok(web::Json(search))
+    let entry = AuditEntry::new(
+        &tag.catalog,
+        &session.user.email,
+        AuditAction::MergeTags,
+        &tag.id,
+    )
+    .change("merged", None, Some(&request.tags));
+    AuditEntry::insert(&mut conn, &[entry]).await?;
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: tag.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(tag))
 }
 
-#[get("/catalog/{catalog_id}")]
-#[instrument(err, skip(app_state, session))]
-async fn get_catalog(
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/tag/delete")]
+#[instrument(err, skip(app_state, session, tags))]
+async fn delete_tag(
     app_state: web::Data<AppState>,
     session: Session,
-    catalog_id: web::Path<String>,
-) -> ApiResult<web::Json<UserCatalogWithCount>> {
-    let mut conn = app_state.store.connect().await?;
-    let user_catalog =
-        models::Catalog::get_for_user_with_count(&mut conn, &session.user.email, &catalog_id)
+    tags: web::Json<Vec<String>>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut ids: Vec<String> = Vec::new();
+    let mut catalogs: HashSet<String> = HashSet::new();
+    let mut entries: Vec<AuditEntry> = Vec::new();
+
+    for id in tags.iter() {
+        let tag = models::Tag::get_writable_for_user(&mut conn, &session.user.email, id).await?;
+        session.check_catalog(&tag.catalog)?;
+        entries.push(
+            AuditEntry::new(
+                &tag.catalog,
+                &session.user.email,
+                AuditAction::DeleteTag,
+                &tag.id,
+            )
+            .change("name", Some(&tag.name), None),
+        );
+        catalogs.insert(tag.catalog);
+        ids.push(tag.id);
+    }
+
+    models::Tag::delete(&mut conn, &ids).await?;
+    AuditEntry::insert(&mut conn, &entries).await?;
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct EditPersonRequest {
+    id: String,
+    name: String,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Person), ApiErrorCode))]
+#[post("/person/edit")]
+#[instrument(err, skip(app_state, session, request))]
+async fn edit_person(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<EditPersonRequest>,
+) -> ApiResult<web::Json<models::Person>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut person =
+        models::Person::get_writable_for_user(&mut conn, &session.user.email, &request.id).await?;
+    session.check_catalog(&person.catalog)?;
+
+    let entry = AuditEntry::new(
+        &person.catalog,
+        &session.user.email,
+        AuditAction::EditPerson,
+        &person.id,
+    )
+    .change("name", &person.name, &request.name);
+
+    person.rename(&mut conn, &request.name).await?;
+    if !entry.changes.is_empty() {
+        AuditEntry::insert(&mut conn, &[entry]).await?;
+    }
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: person.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(person))
+}
+
+#[serde_as]
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct MergePeopleRequest {
+    target: String,
+    #[serde_as(deserialize_as = "OneOrMany<_>")]
+    people: Vec<String>,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Person), ApiErrorCode))]
+#[post("/person/merge")]
+#[instrument(err, skip(app_state, session, request))]
+async fn merge_people(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<MergePeopleRequest>,
+) -> ApiResult<web::Json<models::Person>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let person =
+        models::Person::get_writable_for_user(&mut conn, &session.user.email, &request.target)
             .await?;
+    session.check_catalog(&person.catalog)?;
 
-    Ok(web::Json(user_catalog))
+    person.merge(&mut conn, &request.people).await?;
+
+    let entry = AuditEntry::new(
+        &person.catalog,
+        &session.user.email,
+        AuditAction::MergePeople,
+        &person.id,
+    )
+    .change("merged", None, Some(&request.people));
+    AuditEntry::insert(&mut conn, &[entry]).await?;
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: person.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(person))
 }
 
-#[get("/catalog/{catalog_id}/media")]
-#[instrument(err, skip(app_state, session))]
-async fn get_catalog_media(
+#[derive(Serialize, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct DeletePeopleResponse {
+    /// Saved searches that still reference one of the deleted people.
+    affected_searches: Vec<models::SavedSearch>,
+}
+
+#[utoipa::path(responses((status = OK, body = DeletePeopleResponse), ApiErrorCode))]
+#[post("/person/delete")]
+#[instrument(err, skip(app_state, session, people))]
+async fn delete_person(
     app_state: web::Data<AppState>,
     session: Session,
-    catalog_id: web::Path<String>,
-) -> ApiResult<HttpResponse> {
-    let mut conn = app_state.store.connect().await?;
-    let user_catalog =
-        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+    people: web::Json<Vec<String>>,
+) -> ApiResult<web::Json<DeletePeopleResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut ids: Vec<String> = Vec::new();
+    let mut catalogs: HashSet<String> = HashSet::new();
+    let mut entries: Vec<AuditEntry> = Vec::new();
+
+    for id in people.iter() {
+        let person =
+            models::Person::get_writable_for_user(&mut conn, &session.user.email, id).await?;
+        session.check_catalog(&person.catalog)?;
+        entries.push(
+            AuditEntry::new(
+                &person.catalog,
+                &session.user.email,
+                AuditAction::DeletePerson,
+                &person.id,
+            )
+            .change("name", Some(&person.name), None),
+        );
+        catalogs.insert(person.catalog);
+        ids.push(person.id);
+    }
 
-    let (stream, sender) = MediaViewStream::new();
+    models::Person::delete(&mut conn, &ids).await?;
+    AuditEntry::insert(&mut conn, &entries).await?;
 
-    tokio::spawn(user_catalog.catalog.stream_media(conn, sender));
+    let mut affected_searches = Vec::new();
+    for catalog in catalogs.iter() {
+        let mut searches = models::SavedSearch::list_for_catalog(&mut conn, catalog).await?;
+        searches.retain_mut(|search| search.query.references_people(&ids));
+        affected_searches.extend(searches);
+    }
 
-    Ok(HttpResponseBuilder::new(StatusCode::OK)
-        .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
-        .streaming(stream))
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
+    Ok(web::Json(DeletePeopleResponse { affected_searches }))
 }
 
-#[derive(Debug, Deserialize)]
-struct GetRecursiveMediaRequest {
-    #[serde(default = "default_true")]
-    recursive: bool,
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct SearchDetail {
+    name: String,
+    #[serde(default)]
+    shared: bool,
+    query: SearchQuery,
 }
 
-#[get("/album/{album_id}/media")]
-#[instrument(err, skip(app_state, session))]
-async fn get_album_media(
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct CreateSearchRequest {
+    catalog: String,
+    search: SearchDetail,
+}
+
+/// Writes the search to the database and immediately updates the media it
+/// matches. Queries that the database rejects are reported as invalid.
+async fn save_search(conn: &mut DbConnection<'_>, search: &models::SavedSearch) -> Result {
+    if search.name.is_empty() {
+        return Err(Error::InvalidData {
+            message: "Search name cannot be empty".to_string(),
+        });
+    }
+
+    models::SavedSearch::upsert(conn, slice::from_ref(search)).await?;
+
+    match search.update(conn).await {
+        Err(Error::SqlxError {
+            source: sqlx::Error::Database(e),
+        }) => Err(Error::InvalidData {
+            message: format!("Invalid search query: {e}"),
+        }),
+        result => result,
+    }
+}
+
+#[utoipa::path(responses((status = OK, body = models::SavedSearch), ApiErrorCode))]
+#[post("/search/create")]
+#[instrument(err, skip(app_state, session, request))]
+async fn create_search(
     app_state: web::Data<AppState>,
     session: Session,
-    album_id: web::Path<String>,
+    request: web::Json<CreateSearchRequest>,
+) -> ApiResult<web::Json<models::SavedSearch>> {
+    session.check_catalog(&request.catalog)?;
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &request.catalog, true)
+            .await?;
+
+    let search = models::SavedSearch {
+        id: short_id("S"),
+        catalog: user_catalog.catalog.id.clone(),
+        name: request.search.name.clone(),
+        shared: request.search.shared,
+        query: request.search.query.clone(),
+    };
+
+    save_search(&mut conn, &search).await?;
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: search.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(search))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct EditSearchRequest {
+    id: String,
+    search: SearchDetail,
+}
+
+#[utoipa::path(responses((status = OK, body = models::SavedSearch), ApiErrorCode))]
+#[post("/search/edit")]
+#[instrument(err, skip(app_state, session, request))]
+async fn edit_search(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<EditSearchRequest>,
+) -> ApiResult<web::Json<models::SavedSearch>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut search =
+        models::SavedSearch::get_writable_for_user(&mut conn, &session.user.email, &request.id)
+            .await?;
+    session.check_catalog(&search.catalog)?;
+
+    search.name.clone_from(&request.search.name);
+    search.shared = request.search.shared;
+    search.query = request.search.query.clone();
+
+    save_search(&mut conn, &search).await?;
+    conn.commit().await?;
+
+    app_state
+        .store
+        .queue_task(Task::UpdateSearches {
+            catalog: search.catalog.clone(),
+        })
+        .await;
+
+    Ok(web::Json(search))
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/search/delete")]
+#[instrument(err, skip(app_state, session, searches))]
+async fn delete_search(
+    app_state: web::Data<AppState>,
+    session: Session,
+    searches: web::Json<Vec<String>>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut ids: Vec<String> = Vec::new();
+    let mut catalogs: HashSet<String> = HashSet::new();
+
+    for id in searches.iter() {
+        let search =
+            models::SavedSearch::get_writable_for_user(&mut conn, &session.user.email, id).await?;
+        session.check_catalog(&search.catalog)?;
+        catalogs.insert(search.catalog);
+        ids.push(search.id);
+    }
+
+    models::SavedSearch::delete(&mut conn, &ids).await?;
+    conn.commit().await?;
+
+    for catalog in catalogs {
+        app_state
+            .store
+            .queue_task(Task::UpdateSearches { catalog })
+            .await;
+    }
+
+    Ok(web::Json(Default::default()))
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct AlbumListRequest {
+    #[serde(default = "default_true")]
+    recursive: bool,
+}
+
+#[utoipa::path(params(AlbumListRequest), responses((status = OK, body = AlbumWithCount), ApiErrorCode))]
+#[get("/album/{album_id}")]
+#[instrument(err, skip(app_state, session))]
+async fn get_album(
+    app_state: web::Data<AppState>,
+    session: Session,
+    album_id: web::Path<String>,
+    query: web::Query<AlbumListRequest>,
+) -> ApiResult<web::Json<AlbumWithCount>> {
+    let mut conn = app_state.store.connect().await?;
+    let album = models::Album::get_for_user_with_count(
+        &mut conn,
+        &session.user.email,
+        &album_id,
+        query.recursive,
+    )
+    .await?;
+    session.check_catalog(&album.album.catalog)?;
+
+    Ok(web::Json(album))
+}
+
+#[utoipa::path(security((), ("bearer" = [])), responses((status = OK, body = SavedSearchWithCount), ApiErrorCode))]
+#[get("/search/{search_id}")]
+#[instrument(err, skip(app_state, session))]
+async fn get_search(
+    app_state: web::Data<AppState>,
+    session: MaybeSession,
+    search_id: web::Path<String>,
+) -> ApiResult<web::Json<SavedSearchWithCount>> {
+    let email = session.session().map(|s| s.user.email.as_str());
+
+    let mut conn = app_state.store.connect().await?;
+    let search = models::SavedSearch::get_for_user_with_count(&mut conn, email, &search_id).await?;
+    session.check_catalog(&search.search.catalog)?;
+
+    Ok(web::Json(search))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+struct StorageDetail {
+    name: String,
+    access_key_id: Option<String>,
+    secret_access_key: Option<String>,
+    bucket: String,
+    region: String,
+    path: Option<String>,
+    endpoint: Option<String>,
+    public_url: Option<String>,
+}
+
+impl StorageDetail {
+    fn apply(&self, storage: &mut models::Storage) {
+        storage.name.clone_from(&self.name);
+        storage.bucket.clone_from(&self.bucket);
+        storage.region.clone_from(&self.region);
+        storage.path.clone_from(&self.path);
+        storage.endpoint.clone_from(&self.endpoint);
+        storage.public_url.clone_from(&self.public_url);
+
+        if let Some(ref access_key_id) = self.access_key_id {
+            storage.access_key_id.clone_from(access_key_id);
+        }
+        if let Some(ref secret_access_key) = self.secret_access_key {
+            storage.secret_access_key.clone_from(secret_access_key);
+        }
+    }
+}
+
+#[utoipa::path(responses((status = OK, body = models::Storage), ApiErrorCode))]
+#[post("/storage/create")]
+#[instrument(err, skip(app_state, session, request))]
+async fn create_storage(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<StorageDetail>,
+) -> ApiResult<web::Json<models::Storage>> {
+    let mut storage = models::Storage {
+        id: short_id("B"),
+        name: String::new(),
+        access_key_id: String::new(),
+        secret_access_key: String::new(),
+        bucket: String::new(),
+        region: String::new(),
+        path: None,
+        endpoint: None,
+        public_url: None,
+        _owner: session.user.email.clone(),
+    };
+    request.apply(&mut storage);
+
+    storage.verify(app_state.store.config()).await?;
+
+    let mut conn = app_state.store.connect().await?;
+    storage.create_or_update(&mut conn).await?;
+
+    Ok(web::Json(storage))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct EditStorageRequest {
+    id: String,
+    storage: StorageDetail,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Storage), ApiErrorCode))]
+#[post("/storage/edit")]
+#[instrument(err, skip(app_state, session, request))]
+async fn edit_storage(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<EditStorageRequest>,
+) -> ApiResult<web::Json<models::Storage>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let mut storage =
+        models::Storage::get_for_user(&mut conn, &session.user.email, &request.id).await?;
+
+    // Catalogs already stored here would lose their files.
+    let detail = &request.storage;
+    if detail.bucket != storage.bucket
+        || detail.region != storage.region
+        || detail.path != storage.path
+        || detail.endpoint != storage.endpoint
+    {
+        let catalogs = models::Storage::catalog_count(&mut conn, &storage.id).await?;
+        if catalogs > 0 {
+            return Err(ApiErrorCode::InvalidData(format!(
+                "Storage is still used by {catalogs} catalogs so its location cannot be changed"
+            )));
+        }
+    }
+
+    detail.apply(&mut storage);
+
+    storage.verify(app_state.store.config()).await?;
+
+    storage.create_or_update(&mut conn).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(storage))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct DeleteRequest {
+    id: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/storage/delete")]
+#[instrument(err, skip(app_state, session))]
+async fn delete_storage(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<DeleteRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let storage =
+        models::Storage::get_for_user(&mut conn, &session.user.email, &request.id).await?;
+
+    models::Storage::delete(&mut conn, &storage.id).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct CreateCatalogRequest {
+    storage: String,
+    name: String,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Catalog), ApiErrorCode))]
+#[post("/catalog/create")]
+#[instrument(err, skip(app_state, session, request))]
+async fn create_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<CreateCatalogRequest>,
+) -> ApiResult<web::Json<models::Catalog>> {
+    if request.name.is_empty() {
+        return Err(Error::InvalidData {
+            message: "Catalog name cannot be empty".to_string(),
+        }
+        .into());
+    }
+
+    let mut conn = app_state.store.connect().await?;
+    let storage =
+        models::Storage::get_for_user(&mut conn, &session.user.email, &request.storage).await?;
+
+    let catalog = models::Catalog {
+        id: short_id("C"),
+        name: request.name.clone(),
+        storage: storage.id,
+    };
+
+    catalog.create_or_update(&mut conn).await?;
+
+    Ok(web::Json(catalog))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct EditCatalogRequest {
+    id: String,
+    name: String,
+}
+
+#[utoipa::path(responses((status = OK, body = models::Catalog), ApiErrorCode))]
+#[post("/catalog/edit")]
+#[instrument(err, skip(app_state, session, request))]
+async fn edit_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<EditCatalogRequest>,
+) -> ApiResult<web::Json<models::Catalog>> {
+    if request.name.is_empty() {
+        return Err(Error::InvalidData {
+            message: "Catalog name cannot be empty".to_string(),
+        }
+        .into());
+    }
+
+    let mut conn = app_state.store.connect().await?;
+    let mut catalog =
+        models::Catalog::get_for_owner(&mut conn, &session.user.email, &request.id).await?;
+
+    catalog.name.clone_from(&request.name);
+    catalog.create_or_update(&mut conn).await?;
+
+    Ok(web::Json(catalog))
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/catalog/delete")]
+#[instrument(err, skip(app_state, session))]
+async fn delete_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<DeleteRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let catalog =
+        models::Catalog::get_for_owner(&mut conn, &session.user.email, &request.id).await?;
+
+    models::Catalog::delete(&mut conn, &catalog.id).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[utoipa::path(responses((status = OK, body = Vec<models::SharedCatalog>), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/shares")]
+#[instrument(err, skip(app_state, session))]
+async fn list_catalog_shares(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+) -> ApiResult<web::Json<Vec<models::SharedCatalog>>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let catalog =
+        models::Catalog::get_for_owner(&mut conn, &session.user.email, &catalog_id).await?;
+
+    Ok(web::Json(catalog.list_shares(&mut conn).await?))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct ShareCatalogRequest {
+    catalog: String,
+    email: String,
+    #[serde(default)]
+    writable: bool,
+}
+
+#[utoipa::path(responses((status = OK, body = models::SharedCatalog), ApiErrorCode))]
+#[post("/catalog/share")]
+#[instrument(err, skip(app_state, session, request))]
+async fn share_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<ShareCatalogRequest>,
+) -> ApiResult<web::Json<models::SharedCatalog>> {
+    let email = request.email.trim();
+    if !email.contains('@') {
+        return Err(Error::InvalidData {
+            message: format!("'{email}' is not a valid email address"),
+        }
+        .into());
+    }
+
+    if email.to_lowercase() == session.user.email.to_lowercase() {
+        return Err(Error::InvalidData {
+            message: "You cannot share a catalog with yourself".to_string(),
+        }
+        .into());
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let catalog =
+        models::Catalog::get_for_owner(&mut conn, &session.user.email, &request.catalog).await?;
+
+    let (shared, invited) = catalog.share(&mut conn, email, request.writable).await?;
+    conn.commit().await?;
+
+    if invited {
+        let inviter = session
+            .user
+            .fullname
+            .as_deref()
+            .unwrap_or(&session.user.email);
+
+        send_messages(
+            app_state.store.config(),
+            &[CatalogInvitation {
+                base_url: app_state.store.config().base_url.to_string(),
+                catalog: &catalog,
+                inviter,
+                email,
+            }],
+        )
+        .await;
+    }
+
+    Ok(web::Json(shared))
+}
+
+#[derive(Deserialize, Clone, Debug, ToSchema)]
+struct UnshareCatalogRequest {
+    catalog: String,
+    email: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/catalog/unshare")]
+#[instrument(err, skip(app_state, session, request))]
+async fn unshare_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<UnshareCatalogRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let catalog =
+        models::Catalog::get_for_owner(&mut conn, &session.user.email, &request.catalog).await?;
+
+    catalog.unshare(&mut conn, request.email.trim()).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[utoipa::path(responses((status = OK, body = UserCatalogWithCount), ApiErrorCode))]
+#[get("/catalog/{catalog_id}")]
+#[instrument(err, skip(app_state, session))]
+async fn get_catalog(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+) -> ApiResult<web::Json<UserCatalogWithCount>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user_with_count(&mut conn, &session.user.email, &catalog_id)
+            .await?;
+
+    Ok(web::Json(user_catalog))
+}
+
+/// Lists the media items in the catalog that have identical files.
+#[utoipa::path(responses((status = OK, body = Vec<models::DuplicateMedia>), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/duplicates")]
+#[instrument(err, skip(app_state, session))]
+async fn list_catalog_duplicates(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+) -> ApiResult<web::Json<Vec<models::DuplicateMedia>>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+
+    Ok(web::Json(
+        models::MediaFile::list_duplicates(&mut conn, &user_catalog.catalog.id).await?,
+    ))
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+struct CatalogTrash {
+    media: Vec<models::TrashedMedia>,
+    albums: Vec<models::TrashedAlbum>,
+}
+
+/// Lists the media and albums in the catalog's trash. Items are removed for
+/// good once they have been in the trash for longer than the retention period.
+#[utoipa::path(responses((status = OK, body = CatalogTrash), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/trash")]
+#[instrument(err, skip(app_state, session))]
+async fn list_catalog_trash(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+) -> ApiResult<web::Json<CatalogTrash>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+
+    Ok(web::Json(CatalogTrash {
+        media: models::MediaItem::list_trash(&mut conn, &user_catalog.catalog.id).await?,
+        albums: models::TrashedAlbum::list(&mut conn, &user_catalog.catalog.id).await?,
+    }))
+}
+
+/// Lists the pairs of media items in the catalog that look similar and so are
+/// likely to be duplicates, closest first.
+#[utoipa::path(params(SimilarityOptions), responses((status = OK, body = Vec<models::SimilarMediaPair>), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/similar")]
+#[instrument(err, skip(app_state, session))]
+async fn list_catalog_similar(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+    options: web::Query<SimilarityOptions>,
+) -> ApiResult<web::Json<Vec<models::SimilarMediaPair>>> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+
+    Ok(web::Json(
+        models::MediaFile::list_similar_pairs(
+            &mut conn,
+            &user_catalog.catalog.id,
+            options.distance(),
+        )
+        .await?,
+    ))
+}
+
+/// Streams the catalog's media. Pass the cursor from the end of a previous
+/// response as `since` to only receive the changes after it.
+#[utoipa::path(params(MediaOptions, MediaPage), responses((status = OK, content_type = "application/x-ndjson", body = models::MediaStreamItem), ApiErrorCode))]
+#[get("/catalog/{catalog_id}/media")]
+#[instrument(err, skip(app_state, session))]
+async fn get_catalog_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    catalog_id: web::Path<String>,
+    options: web::Query<MediaOptions>,
+    page: web::Query<MediaPage>,
+) -> ApiResult<HttpResponse> {
+    session.check_catalog(&catalog_id)?;
+
+    let mut conn = app_state.store.connect().await?;
+    let user_catalog =
+        models::Catalog::get_for_user(&mut conn, &session.user.email, &catalog_id, false).await?;
+
+    let (stream, sender) = MediaViewStream::new();
+
+    tokio::spawn(
+        user_catalog
+            .catalog
+            .stream_media(conn, options.since, page.into_inner(), sender),
+    );
+
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
+        .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
+        .streaming(stream))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+struct GetRecursiveMediaRequest {
+    #[serde(default = "default_true")]
+    recursive: bool,
+    since: Option<DateTime<Utc>>,
+}
+
+/// Streams the album's media. Pass the `since` time from the end of a
+/// previous response to only receive the changes after it.
+#[utoipa::path(params(GetRecursiveMediaRequest, MediaPage), responses((status = OK, content_type = "application/x-ndjson", body = models::MediaStreamItem), ApiErrorCode))]
+#[get("/album/{album_id}/media")]
+#[instrument(err, skip(app_state, session))]
+async fn get_album_media(
+    app_state: web::Data<AppState>,
+    session: Session,
+    album_id: web::Path<String>,
     query: web::Query<GetRecursiveMediaRequest>,
+    page: web::Query<MediaPage>,
 ) -> ApiResult<HttpResponse> {
     let mut conn = app_state.store.connect().await?;
     let album = models::Album::get_for_user(&mut conn, &session.user.email, &album_id).await?;
+    session.check_catalog(&album.catalog)?;
+
+    let (stream, sender) = MediaViewStream::new();
+
+    tokio::spawn(album.stream_media(
+        conn,
+        query.recursive,
+        query.since,
+        page.into_inner(),
+        sender,
+    ));
+
+    Ok(HttpResponseBuilder::new(StatusCode::OK)
+        .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
+        .streaming(stream))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct ShareAlbumRequest {
+    album: String,
+    #[serde(default)]
+    recursive: bool,
+    expiry: Option<DateTime<Utc>>,
+    password: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AlbumShareResponse {
+    #[serde(flatten)]
+    share: models::AlbumShare,
+    protected: bool,
+}
+
+impl From<models::AlbumShare> for AlbumShareResponse {
+    fn from(share: models::AlbumShare) -> Self {
+        AlbumShareResponse {
+            protected: share.protected(),
+            share,
+        }
+    }
+}
+
+#[utoipa::path(responses((status = OK, body = AlbumShareResponse), ApiErrorCode))]
+#[post("/album/share")]
+#[instrument(err, skip(app_state, session, request), fields(album = request.album))]
+async fn share_album(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<ShareAlbumRequest>,
+) -> ApiResult<web::Json<AlbumShareResponse>> {
+    if request.expiry.is_some_and(|expiry| expiry <= Utc::now()) {
+        return Err(ApiErrorCode::InvalidData(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let album =
+        models::Album::get_writable_for_user(&mut conn, &session.user.email, &request.album)
+            .await?;
+    session.check_catalog(&album.catalog)?;
+
+    let share = models::AlbumShare::create(
+        &mut conn,
+        &album.id,
+        request.recursive,
+        request.expiry,
+        request.password.as_deref(),
+    )
+    .await?;
+    conn.commit().await?;
+
+    Ok(web::Json(share.into()))
+}
+
+#[utoipa::path(responses((status = OK, body = Vec<AlbumShareResponse>), ApiErrorCode))]
+#[get("/album/{album_id}/shares")]
+#[instrument(err, skip(app_state, session))]
+async fn list_album_shares(
+    app_state: web::Data<AppState>,
+    session: Session,
+    album_id: web::Path<String>,
+) -> ApiResult<web::Json<Vec<AlbumShareResponse>>> {
+    let mut conn = app_state.store.connect().await?;
+    let album =
+        models::Album::get_writable_for_user(&mut conn, &session.user.email, &album_id).await?;
+    session.check_catalog(&album.catalog)?;
+
+    let shares = models::AlbumShare::list_for_album(&mut conn, &album.id).await?;
+
+    Ok(web::Json(shares.into_iter().map(Into::into).collect()))
+}
+
+#[derive(Deserialize, Debug, ToSchema)]
+struct UnshareAlbumRequest {
+    id: String,
+}
+
+#[utoipa::path(responses((status = OK, body = ApiResponse), ApiErrorCode))]
+#[post("/album/unshare")]
+#[instrument(err, skip(app_state, session))]
+async fn unshare_album(
+    app_state: web::Data<AppState>,
+    session: Session,
+    request: web::Json<UnshareAlbumRequest>,
+) -> ApiResult<web::Json<ApiResponse>> {
+    let mut conn = app_state.store.isolated(Isolation::Committed).await?;
+    let share = models::AlbumShare::get(&mut conn, &request.id).await?;
+    let album =
+        models::Album::get_writable_for_user(&mut conn, &session.user.email, &share.album).await?;
+    session.check_catalog(&album.catalog)?;
+
+    models::AlbumShare::delete(&mut conn, &share.id).await?;
+    conn.commit().await?;
+
+    Ok(web::Json(Default::default()))
+}
+
+#[derive(Deserialize, ToSchema)]
+struct UnlockShareRequest {
+    id: String,
+    #[serde(default)]
+    password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UnlockShareResponse {
+    access: String,
+}
+
+/// Exchanges a share link, along with its password if it is protected, for the
+/// credential used to access the shared media.
+#[utoipa::path(security(()), responses((status = OK, body = UnlockShareResponse), ApiErrorCode))]
+#[post("/share/unlock")]
+#[instrument(err, skip(app_state, request))]
+async fn unlock_share(
+    app_state: web::Data<AppState>,
+    request: web::Json<UnlockShareRequest>,
+) -> ApiResult<web::Json<UnlockShareResponse>> {
+    let mut conn = app_state.store.connect().await?;
+
+    match models::AlbumShare::unlock(&mut conn, &request.id, &request.password).await {
+        Ok(access) => Ok(web::Json(UnlockShareResponse { access })),
+        Err(Error::NotFound) => Err(ApiErrorCode::NotLoggedIn),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+struct SharedAlbumResponse {
+    album: models::Album,
+    recursive: bool,
+    expiry: Option<DateTime<Utc>>,
+}
+
+#[utoipa::path(security(()), responses((status = OK, body = SharedAlbumResponse), ApiErrorCode))]
+#[get("/share/{access}")]
+#[instrument(err, skip(app_state, access))]
+async fn get_shared_album(
+    app_state: web::Data<AppState>,
+    access: web::Path<String>,
+) -> ApiResult<web::Json<SharedAlbumResponse>> {
+    let mut conn = app_state.store.connect().await?;
+    let share = models::AlbumShare::get_for_access(&mut conn, &access).await?;
+    let album = share.get_album(&mut conn).await?;
+
+    Ok(web::Json(SharedAlbumResponse {
+        album,
+        recursive: share.recursive,
+        expiry: share.expiry,
+    }))
+}
+
+#[utoipa::path(params(MediaPage), security(()), responses((status = OK, content_type = "application/x-ndjson", body = models::MediaStreamItem), ApiErrorCode))]
+#[get("/share/{access}/media")]
+#[instrument(err, skip(app_state, access))]
+async fn get_shared_album_media(
+    app_state: web::Data<AppState>,
+    access: web::Path<String>,
+    page: web::Query<MediaPage>,
+) -> ApiResult<HttpResponse> {
+    let mut conn = app_state.store.connect().await?;
+    let share = models::AlbumShare::get_for_access(&mut conn, &access).await?;
+    let album = share.get_album(&mut conn).await?;
 
     let (stream, sender) = MediaViewStream::new();
 
-    tokio::spawn(album.stream_media(conn, query.recursive, sender));
+    tokio::spawn(album.stream_media(conn, share.recursive, None, page.into_inner(), sender));
 
     Ok(HttpResponseBuilder::new(StatusCode::OK)
         .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
         .streaming(stream))
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, IntoParams)]
 struct MediaOptions {
     since: Option<DateTime<Utc>>,
 }
 
+#[utoipa::path(params(MediaOptions, MediaPage), security((), ("bearer" = [])), responses((status = OK, content_type = "application/x-ndjson", body = models::MediaStreamItem), ApiErrorCode))]
 #[get("/search/{search_id}/media")]
 #[instrument(err, skip(app_state, session))]
 async fn get_search_media(
@@ -310,27 +1476,30 @@ async fn get_search_media(
     session: MaybeSession,
     search_id: web::Path<String>,
     options: web::Query<MediaOptions>,
+    page: web::Query<MediaPage>,
 ) -> ApiResult<HttpResponse> {
     let email = session.session().map(|s| s.user.email.as_str());
 
     let mut conn = app_state.store.connect().await?;
     let search = models::SavedSearch::get_for_user(&mut conn, email, &search_id).await?;
+    session.check_catalog(&search.catalog)?;
 
     let (stream, sender) = MediaViewStream::new();
 
-    tokio::spawn(search.stream_media(conn, options.since, sender));
+    tokio::spawn(search.stream_media(conn, options.since, page.into_inner(), sender));
 
     Ok(HttpResponseBuilder::new(StatusCode::OK)
         .append_header((header::CONTENT_TYPE, "application/x-ndjson"))
         .streaming(stream))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct SubscribeRequest {
     search: String,
     email: String,
 }
 
+#[utoipa::path(security(()), responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/subscribe")]
 #[instrument(err, skip(app_state))]
 async fn subscribe(
@@ -346,11 +1515,12 @@ async fn subscribe(
     Ok(web::Json(Default::default()))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct VerifyRequest {
     token: String,
 }
 
+#[utoipa::path(security(()), responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/verify")]
 #[instrument(err, skip(app_state))]
 async fn verify_subscription(
@@ -367,12 +1537,13 @@ async fn verify_subscription(
     Ok(web::Json(Default::default()))
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct UnsubscribeRequest {
     email: String,
     search: Option<String>,
 }
 
+#[utoipa::path(security(()), responses((status = OK, body = ApiResponse), ApiErrorCode))]
 #[post("/unsubscribe")]
 #[instrument(err, skip(app_state))]
 async fn unsubscribe(
@@ -386,7 +1557,7 @@ async fn unsubscribe(
     Ok(web::Json(Default::default()))
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct SourceRequest {
     id: Option<String>,
     name: String,
@@ -394,6 +1565,7 @@ struct SourceRequest {
     source_type: SourceType,
 }
 
+#[utoipa::path(responses((status = OK, body = models::Source), ApiErrorCode))]
 #[post("/source")]
 #[instrument(err, skip(app_state, request, _session))]
 async fn set_source(
@@ -413,6 +1585,7 @@ async fn set_source(
     }
 }
 
+#[utoipa::path(responses((status = OK, body = Vec<String>), ApiErrorCode))]
 #[get("/source/{id}")]
 #[instrument(err, skip(app_state, _session))]
 async fn list_source(
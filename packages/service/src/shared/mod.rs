@@ -1,11 +1,18 @@
 //! Shared functionality for the Pixelbin server
 pub(crate) mod json;
 pub(crate) mod mime;
+pub(crate) mod password;
+pub(crate) mod totp;
 
 use std::{io::ErrorKind, path::Path};
 
+use data_encoding::HEXLOWER;
 use nano_id::base62;
-use tokio::fs::metadata;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{metadata, File},
+    io::AsyncReadExt,
+};
 use tracing::{error, Instrument, Span};
 
 use crate::{Error, Result};
@@ -20,6 +27,12 @@ pub(crate) fn short_id(prefix: &str) -> String {
     format!("{prefix}:{}", base62::<10>())
 }
 
+pub(crate) fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut bytes = [0; N];
+    getrandom::getrandom(&mut bytes).expect("system random number generator failed");
+    bytes
+}
+
 pub(crate) async fn spawn_blocking<F, R>(span: Span, f: F) -> R
 where
     F: FnOnce() -> R + Send + 'static,
@@ -52,6 +65,24 @@ pub(crate) async fn file_exists(path: &Path) -> Result<bool> {
     }
 }
 
+/// Returns the hex encoded SHA-256 of the file.
+pub(crate) async fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buffer[..read]);
+    }
+
+    Ok(HEXLOWER.encode(&hasher.finalize()))
+}
+
 pub(crate) fn record_result<T>(span: &Span, result: &Result<T>) {
     match result {
         Ok(_) => {
@@ -0,0 +1,139 @@
+//! Password hashing. New hashes use Argon2id but older bcrypt hashes are still
+//! accepted. The format of a stored hash is detected from its prefix.
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Algorithm, Argon2, Params, Version,
+};
+use pixelbin_shared::Argon2Config;
+use tracing::{span, Level};
+
+use crate::{
+    shared::{random_bytes, spawn_blocking},
+    Config, Error, Result,
+};
+
+const ARGON2_PREFIX: &str = "$argon2";
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+fn hasher(config: &Argon2Config) -> password_hash::Result<Argon2<'static>> {
+    let params = Params::new(
+        config.memory_cost,
+        config.time_cost,
+        config.parallelism,
+        None,
+    )?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hashes a password. This is slow so should not be run on an async thread.
+pub(crate) fn hash(config: &Argon2Config, password: &str) -> password_hash::Result<String> {
+    let salt = SaltString::encode_b64(&random_bytes::<16>())?;
+
+    Ok(hasher(config)?
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Checks a password against a stored hash. This is slow so should not be run
+/// on an async thread.
+pub(crate) fn verify(password: &str, hash: &str) -> bool {
+    if hash.starts_with(ARGON2_PREFIX) {
+        // The parameters used come from the hash itself.
+        PasswordHash::new(hash)
+            .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+            .is_ok()
+    } else {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    }
+}
+
+/// Whether a stored hash should be replaced by one using the current
+/// algorithm and parameters.
+pub(crate) fn needs_rehash(config: &Argon2Config, hash: &str) -> bool {
+    let Ok(hash) = PasswordHash::new(hash) else {
+        return true;
+    };
+
+    if hash.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+
+    match Params::try_from(&hash) {
+        Ok(params) => {
+            params.m_cost() != config.memory_cost
+                || params.t_cost() != config.time_cost
+                || params.p_cost() != config.parallelism
+        }
+        Err(_) => true,
+    }
+}
+
+/// Hashes a new password, rejecting it if it is too short.
+pub(crate) async fn hash_password(config: &Config, password: &str) -> Result<String> {
+    if password.len() < MIN_PASSWORD_LENGTH {
+        return Err(Error::InvalidData {
+            message: format!("Passwords must be at least {MIN_PASSWORD_LENGTH} characters"),
+        });
+    }
+
+    rehash(config, password).await
+}
+
+/// Hashes an existing password with the current settings.
+pub(crate) async fn rehash(config: &Config, password: &str) -> Result<String> {
+    let config = config.argon2.clone();
+    let password = password.to_owned();
+    spawn_blocking(span!(Level::TRACE, "hash password"), move || {
+        hash(&config, &password)
+    })
+    .await
+    .map_err(|e| Error::Unknown {
+        message: format!("Failed to hash password: {e}"),
+    })
+}
+
+/// Checks a password against a stored hash.
+pub(crate) async fn check(password: &str, password_hash: &str) -> bool {
+    let password = password.to_owned();
+    let password_hash = password_hash.to_owned();
+    spawn_blocking(span!(Level::TRACE, "verify password"), move || {
+        verify(&password, &password_hash)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use pixelbin_shared::Argon2Config;
+
+    use super::{hash, needs_rehash, verify};
+
+    #[test]
+    fn hashes() {
+        let config = Argon2Config {
+            memory_cost: 1024,
+            time_cost: 1,
+            parallelism: 1,
+        };
+
+        let bcrypt = bcrypt::hash("password123", 4).unwrap();
+        assert!(verify("password123", &bcrypt));
+        assert!(!verify("password124", &bcrypt));
+        assert!(needs_rehash(&config, &bcrypt));
+
+        let argon2 = hash(&config, "password123").unwrap();
+        assert!(argon2.starts_with("$argon2id$"));
+        assert!(verify("password123", &argon2));
+        assert!(!verify("password124", &argon2));
+        assert!(!needs_rehash(&config, &argon2));
+
+        let stronger = Argon2Config {
+            time_cost: 2,
+            ..config
+        };
+        assert!(needs_rehash(&stronger, &argon2));
+
+        assert!(!verify("password123", "garbage"));
+    }
+}
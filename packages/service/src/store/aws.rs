@@ -4,6 +4,7 @@ use async_trait::async_trait;
 use aws_config::{AppName, BehaviorVersion};
 use aws_sdk_s3::{
     config::{Credentials, Region},
+    error::{DisplayErrorContext, ProvideErrorMetadata, SdkError},
     presigning::PresigningConfig,
     primitives::ByteStream,
     types::{Delete, ObjectIdentifier},
@@ -18,6 +19,7 @@ use tokio::{
 use tracing::{debug, instrument, trace};
 
 use crate::{
+    shared::long_id,
     store::{
         file::FileStore,
         models::Storage,
@@ -34,6 +36,36 @@ fn remote_path<P: PathLike>(path: &P) -> String {
     path.path_parts().join("/")
 }
 
+/// Converts a failed request made while verifying storage into an error
+/// describing the likely misconfiguration.
+fn verify_error<E, R>(storage: &Storage, action: &str, error: SdkError<E, R>) -> Error
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: fmt::Debug,
+{
+    let reason = match (&error, error.code()) {
+        (SdkError::DispatchFailure(_) | SdkError::TimeoutError(_), _) => match storage.endpoint {
+            Some(ref endpoint) => format!("could not connect to {endpoint}"),
+            None => format!("could not connect to the S3 service in {}", storage.region),
+        },
+        (_, Some("NoSuchBucket")) => format!("bucket {} does not exist", storage.bucket),
+        (_, Some("PermanentRedirect" | "AuthorizationHeaderMalformed" | "IncorrectEndpoint")) => {
+            format!(
+                "bucket {} is not in region {}",
+                storage.bucket, storage.region
+            )
+        }
+        (_, Some("InvalidAccessKeyId")) => "the access key ID is not valid".to_string(),
+        (_, Some("SignatureDoesNotMatch")) => "the secret access key is not valid".to_string(),
+        (_, Some("AccessDenied")) => "access was denied".to_string(),
+        _ => DisplayErrorContext(&error).to_string(),
+    };
+
+    Error::InvalidData {
+        message: format!("Failed to {action}: {reason}"),
+    }
+}
+
 pub(crate) struct AwsClient {
     client: Client,
     bucket: String,
@@ -85,6 +117,59 @@ impl AwsClient {
         })
     }
 
+    /// Checks that the storage can be written to, listed and deleted from by
+    /// creating and then removing a small test file.
+    #[instrument(skip_all, fields(storage = storage.id), err)]
+    pub(crate) async fn verify(&self, storage: &Storage) -> Result {
+        let name = format!(".pixelbin-verify-{}", &long_id("")[1..]);
+        let key = match &self.path {
+            Some(path) => format!("{path}/{name}"),
+            None => name,
+        };
+
+        if !self.testing {
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .content_type(mime::TEXT_PLAIN.as_ref())
+                .body(ByteStream::from_static(b"pixelbin"))
+                .send()
+                .await
+                .map_err(|e| verify_error(storage, "write a test file", e))?;
+        }
+
+        let listed = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&key)
+            .send()
+            .await
+            .map_err(|e| verify_error(storage, "list files", e))?;
+
+        if self.testing {
+            debug!("Not writing test file in testing mode.");
+            return Ok(());
+        }
+
+        if !listed.contents().iter().any(|o| o.key() == Some(&key)) {
+            return Err(Error::InvalidData {
+                message: "Failed to list files: the test file was not listed".to_string(),
+            });
+        }
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| verify_error(storage, "delete a test file", e))?;
+
+        Ok(())
+    }
+
     pub(crate) async fn file_uri(
         &self,
         path: &FilePath,
@@ -38,6 +38,47 @@ macro_rules! from_row {
             created: $row.created,
             last_login: $row.last_login,
             verified: $row.verified,
+            disabled: $row.disabled,
+            totp_secret: $row.totp_secret,
+            totp: $row.totp,
+        }
+    };
+    (AlbumShare($row:ident)) => {
+        crate::store::db::models::AlbumShare {
+            id: $row.id,
+            access: $row.access,
+            album: $row.album,
+            recursive: $row.recursive,
+            password: $row.password,
+            expiry: $row.expiry,
+            created: $row.created,
+        }
+    };
+    (SharedCatalog($row:ident)) => {
+        crate::store::db::models::SharedCatalog {
+            user: $row.user,
+            catalog: $row.catalog,
+            writable: $row.writable,
+        }
+    };
+    (AuthSession($row:ident)) => {
+        crate::store::db::models::AuthSession {
+            id: $row.id,
+            created: $row.created,
+            last_used: $row.last_used,
+            expiry: $row.expiry,
+            client_ip: $row.client_ip,
+            user_agent: $row.user_agent,
+        }
+    };
+    (ApiToken($row:ident)) => {
+        crate::store::db::models::ApiToken {
+            id: $row.id,
+            name: $row.name,
+            scope: crate::store::db::models::TokenScope::decode(&$row.scope)?,
+            catalog: $row.catalog,
+            created: $row.created,
+            last_used: $row.last_used,
         }
     };
     (Person($row:ident)) => {
@@ -176,6 +217,8 @@ macro_rules! from_row {
             uploaded: $row.uploaded,
             file_name: $row.file_name.clone(),
             file_size: $row.file_size,
+            sha256: $row.sha256.clone(),
+            phash: $row.phash,
             mimetype: crate::store::db::functions::from_mime(&$row.mimetype)?,
             width: $row.width,
             height: $row.height,
@@ -194,6 +237,8 @@ macro_rules! from_row {
             $row.media_file_uploaded,
             $row.media_file_file_name,
             $row.media_file_file_size,
+            $row.media_file_sha256,
+            $row.media_file_phash,
             $row.media_file_mimetype,
             $row.media_file_width,
             $row.media_file_height,
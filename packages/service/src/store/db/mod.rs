@@ -1,6 +1,7 @@
 pub(crate) mod functions;
 mod internal;
 pub(crate) mod models;
+pub(crate) mod page;
 pub(crate) mod search;
 
 use std::{fmt, mem, time::Duration};
@@ -12,6 +13,7 @@ use sqlx::{
     Result as SqlxResult, Transaction,
 };
 use tracing::{error, info, instrument, trace, warn, Span};
+use utoipa::ToSchema;
 
 use crate::{
     store::{db::internal::Connection, locks::Locks, StoreInner},
@@ -21,13 +23,26 @@ use crate::{
 
 pub(crate) type SqlxDatabase = sqlx::Postgres;
 
-#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum MediaAccess {
     WritableCatalog,
     ReadableCatalog,
     PublicSearch,
     PublicMedia,
+    /// Accessed through a share link for an album.
+    SharedAlbum,
+}
+
+/// The ways a request can prove it is allowed to read a media file.
+#[derive(Default, Debug, Clone, Copy)]
+pub(crate) struct MediaCredentials<'a> {
+    /// The logged in user.
+    pub(crate) email: Option<&'a str>,
+    /// The access credential for an album share.
+    pub(crate) share: Option<&'a str>,
+    /// The request carried a valid signature for the file being requested.
+    pub(crate) signed: bool,
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq)]
@@ -339,6 +354,36 @@ impl<'conn> DbConnection<'conn> {
         let catalogs = models::Catalog::list(self).await?;
         Ok(catalogs.into_iter().map(|c| c.id).collect())
     }
+
+    /// Creates a verified user.
+    pub async fn create_user(
+        &mut self,
+        email: &str,
+        password: &str,
+        fullname: Option<&str>,
+        administrator: bool,
+    ) -> Result {
+        models::User::create(self, email, password, fullname, administrator, true).await?;
+        Ok(())
+    }
+
+    pub async fn set_user_password(&mut self, email: &str, password: &str) -> Result {
+        models::User::set_password(self, email, password, None).await
+    }
+
+    pub async fn set_user_disabled(&mut self, email: &str, disabled: bool) -> Result {
+        models::User::set_disabled(self, email, disabled).await?;
+        Ok(())
+    }
+
+    pub async fn set_user_administrator(&mut self, email: &str, administrator: bool) -> Result {
+        models::User::set_administrator(self, email, administrator).await?;
+        Ok(())
+    }
+
+    pub async fn disable_user_totp(&mut self, email: &str) -> Result {
+        models::User::disable_totp(self, email).await
+    }
 }
 
 pub(crate) trait AsDb<'conn> {
@@ -1,7 +1,9 @@
 use std::{
     cmp::{max, min},
-    collections::{HashMap, HashSet},
-    fmt, result,
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    path::PathBuf,
+    result,
     str::FromStr,
     task::Poll,
 };
@@ -24,18 +26,20 @@ use sqlx::{
     Result as SqlxResult, Row,
 };
 use tokio::sync::mpsc::{channel, Receiver, Sender};
-use tracing::{error, instrument, span, Level};
+use tracing::{error, instrument};
+use utoipa::ToSchema;
 
 use crate::{
     mail::{send_messages, Subscribed, SubscriptionRequest},
     metadata::{alternates_for_media_file, lookup_timezone, media_datetime, Alternate},
-    shared::{long_id, short_id, spawn_blocking},
+    shared::{long_id, password, short_id, totp},
     store::{
         aws::AwsClient,
         db::{
             functions::{from_mime, from_row},
+            page::MediaPage,
             search::{Filterable, SearchQuery},
-            AsDb, MediaAccess,
+            AsDb, MediaAccess, MediaCredentials,
         },
         file::FileStore,
         models,
@@ -45,7 +49,7 @@ use crate::{
     Config, Error, Result, Task,
 };
 
-const TOKEN_EXPIRY_DAYS: i64 = 90;
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
 
 pub(crate) struct Batch<'a, T> {
     slice: &'a [T],
@@ -82,10 +86,25 @@ pub(crate) fn batch<T>(slice: &[T], count: usize) -> Batch<T> {
     Batch::new(slice, count)
 }
 
+/// A line in a streamed media response.
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MediaStreamItem {
+    Media(Vec<MediaView>),
+    /// The media deleted since the requested time.
+    Deleted(Vec<String>),
+    /// The media removed from the album since the requested time.
+    Removed(Vec<String>),
+    /// Passed as `since` to fetch the changes after this response.
+    Since(DateTime<Utc>),
+    /// Passed as `cursor` to fetch the next page, null for the last page.
+    Cursor(Option<String>),
+}
+
 #[pin_project]
 pub(crate) struct MediaViewStream {
     is_done: bool,
-    receiver: Receiver<Option<Result<Vec<models::MediaView>>>>,
+    receiver: Receiver<Option<Result<MediaStreamItem>>>,
 }
 
 impl MediaViewStream {
@@ -117,10 +136,8 @@ impl Stream for MediaViewStream {
 
         match this.receiver.poll_recv(cx) {
             Poll::Ready(Some(item)) => match item {
-                Some(Ok(media_view)) => match to_string(&media_view) {
-                    Ok(st) => {
-                        Poll::Ready(Some(Ok(Bytes::from(format!("{{ \"media\": {} }}\n", st)))))
-                    }
+                Some(Ok(item)) => match to_string(&item) {
+                    Ok(st) => Poll::Ready(Some(Ok(Bytes::from(format!("{st}\n"))))),
                     Err(e) => {
                         *this.is_done = true;
                         Poll::Ready(Some(Ok(Bytes::from(format!("{{ \"error\": {} }}\n", e)))))
@@ -139,38 +156,97 @@ impl Stream for MediaViewStream {
 }
 
 pub(crate) struct MediaViewSender {
-    sender: Sender<Option<Result<Vec<models::MediaView>>>>,
+    sender: Sender<Option<Result<MediaStreamItem>>>,
 }
 
 impl MediaViewSender {
+    async fn send_item(&self, item: MediaStreamItem) {
+        self.sender.send(Some(Ok(item))).await.ignore();
+    }
+
     async fn send_media_views(&self, mut media_views: Vec<MediaView>) {
         media_views
             .iter_mut()
             .for_each(|mv| mv.amend_for_access(MediaAccess::PublicMedia));
-        self.sender.send(Some(Ok(media_views))).await.ignore();
+        self.send_item(MediaStreamItem::Media(media_views)).await;
     }
 
-    pub(crate) async fn send_stream<S>(self, stream: S)
+    /// Sends a page of media from the stream. Returns the cursor for the next
+    /// page if there is one or an error if the stream failed.
+    async fn send_media<S>(
+        &self,
+        mut stream: S,
+        page: &MediaPage,
+    ) -> result::Result<Option<String>, ()>
     where
         S: Stream<Item = SqlxResult<MediaView>> + Unpin,
     {
-        let mut chunked = stream.try_ready_chunks(50);
+        let limit = page.limit();
+        let mut last = None;
+
+        let mut chunked = (&mut stream)
+            .take(limit.unwrap_or(usize::MAX))
+            .try_ready_chunks(50);
         loop {
             match chunked.next().await {
                 Some(Ok(media_views)) => {
+                    last = media_views.last().map(|media| page.cursor_after(media));
                     self.send_media_views(media_views).await;
                 }
                 Some(Err(chunk_error)) => {
                     self.send_media_views(chunk_error.0).await;
                     self.send_error(chunk_error.1.into()).await;
-                    break;
-                }
-                None => {
-                    self.sender.send(None).await.ignore();
-                    break;
+                    return Err(());
                 }
+                None => break,
+            }
+        }
+
+        if limit.is_none() {
+            return Ok(None);
+        }
+
+        match stream.next().await {
+            Some(Ok(_)) => Ok(last.map(|cursor| cursor.to_string())),
+            Some(Err(e)) => {
+                self.send_error(e.into()).await;
+                Err(())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Sends a page of media from the stream followed by the media deleted
+    /// and the cursors for the next request.
+    pub(crate) async fn send_stream<S>(
+        self,
+        stream: S,
+        page: &MediaPage,
+        changes: Option<MediaChanges>,
+    ) where
+        S: Stream<Item = SqlxResult<MediaView>> + Unpin,
+    {
+        let Ok(next) = self.send_media(stream, page).await else {
+            return;
+        };
+
+        if let Some(changes) = changes {
+            if !changes.deleted.is_empty() {
+                self.send_item(MediaStreamItem::Deleted(changes.deleted))
+                    .await;
+            }
+            if !changes.removed.is_empty() {
+                self.send_item(MediaStreamItem::Removed(changes.removed))
+                    .await;
             }
+            self.send_item(MediaStreamItem::Since(changes.cursor)).await;
         }
+
+        if page.limit().is_some() {
+            self.send_item(MediaStreamItem::Cursor(next)).await;
+        }
+
+        self.sender.send(None).await.ignore();
     }
 
     async fn send_error(&self, error: Error) {
@@ -178,7 +254,106 @@ impl MediaViewSender {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+/// The point in time that a change feed was read at along with the media
+/// deleted, or removed from an album, since the previous read.
+pub(crate) struct MediaChanges {
+    cursor: DateTime<Utc>,
+    deleted: Vec<String>,
+    removed: Vec<String>,
+}
+
+impl MediaChanges {
+    /// Changes are only sent with the first page of a stream so that later
+    /// pages return `None`.
+    pub(crate) async fn since(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+        since: Option<DateTime<Utc>>,
+        page: &MediaPage,
+    ) -> Result<Option<Self>> {
+        if !page.is_first() {
+            return Ok(None);
+        }
+
+        // Changes are stamped with the start of the transaction that made them
+        // so a transaction that is still open may yet commit changes from
+        // before now. Starting the next feed from the oldest open transaction
+        // means those are not missed.
+        let cursor = sqlx::query_scalar!(
+            r#"
+            SELECT LEAST(CURRENT_TIMESTAMP, MIN("xact_start")) AS "cursor!"
+            FROM "pg_stat_activity"
+            WHERE "backend_xid" IS NOT NULL
+            "#
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        let deleted = match since {
+            Some(since) => {
+                sqlx::query_scalar!(
+                    r#"
+                    SELECT "id"
+                    FROM "media_tombstone"
+                    WHERE "catalog"=$1 AND "deleted" >= $2
+                    "#,
+                    catalog,
+                    since
+                )
+                .fetch_all(conn)
+                .await?
+            }
+            None => Vec::new(),
+        };
+
+        Ok(Some(Self {
+            cursor,
+            deleted,
+            removed: Vec::new(),
+        }))
+    }
+
+    /// Includes the media removed from the album, or for a recursive feed
+    /// from the album and its descendents, that is no longer in it.
+    pub(crate) async fn add_album_removals(
+        &mut self,
+        conn: &mut DbConnection<'_>,
+        album: &str,
+        recursive: bool,
+        since: DateTime<Utc>,
+    ) -> Result {
+        self.removed = sqlx::query_scalar!(
+            r#"
+            WITH "albums" AS (
+                SELECT "descendent" AS "id"
+                FROM "album_descendent"
+                WHERE "id"=$1 AND $2
+                UNION
+                SELECT $1
+            )
+            SELECT DISTINCT "media" AS "media!"
+            FROM "media_album_removal"
+            WHERE
+                "album" IN (SELECT "id" FROM "albums") AND
+                "removed" >= $3 AND
+                "media" NOT IN (
+                    SELECT "media"
+                    FROM "media_album"
+                    WHERE "album" IN (SELECT "id" FROM "albums")
+                )
+            "#,
+            album,
+            recursive,
+            since
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum SourceType {
     Lightroom,
@@ -187,7 +362,7 @@ pub(crate) enum SourceType {
 derive_display_from_serialize!(SourceType);
 derive_fromstr_from_deserialize!(SourceType);
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Source {
     pub(crate) id: String,
@@ -247,7 +422,7 @@ impl Source {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum AlternateFileType {
     Thumbnail,
@@ -268,7 +443,9 @@ impl AlternateFileType {
 }
 
 #[EnumRepr(type = "i32")]
-#[derive(Default, Debug, Clone, Copy, Serialize_repr, Deserialize_repr, PartialEq, Eq)]
+#[derive(
+    Default, Debug, Clone, Copy, Serialize_repr, Deserialize_repr, PartialEq, Eq, ToSchema,
+)]
 pub(crate) enum Orientation {
     #[default]
     TopLeft = 1,
@@ -281,7 +458,29 @@ pub(crate) enum Orientation {
     LeftBottom = 8,
 }
 
-#[derive(Serialize, Clone, Debug)]
+fn session_expiry(config: &Config) -> DateTime<Utc> {
+    Utc::now() + Duration::from_std(config.session_lifetime).unwrap_or(Duration::days(90))
+}
+
+/// Details of the client using a login session.
+#[derive(Default, Clone, Debug)]
+pub(crate) struct SessionClient {
+    pub(crate) ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AuthSession {
+    pub(crate) id: String,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) last_used: Option<DateTime<Utc>>,
+    pub(crate) expiry: DateTime<Utc>,
+    pub(crate) client_ip: Option<String>,
+    pub(crate) user_agent: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct User {
     pub(crate) email: String,
     #[serde(skip)]
@@ -291,6 +490,20 @@ pub(crate) struct User {
     pub(crate) created: DateTime<Utc>,
     pub(crate) last_login: Option<DateTime<Utc>>,
     pub(crate) verified: bool,
+    pub(crate) disabled: bool,
+    #[serde(skip)]
+    pub(crate) totp_secret: Option<String>,
+    /// Whether logging in requires a one-time password.
+    pub(crate) totp: bool,
+}
+
+/// The outcome of a successful first factor check.
+pub(crate) enum Login {
+    /// A new session token.
+    Session(String),
+    /// A challenge that must be completed with a second factor to get a
+    /// session token.
+    SecondFactor(String),
 }
 
 impl User {
@@ -308,12 +521,22 @@ impl User {
         .await?)
     }
 
+    /// Checks the password against the user's password hash, returning
+    /// `Error::NotFound` if it does not match.
+    pub(crate) async fn check_password(&self, password: &str) -> Result {
+        match self.password {
+            Some(ref password_hash) if password::check(password, password_hash).await => Ok(()),
+            _ => Err(Error::NotFound),
+        }
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn verify_credentials(
         conn: &mut DbConnection<'_>,
         email: &str,
         password: &str,
-    ) -> Result<(models::User, String)> {
+        client: &SessionClient,
+    ) -> Result<(models::User, Login)> {
         let mut user = sqlx::query!(
             r#"
             SELECT *
@@ -326,185 +549,1661 @@ impl User {
         .fetch_one(conn.as_db())
         .await?;
 
-        if let Some(password_hash) = user.password.clone() {
-            let password = password.to_owned();
-            match spawn_blocking(span!(Level::TRACE, "verify password"), move || {
-                bcrypt::verify(password, &password_hash)
-            })
-            .await
-            {
-                Ok(true) => (),
-                _ => return Err(Error::NotFound),
-            }
-        } else {
+        if user.disabled || !user.verified {
             return Err(Error::NotFound);
         }
 
-        let token = long_id("T");
+        user.check_password(password).await?;
 
-        sqlx::query!(
-            r#"
-            INSERT INTO "auth_token" ("email", "token", "expiry")
-            VALUES ($1,$2,$3)
-            "#,
-            email,
-            token,
-            Some(Utc::now() + Duration::days(TOKEN_EXPIRY_DAYS))
-        )
-        .execute(conn.as_db())
-        .await?;
+        // Older hashes are transparently upgraded to the current algorithm and
+        // parameters. Short passwords from before the length limit are allowed.
+        if let Some(ref old_hash) = user.password {
+            if password::needs_rehash(&conn.config().argon2, old_hash) {
+                let new_hash = password::rehash(conn.config(), password).await?;
 
-        user.last_login = Some(Utc::now());
+                sqlx::query!(
+                    r#"
+                    UPDATE "user"
+                    SET "password"=$1
+                    WHERE "email"=$2 AND "password"=$3
+                    "#,
+                    new_hash,
+                    email,
+                    old_hash,
+                )
+                .execute(conn.as_db())
+                .await?;
 
-        sqlx::query!(
-            r#"
-            UPDATE "user"
-            SET "last_login"=$1
-            WHERE "email"=$2
-            "#,
-            user.last_login,
-            email,
-        )
-        .execute(conn.as_db())
-        .await?;
+                user.password = Some(new_hash);
+            }
+        }
 
-        Ok((user, token))
+        let login = Self::login_challenge(conn, &mut user, client).await?;
+
+        Ok((user, login))
     }
 
-    #[instrument(skip_all)]
-    pub(crate) async fn verify_token(
+    /// Starts a session for a user that has passed their first factor, or a
+    /// challenge if they must also provide a one-time password.
+    async fn login_challenge(
         conn: &mut DbConnection<'_>,
-        token: &str,
-    ) -> Result<Option<models::User>> {
-        let expiry = Utc::now() + Duration::days(TOKEN_EXPIRY_DAYS);
+        user: &mut User,
+        client: &SessionClient,
+    ) -> Result<Login> {
+        if user.totp {
+            let challenge = long_id("Z");
+
+            sqlx::query!(
+                r#"
+                INSERT INTO "login_challenge" ("challenge", "email")
+                VALUES ($1,$2)
+                "#,
+                challenge,
+                user.email,
+            )
+            .execute(conn.as_db())
+            .await?;
+
+            return Ok(Login::SecondFactor(challenge));
+        }
 
-        let email = match sqlx::query_scalar!(
+        let token = Self::create_session(conn, user, client).await?;
+
+        Ok(Login::Session(token))
+    }
+
+    /// Completes a login challenge with a one-time password or recovery code.
+    /// Each challenge only allows a few attempts.
+    #[instrument(skip_all)]
+    pub(crate) async fn verify_second_factor(
+        conn: &mut DbConnection<'_>,
+        challenge: &str,
+        code: &str,
+        client: &SessionClient,
+    ) -> Result<(models::User, String)> {
+        let email = sqlx::query_scalar!(
             r#"
-            UPDATE "auth_token"
-            SET "expiry"=$1
-            WHERE "token"=$2
+            UPDATE "login_challenge"
+            SET "attempts"="attempts" + 1
+            WHERE
+                "challenge"=$1 AND
+                "attempts" < $2 AND
+                "request" > CURRENT_TIMESTAMP - INTERVAL '5 minutes'
             RETURNING "email"
             "#,
-            expiry,
-            token
+            challenge,
+            MAX_CHALLENGE_ATTEMPTS,
         )
-        .fetch_optional(conn.as_db())
-        .await?
-        {
-            Some(u) => u,
-            None => return Ok(None),
-        };
+        .fetch_one(conn.as_db())
+        .await?;
 
-        let user = sqlx::query!(
+        let mut user = sqlx::query!(
             r#"
-            UPDATE "user"
-            SET "last_login"=CURRENT_TIMESTAMP
+            SELECT *
+            FROM "user"
             WHERE "email"=$1
-            RETURNING "user".*
             "#,
             email
         )
         .map(|row| from_row!(User(row)))
-        .fetch_optional(conn.as_db())
+        .fetch_one(conn.as_db())
         .await?;
+        if user.disabled || !user.check_second_factor(conn, code).await? {
+            return Err(Error::NotFound);
+        }
 
-        Ok(user)
-    }
-
-    pub(crate) async fn delete_token(conn: &mut DbConnection<'_>, token: &str) -> Result {
         sqlx::query!(
-            r#"
-            DELETE FROM "auth_token"
-            WHERE "token"=$1
-            "#,
-            token
+            r#"DELETE FROM "login_challenge" WHERE "challenge"=$1"#,
+            challenge
         )
         .execute(conn.as_db())
         .await?;
 
-        Ok(())
+        let token = Self::create_session(conn, &mut user, client).await?;
+
+        Ok((user, token))
     }
-}
 
-#[derive(Serialize, Clone, Debug)]
-#[serde(rename_all = "camelCase")]
-pub(crate) struct Storage {
-    pub(crate) id: String,
-    pub(crate) name: String,
-    #[serde(skip)]
-    pub(crate) access_key_id: String,
-    #[serde(skip)]
-    pub(crate) secret_access_key: String,
-    pub(crate) bucket: String,
-    pub(crate) region: String,
-    pub(crate) path: Option<String>,
-    pub(crate) endpoint: Option<String>,
-    pub(crate) public_url: Option<String>,
-    #[serde(skip)]
-    pub(crate) _owner: String,
-}
+    /// Checks a one-time password or, failing that, a recovery code. Both can
+    /// only be used once.
+    async fn check_second_factor(&self, conn: &mut DbConnection<'_>, code: &str) -> Result<bool> {
+        let secret = match self.totp_secret {
+            Some(ref secret) if self.totp => secret,
+            _ => return Ok(false),
+        };
 
-impl Storage {
-    pub(crate) async fn file_store(&self, config: &Config) -> Result<impl FileStore> {
-        let client = AwsClient::from_storage(self, config).await?;
+        if let Some(step) = totp::verify(secret, code, Utc::now().timestamp()) {
+            let result = sqlx::query!(
+                r#"
+                UPDATE "user"
+                SET "totp_step"=$1
+                WHERE "email"=$2 AND ("totp_step" IS NULL OR "totp_step" < $1)
+                "#,
+                step,
+                self.email,
+            )
+            .execute(conn.as_db())
+            .await?;
 
-        Ok(client)
-    }
+            return Ok(result.rows_affected() > 0);
+        }
 
-    pub(crate) async fn online_uri(
-        &self,
-        path: &FilePath,
-        mimetype: &Mime,
-        filename: Option<&str>,
-        config: &Config,
-    ) -> Result<String> {
-        let client = AwsClient::from_storage(self, config).await?;
-        client.file_uri(path, mimetype, filename).await
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM "user_recovery_code"
+            WHERE "email"=$1 AND "code"=$2
+            "#,
+            self.email,
+            totp::hash_recovery_code(code),
+        )
+        .execute(conn.as_db())
+        .await?;
+
+        Ok(result.rows_affected() > 0)
     }
 
-    pub(crate) async fn get_for_catalog(
-        conn: &mut DbConnection<'_>,
-        catalog: &str,
-    ) -> Result<Storage> {
-        Ok(sqlx::query!(
+    /// Starts enrolling the user in two-factor authentication, returning the
+    /// new secret. It is not required to log in until confirmed.
+    #[instrument(skip(conn))]
+    pub(crate) async fn enrol_totp(conn: &mut DbConnection<'_>, email: &str) -> Result<String> {
+        let secret = totp::generate_secret();
+
+        let result = sqlx::query!(
             r#"
-            SELECT "storage".*
-            FROM "storage" JOIN "catalog" ON "catalog"."storage"="storage"."id"
-            WHERE "catalog"."id"=$1
+            UPDATE "user"
+            SET "totp_secret"=$1, "totp_step"=NULL
+            WHERE "email"=$2 AND NOT "totp"
             "#,
-            catalog
+            secret,
+            email,
         )
-        .map(|row| from_row!(Storage(row)))
-        .fetch_one(conn)
-        .await?)
+        .execute(conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::InvalidData {
+                message: "Two-factor authentication is already enabled".to_string(),
+            });
+        }
+
+        Ok(secret)
     }
 
-    pub(crate) async fn list_for_user<'c, D: AsDb<'c>>(
-        mut conn: D,
+    /// Enables two-factor authentication once the user has shown they can
+    /// generate codes. Returns a new set of recovery codes.
+    #[instrument(skip(conn, code))]
+    pub(crate) async fn confirm_totp(
+        conn: &mut DbConnection<'_>,
         email: &str,
-    ) -> Result<Vec<Storage>> {
-        Ok(sqlx::query!(
+        code: &str,
+    ) -> Result<Vec<String>> {
+        let user = sqlx::query!(
             r#"
-            SELECT "storage".*
-            FROM "storage"
-            WHERE "owner"=$1
+            SELECT *
+            FROM "user"
+            WHERE "email"=$1
             "#,
             email
         )
-        .map(|row| from_row!(Storage(row)))
-        .fetch_all(conn.as_db())
-        .await?)
-    }
-}
+        .map(|row| from_row!(User(row)))
+        .fetch_one(conn.as_db())
+        .await?;
 
-#[derive(Serialize, Clone, Debug)]
-pub(crate) struct Catalog {
+        let step = match (user.totp, user.totp_secret) {
+            (false, Some(secret)) => totp::verify(&secret, code, Utc::now().timestamp()),
+            _ => {
+                return Err(Error::InvalidData {
+                    message: "Two-factor authentication enrolment has not been started".to_string(),
+                })
+            }
+        };
+
+        let step = step.ok_or_else(|| Error::InvalidData {
+            message: "Incorrect code".to_string(),
+        })?;
+
+        sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "totp"=TRUE, "totp_step"=$1
+            WHERE "email"=$2
+            "#,
+            step,
+            email,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Self::reset_recovery_codes(conn, email).await
+    }
+
+    /// Replaces the user's recovery codes with a new set.
+    #[instrument(skip(conn))]
+    pub(crate) async fn reset_recovery_codes(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+    ) -> Result<Vec<String>> {
+        let codes = totp::generate_recovery_codes();
+        let hashes: Vec<String> = codes.iter().map(|c| totp::hash_recovery_code(c)).collect();
+
+        sqlx::query!(
+            r#"DELETE FROM "user_recovery_code" WHERE "email"=$1"#,
+            email
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "user_recovery_code" ("email", "code")
+            SELECT $1, * FROM UNNEST($2::text[])
+            "#,
+            email,
+            &hashes,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(codes)
+    }
+
+    #[instrument(skip(conn))]
+    pub(crate) async fn disable_totp(conn: &mut DbConnection<'_>, email: &str) -> Result {
+        let result = sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "totp"=FALSE, "totp_secret"=NULL, "totp_step"=NULL
+            WHERE "email"=$1
+            "#,
+            email,
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        sqlx::query!(
+            r#"DELETE FROM "user_recovery_code" WHERE "email"=$1"#,
+            email
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn clean_challenges(conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "login_challenge"
+            WHERE "request" <= CURRENT_TIMESTAMP - INTERVAL '5 minutes'
+            "#
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Logs in a user whose email address has been verified by an external
+    /// identity provider. Unknown users are only created if `provision` is
+    /// set. Users with a one-time password must still complete a challenge.
+    #[instrument(skip(conn, client))]
+    pub(crate) async fn external_login(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        fullname: Option<&str>,
+        provision: bool,
+        client: &SessionClient,
+    ) -> Result<(models::User, Login)> {
+        // The provider has proven ownership of the email address. Any password
+        // set on an unverified account was not, so it is discarded.
+        let mut user = if provision {
+            sqlx::query!(
+                r#"
+                INSERT INTO "user" ("email", "fullname", "administrator", "created", "verified")
+                VALUES ($1,$2,FALSE,CURRENT_TIMESTAMP,TRUE)
+                ON CONFLICT("email") DO UPDATE SET
+                    "password"=CASE WHEN "user"."verified" THEN "user"."password" ELSE NULL END,
+                    "fullname"=COALESCE("user"."fullname", "excluded"."fullname"),
+                    "verified"=TRUE
+                RETURNING *
+                "#,
+                email,
+                fullname,
+            )
+            .map(|row| from_row!(User(row)))
+            .fetch_one(conn.as_db())
+            .await?
+        } else {
+            sqlx::query!(
+                r#"
+                UPDATE "user"
+                SET
+                    "password"=CASE WHEN "verified" THEN "password" ELSE NULL END,
+                    "verified"=TRUE
+                WHERE "email"=$1
+                RETURNING *
+                "#,
+                email,
+            )
+            .map(|row| from_row!(User(row)))
+            .fetch_one(conn.as_db())
+            .await?
+        };
+
+        if user.disabled {
+            return Err(Error::NotFound);
+        }
+
+        let login = Self::login_challenge(conn, &mut user, client).await?;
+
+        Ok((user, login))
+    }
+
+    /// Starts a new login session for the user and returns its token.
+    async fn create_session(
+        conn: &mut DbConnection<'_>,
+        user: &mut User,
+        client: &SessionClient,
+    ) -> Result<String> {
+        let token = long_id("T");
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "auth_token" ("id", "email", "token", "expiry", "last_used", "client_ip", "user_agent")
+            VALUES ($1,$2,$3,$4,CURRENT_TIMESTAMP,$5,$6)
+            "#,
+            short_id("N"),
+            user.email,
+            token,
+            session_expiry(conn.config()),
+            client.ip,
+            client.user_agent,
+        )
+        .execute(conn.as_db())
+        .await?;
+
+        user.last_login = Some(Utc::now());
+
+        sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "last_login"=$1
+            WHERE "email"=$2
+            "#,
+            user.last_login,
+            user.email,
+        )
+        .execute(conn.as_db())
+        .await?;
+
+        Ok(token)
+    }
+
+    #[instrument(skip_all)]
+    /// Finds the user for a session token, returning them along with the
+    /// session's ID. Each use extends the session's expiry.
+    pub(crate) async fn verify_token(
+        conn: &mut DbConnection<'_>,
+        token: &str,
+        client: &SessionClient,
+    ) -> Result<Option<(models::User, String)>> {
+        let expiry = session_expiry(conn.config());
+
+        let (email, session_id) = match sqlx::query!(
+            r#"
+            UPDATE "auth_token"
+            SET
+                "expiry"=$1,
+                "last_used"=CURRENT_TIMESTAMP,
+                "client_ip"=COALESCE($3, "client_ip"),
+                "user_agent"=COALESCE($4, "user_agent")
+            WHERE "token"=$2 AND "expiry" > CURRENT_TIMESTAMP
+            RETURNING "email", "id"
+            "#,
+            expiry,
+            token,
+            client.ip,
+            client.user_agent,
+        )
+        .map(|row| (row.email, row.id))
+        .fetch_optional(conn.as_db())
+        .await?
+        {
+            Some(u) => u,
+            None => return Ok(None),
+        };
+
+        let user = sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "last_login"=CURRENT_TIMESTAMP
+            WHERE "email"=$1 AND NOT "disabled"
+            RETURNING "user".*
+            "#,
+            email
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_optional(conn.as_db())
+        .await?;
+
+        Ok(user.map(|user| (user, session_id)))
+    }
+
+    pub(crate) async fn list_sessions(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+    ) -> Result<Vec<AuthSession>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "auth_token"
+            WHERE "email"=$1 AND "expiry" > CURRENT_TIMESTAMP
+            ORDER BY "last_used" DESC NULLS LAST
+            "#,
+            email
+        )
+        .map(|row| from_row!(AuthSession(row)))
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub(crate) async fn delete_session(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM "auth_token"
+            WHERE "email"=$1 AND "id"=$2
+            "#,
+            email,
+            id
+        )
+        .execute(conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete_all_sessions(conn: &mut DbConnection<'_>, email: &str) -> Result {
+        sqlx::query!(r#"DELETE FROM "auth_token" WHERE "email"=$1"#, email)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn clean_sessions(conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "auth_token"
+            WHERE "expiry" <= CURRENT_TIMESTAMP
+            "#
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete_token(conn: &mut DbConnection<'_>, token: &str) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "auth_token"
+            WHERE "token"=$1
+            "#,
+            token
+        )
+        .execute(conn.as_db())
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn list(conn: &mut DbConnection<'_>) -> Result<Vec<User>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "user"
+            ORDER BY "email"
+            "#
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Creates a new user. Users that were invited to a catalog before they
+    /// had an account have no password and are completed rather than
+    /// rejected.
+    #[instrument(skip(conn, password))]
+    pub(crate) async fn create(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        password: &str,
+        fullname: Option<&str>,
+        administrator: bool,
+        verified: bool,
+    ) -> Result<User> {
+        if !email.contains('@') {
+            return Err(Error::InvalidData {
+                message: format!("'{email}' is not a valid email address"),
+            });
+        }
+
+        let password_hash = password::hash_password(conn.config(), password).await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "user" ("email", "password", "fullname", "administrator", "created", "verified")
+            VALUES ($1,$2,$3,$4,CURRENT_TIMESTAMP,$5)
+            ON CONFLICT("email") DO UPDATE SET
+                "password"="excluded"."password",
+                "fullname"="excluded"."fullname",
+                "administrator"="excluded"."administrator",
+                "verified"="excluded"."verified"
+            WHERE "user"."password" IS NULL
+            RETURNING *
+            "#,
+            email,
+            password_hash,
+            fullname,
+            administrator,
+            verified,
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_optional(conn)
+        .await?
+        .ok_or_else(|| Error::InvalidData {
+            message: format!("An account already exists for {email}"),
+        })
+    }
+
+    /// Creates an unverified user and returns a token that must be used to
+    /// verify their email address before they can log in. Registering again
+    /// before verifying replaces the earlier registration and its token.
+    #[instrument(skip(conn, password))]
+    pub(crate) async fn register(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        password: &str,
+        fullname: Option<&str>,
+    ) -> Result<(User, String)> {
+        if !email.contains('@') {
+            return Err(Error::InvalidData {
+                message: format!("'{email}' is not a valid email address"),
+            });
+        }
+
+        let password_hash = password::hash_password(conn.config(), password).await?;
+
+        // Only accounts that were never verified, invited users or earlier
+        // registrations, may be taken over. Accounts provisioned by an
+        // OpenID Connect provider are verified but have no password.
+        let user = sqlx::query!(
+            r#"
+            INSERT INTO "user" ("email", "password", "fullname", "administrator", "created", "verified")
+            VALUES ($1,$2,$3,FALSE,CURRENT_TIMESTAMP,FALSE)
+            ON CONFLICT("email") DO UPDATE SET
+                "password"="excluded"."password",
+                "fullname"="excluded"."fullname"
+            WHERE NOT "user"."verified"
+            RETURNING *
+            "#,
+            email,
+            password_hash,
+            fullname,
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or_else(|| Error::InvalidData {
+            message: format!("An account already exists for {email}"),
+        })?;
+
+        let token = long_id("V");
+
+        // An earlier token would otherwise verify the replaced password.
+        sqlx::query!(
+            r#"
+            DELETE FROM "user_verification"
+            WHERE "email"=$1
+            "#,
+            email
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "user_verification" ("email", "token")
+            VALUES ($1,$2)
+            "#,
+            email,
+            token
+        )
+        .execute(conn)
+        .await?;
+
+        Ok((user, token))
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) async fn verify_email(conn: &mut DbConnection<'_>, token: &str) -> Result<User> {
+        let email = sqlx::query_scalar!(
+            r#"
+            DELETE FROM "user_verification"
+            WHERE "token"=$1 AND "request" > CURRENT_TIMESTAMP - INTERVAL '1 day'
+            RETURNING "email"
+            "#,
+            token
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        Ok(sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "verified"=TRUE
+            WHERE "email"=$1
+            RETURNING *
+            "#,
+            email
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn clean_verifications(conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "user_verification"
+            WHERE "request" < CURRENT_TIMESTAMP - INTERVAL '1 day'
+            "#
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Sets a new password for the user. All sessions other than `keep_token`
+    /// are logged out.
+    #[instrument(skip(conn, password, keep_token))]
+    pub(crate) async fn set_password(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        password: &str,
+        keep_token: Option<&str>,
+    ) -> Result {
+        let password_hash = password::hash_password(conn.config(), password).await?;
+
+        let result = sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "password"=$1
+            WHERE "email"=$2
+            "#,
+            password_hash,
+            email
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM "auth_token"
+            WHERE "email"=$1 AND "token" IS DISTINCT FROM $2
+            "#,
+            email,
+            keep_token
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Disables or enables the user. Disabled users are logged out of all
+    /// sessions.
+    #[instrument(skip(conn))]
+    pub(crate) async fn set_disabled(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        disabled: bool,
+    ) -> Result<User> {
+        let user = sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "disabled"=$1
+            WHERE "email"=$2
+            RETURNING *
+            "#,
+            disabled,
+            email
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if disabled {
+            sqlx::query!(r#"DELETE FROM "auth_token" WHERE "email"=$1"#, email)
+                .execute(conn)
+                .await?;
+        }
+
+        Ok(user)
+    }
+
+    #[instrument(skip(conn))]
+    pub(crate) async fn set_administrator(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        administrator: bool,
+    ) -> Result<User> {
+        Ok(sqlx::query!(
+            r#"
+            UPDATE "user"
+            SET "administrator"=$1
+            WHERE "email"=$2
+            RETURNING *
+            "#,
+            administrator,
+            email
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+}
+
+/// An OpenID Connect login that is waiting for the provider to redirect back.
+#[derive(Clone, Debug)]
+pub(crate) struct OidcLogin {
+    pub(crate) state: String,
+    pub(crate) nonce: String,
+}
+
+impl OidcLogin {
+    pub(crate) async fn create(conn: &mut DbConnection<'_>) -> Result<OidcLogin> {
+        let login = OidcLogin {
+            state: long_id("O"),
+            nonce: long_id("O"),
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "oidc_login" ("state", "nonce")
+            VALUES ($1,$2)
+            "#,
+            login.state,
+            login.nonce,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(login)
+    }
+
+    /// Completes a pending login. Each login can only be completed once.
+    pub(crate) async fn take(conn: &mut DbConnection<'_>, state: &str) -> Result<OidcLogin> {
+        Ok(sqlx::query!(
+            r#"
+            DELETE FROM "oidc_login"
+            WHERE "state"=$1 AND "request" > CURRENT_TIMESTAMP - INTERVAL '10 minutes'
+            RETURNING "state", "nonce"
+            "#,
+            state
+        )
+        .map(|row| OidcLogin {
+            state: row.state,
+            nonce: row.nonce,
+        })
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn clean(conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "oidc_login"
+            WHERE "request" <= CURRENT_TIMESTAMP - INTERVAL '10 minutes'
+            "#
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn idempotency_expiry(config: &Config) -> DateTime<Utc> {
+    Utc::now() - Duration::from_std(config.idempotency_window).unwrap_or(Duration::days(1))
+}
+
+/// The stored response to a request made with an `Idempotency-Key` header.
+/// Keys are separate for each user and route.
+pub(crate) struct IdempotencyKey;
+
+impl IdempotencyKey {
+    /// Claims a key for the current transaction. If an earlier request has
+    /// already used the key its response is returned instead, unless the
+    /// requests differ. A concurrent request using the same key blocks until
+    /// this transaction completes.
+    pub(crate) async fn claim(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        route: &str,
+        key: &str,
+        request_hash: &str,
+    ) -> Result<Option<Value>> {
+        let expiry = idempotency_expiry(conn.config());
+
+        sqlx::query!(
+            r#"
+            DELETE FROM "idempotency_key"
+            WHERE "email"=$1 AND "route"=$2 AND "key"=$3 AND "created" <= $4
+            "#,
+            email,
+            route,
+            key,
+            expiry
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let claimed = sqlx::query!(
+            r#"
+            INSERT INTO "idempotency_key" ("email", "route", "key", "request_hash")
+            VALUES ($1,$2,$3,$4)
+            ON CONFLICT DO NOTHING
+            RETURNING "key"
+            "#,
+            email,
+            route,
+            key,
+            request_hash
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if claimed.is_some() {
+            return Ok(None);
+        }
+
+        let earlier = sqlx::query!(
+            r#"
+            SELECT "response", "request_hash"
+            FROM "idempotency_key"
+            WHERE "email"=$1 AND "route"=$2 AND "key"=$3
+            "#,
+            email,
+            route,
+            key
+        )
+        .fetch_one(conn)
+        .await?;
+
+        if earlier.request_hash.as_deref() != Some(request_hash) {
+            return Err(Error::InvalidData {
+                message: "The idempotency key was already used for a different request".to_owned(),
+            });
+        }
+
+        Ok(Some(earlier.response.unwrap_or_default()))
+    }
+
+    /// Records the response for a key claimed in the current transaction.
+    pub(crate) async fn complete(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        route: &str,
+        key: &str,
+        response: Value,
+    ) -> Result {
+        sqlx::query!(
+            r#"
+            UPDATE "idempotency_key"
+            SET "response"=$4
+            WHERE "email"=$1 AND "route"=$2 AND "key"=$3
+            "#,
+            email,
+            route,
+            key,
+            response
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn clean(conn: &mut DbConnection<'_>) -> Result {
+        let expiry = idempotency_expiry(conn.config());
+
+        sqlx::query!(
+            r#"
+            DELETE FROM "idempotency_key"
+            WHERE "created" <= $1
+            "#,
+            expiry
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, ToSchema)]
+pub(crate) enum AuditAction {
+    #[serde(rename = "media/create")]
+    CreateMedia,
+    #[serde(rename = "media/edit")]
+    EditMedia,
+    #[serde(rename = "media/delete")]
+    DeleteMedia,
+    #[serde(rename = "media/restore")]
+    RestoreMedia,
+    #[serde(rename = "media/revert")]
+    RevertMedia,
+    #[serde(rename = "album/create")]
+    CreateAlbum,
+    #[serde(rename = "album/edit")]
+    EditAlbum,
+    #[serde(rename = "album/delete")]
+    DeleteAlbum,
+    #[serde(rename = "album/restore")]
+    RestoreAlbum,
+    #[serde(rename = "album/media")]
+    AlbumMedia,
+    #[serde(rename = "tag/edit")]
+    EditTag,
+    #[serde(rename = "tag/merge")]
+    MergeTags,
+    #[serde(rename = "tag/delete")]
+    DeleteTag,
+    #[serde(rename = "person/edit")]
+    EditPerson,
+    #[serde(rename = "person/merge")]
+    MergePeople,
+    #[serde(rename = "person/delete")]
+    DeletePerson,
+}
+
+derive_display_from_serialize!(AuditAction);
+derive_fromstr_from_deserialize!(AuditAction);
+
+impl AuditAction {
+    pub(crate) fn decode(source: &str) -> SqlxResult<Self> {
+        Self::from_str(source).map_err(|e| SqlxError::Decode(Box::new(e)))
+    }
+}
+
+/// The values of a field before and after a change.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, ToSchema)]
+pub(crate) struct AuditChange {
+    pub(crate) old: Value,
+    pub(crate) new: Value,
+}
+
+/// A change made by a user to a media item, album, tag or person.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct AuditEntry {
+    pub(crate) id: String,
+    pub(crate) catalog: String,
+    pub(crate) email: String,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) action: AuditAction,
+    pub(crate) target: String,
+    pub(crate) changes: BTreeMap<String, AuditChange>,
+}
+
+impl AuditEntry {
+    pub(crate) fn new(catalog: &str, email: &str, action: AuditAction, target: &str) -> Self {
+        Self {
+            id: long_id("E"),
+            catalog: catalog.to_owned(),
+            email: email.to_owned(),
+            created: Utc::now(),
+            action,
+            target: target.to_owned(),
+            changes: BTreeMap::new(),
+        }
+    }
+
+    /// Records a field's old and new values if they differ.
+    pub(crate) fn change<T: Serialize>(mut self, field: &str, old: T, new: T) -> Self {
+        let old = serde_json::to_value(old).unwrap_or_default();
+        let new = serde_json::to_value(new).unwrap_or_default();
+
+        if old != new {
+            self.changes
+                .insert(field.to_owned(), AuditChange { old, new });
+        }
+
+        self
+    }
+
+    pub(crate) async fn insert(conn: &mut DbConnection<'_>, entries: &[AuditEntry]) -> Result {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        for records in batch(entries, 500) {
+            let mut id = Vec::<String>::new();
+            let mut catalog = Vec::<String>::new();
+            let mut email = Vec::<String>::new();
+            let mut created = Vec::<DateTime<Utc>>::new();
+            let mut action = Vec::<String>::new();
+            let mut target = Vec::<String>::new();
+            let mut changes = Vec::<Value>::new();
+
+            for entry in records {
+                id.push(entry.id.clone());
+                catalog.push(entry.catalog.clone());
+                email.push(entry.email.clone());
+                created.push(entry.created);
+                action.push(entry.action.to_string());
+                target.push(entry.target.clone());
+                changes.push(serde_json::to_value(&entry.changes)?);
+            }
+
+            sqlx::query!(
+                r#"
+                INSERT INTO "audit_log" ("id", "catalog", "email", "created", "action", "target", "changes")
+                SELECT * FROM UNNEST(
+                    $1::text[],
+                    $2::text[],
+                    $3::text[],
+                    $4::timestamptz[],
+                    $5::text[],
+                    $6::text[],
+                    $7::jsonb[]
+                )
+                "#,
+                &id,
+                &catalog,
+                &email,
+                &created,
+                &action,
+                &target,
+                &changes
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Lists the entries for the catalog, newest first. If given only the
+    /// entries older than the entry with the id `before` are included.
+    pub(crate) async fn list_for_catalog(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+        before: Option<&str>,
+        count: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "audit_log"
+            WHERE
+                "catalog"=$1 AND
+                (
+                    $2::text IS NULL OR
+                    ("created", "id") < (SELECT "created", "id" FROM "audit_log" WHERE "id"=$2)
+                )
+            ORDER BY "created" DESC, "id" DESC
+            LIMIT $3
+            "#,
+            catalog,
+            before,
+            count
+        )
+        .try_map(|row| {
+            Ok(AuditEntry {
+                id: row.id,
+                catalog: row.catalog,
+                email: row.email,
+                created: row.created,
+                action: AuditAction::decode(&row.action)?,
+                target: row.target,
+                changes: from_value(row.changes).map_err(|e| SqlxError::Decode(Box::new(e)))?,
+            })
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Lists the entries for a media item, album, tag or person in the
+    /// catalogs the user can access, newest first. Pagination works as for
+    /// `list_for_catalog`.
+    pub(crate) async fn list_for_target(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        target: &str,
+        before: Option<&str>,
+        count: i64,
+    ) -> Result<Vec<AuditEntry>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "audit_log".*
+            FROM "audit_log"
+                JOIN "user_catalog" USING ("catalog")
+            WHERE
+                "user_catalog"."user"=$1 AND
+                "audit_log"."target"=$2 AND
+                (
+                    $3::text IS NULL OR
+                    ("audit_log"."created", "audit_log"."id") <
+                        (SELECT "created", "id" FROM "audit_log" WHERE "id"=$3)
+                )
+            ORDER BY "audit_log"."created" DESC, "audit_log"."id" DESC
+            LIMIT $4
+            "#,
+            email,
+            target,
+            before,
+            count
+        )
+        .try_map(|row| {
+            Ok(AuditEntry {
+                id: row.id,
+                catalog: row.catalog,
+                email: row.email,
+                created: row.created,
+                action: AuditAction::decode(&row.action)?,
+                target: row.target,
+                changes: from_value(row.changes).map_err(|e| SqlxError::Decode(Box::new(e)))?,
+            })
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+}
+
+/// A media file being uploaded in chunks. The data received so far is kept in
+/// temporary storage until the upload is completed.
+#[derive(Clone, Debug)]
+pub(crate) struct MediaUpload {
+    pub(crate) id: String,
+    pub(crate) media_item: String,
+    pub(crate) file_name: Option<String>,
+}
+
+impl MediaUpload {
+    pub(crate) fn directory(config: &Config) -> PathBuf {
+        config.temp_storage.join("uploads")
+    }
+
+    pub(crate) fn file_path(&self, config: &Config) -> PathBuf {
+        Self::directory(config).join(&self.id)
+    }
+
+    pub(crate) async fn create(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        media_item: &str,
+        file_name: Option<&str>,
+    ) -> Result<MediaUpload> {
+        let upload = MediaUpload {
+            id: long_id("R"),
+            media_item: media_item.to_owned(),
+            file_name: file_name.map(ToOwned::to_owned),
+        };
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "media_upload" ("id", "email", "media_item", "file_name")
+            VALUES ($1,$2,$3,$4)
+            "#,
+            upload.id,
+            email,
+            upload.media_item,
+            upload.file_name,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(upload)
+    }
+
+    /// Gets an upload started by the user. The upload is locked until the
+    /// current transaction completes.
+    pub(crate) async fn get_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<MediaUpload> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "id", "media_item", "file_name"
+            FROM "media_upload"
+            WHERE "id"=$1 AND "email"=$2
+            FOR UPDATE
+            "#,
+            id,
+            email
+        )
+        .map(|row| MediaUpload {
+            id: row.id,
+            media_item: row.media_item,
+            file_name: row.file_name,
+        })
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Records that data was received for the upload.
+    pub(crate) async fn touch(&self, conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            UPDATE "media_upload"
+            SET "updated"=CURRENT_TIMESTAMP
+            WHERE "id"=$1
+            "#,
+            self.id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, id: &str) -> Result {
+        sqlx::query!(
+            r#"
+            DELETE FROM "media_upload"
+            WHERE "id"=$1
+            "#,
+            id
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes uploads that have not received data for a day and returns the
+    /// ids of the uploads still in progress.
+    pub(crate) async fn clean(conn: &mut DbConnection<'_>) -> Result<HashSet<String>> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "media_upload"
+            WHERE "updated" <= CURRENT_TIMESTAMP - INTERVAL '1 day'
+            "#
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        Ok(sqlx::query_scalar!(r#"SELECT "id" FROM "media_upload""#)
+            .fetch_all(conn)
+            .await?
+            .into_iter()
+            .collect())
+    }
+}
+
+/// What an API token is allowed to do. Each scope includes the access granted
+/// by the scopes before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum TokenScope {
+    Read,
+    Upload,
+    Write,
+}
+derive_display_from_serialize!(TokenScope);
+derive_fromstr_from_deserialize!(TokenScope);
+
+impl TokenScope {
+    pub(crate) fn decode(source: &str) -> SqlxResult<Self> {
+        Self::from_str(source).map_err(|e| SqlxError::Decode(Box::new(e)))
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ApiToken {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) scope: TokenScope,
+    pub(crate) catalog: Option<String>,
+    pub(crate) created: DateTime<Utc>,
+    pub(crate) last_used: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub(crate) async fn list_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+    ) -> Result<Vec<ApiToken>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "api_token"
+            WHERE "email"=$1
+            ORDER BY "created"
+            "#,
+            email
+        )
+        .try_map(|row| Ok(from_row!(ApiToken(row))))
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Creates a new token, returning it along with the secret used to
+    /// authenticate with it. The secret cannot be retrieved later.
+    #[instrument(skip(conn))]
+    pub(crate) async fn create(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        name: &str,
+        scope: TokenScope,
+        catalog: Option<&str>,
+    ) -> Result<(ApiToken, String)> {
+        if name.is_empty() {
+            return Err(Error::InvalidData {
+                message: "Token name cannot be empty".to_string(),
+            });
+        }
+
+        let token = long_id("K");
+
+        let api_token = sqlx::query!(
+            r#"
+            INSERT INTO "api_token" ("id", "email", "name", "token", "scope", "catalog")
+            VALUES ($1,$2,$3,$4,$5,$6)
+            RETURNING *
+            "#,
+            short_id("K"),
+            email,
+            name,
+            token,
+            scope.to_string(),
+            catalog
+        )
+        .try_map(|row| Ok(from_row!(ApiToken(row))))
+        .fetch_one(conn)
+        .await?;
+
+        Ok((api_token, token))
+    }
+
+    #[instrument(skip(conn))]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, email: &str, id: &str) -> Result {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM "api_token"
+            WHERE "email"=$1 AND "id"=$2
+            "#,
+            email,
+            id
+        )
+        .execute(conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the user and token for an API token secret.
+    #[instrument(skip_all)]
+    pub(crate) async fn verify(
+        conn: &mut DbConnection<'_>,
+        token: &str,
+    ) -> Result<Option<(User, ApiToken)>> {
+        let api_token = match sqlx::query!(
+            r#"
+            UPDATE "api_token"
+            SET "last_used"=CURRENT_TIMESTAMP
+            FROM "user"
+            WHERE
+                "api_token"."email"="user"."email" AND
+                "api_token"."token"=$1 AND
+                NOT "user"."disabled"
+            RETURNING "api_token".*
+            "#,
+            token
+        )
+        .try_map(|row| Ok((row.email.clone(), from_row!(ApiToken(row)))))
+        .fetch_optional(&mut *conn)
+        .await?
+        {
+            Some(t) => t,
+            None => return Ok(None),
+        };
+
+        let (email, api_token) = api_token;
+        let user = sqlx::query!(
+            r#"
+            SELECT *
+            FROM "user"
+            WHERE "email"=$1
+            "#,
+            email
+        )
+        .map(|row| from_row!(User(row)))
+        .fetch_one(conn)
+        .await?;
+
+        Ok(Some((user, api_token)))
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Storage {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    #[serde(skip)]
+    pub(crate) access_key_id: String,
+    #[serde(skip)]
+    pub(crate) secret_access_key: String,
+    pub(crate) bucket: String,
+    pub(crate) region: String,
+    pub(crate) path: Option<String>,
+    pub(crate) endpoint: Option<String>,
+    pub(crate) public_url: Option<String>,
+    #[serde(skip)]
+    pub(crate) _owner: String,
+}
+
+impl Storage {
+    pub(crate) async fn file_store(&self, config: &Config) -> Result<impl FileStore> {
+        let client = AwsClient::from_storage(self, config).await?;
+
+        Ok(client)
+    }
+
+    pub(crate) async fn online_uri(
+        &self,
+        path: &FilePath,
+        mimetype: &Mime,
+        filename: Option<&str>,
+        config: &Config,
+    ) -> Result<String> {
+        let client = AwsClient::from_storage(self, config).await?;
+        client.file_uri(path, mimetype, filename).await
+    }
+
+    pub(crate) async fn get_for_catalog(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+    ) -> Result<Storage> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "storage".*
+            FROM "storage" JOIN "catalog" ON "catalog"."storage"="storage"."id"
+            WHERE "catalog"."id"=$1
+            "#,
+            catalog
+        )
+        .map(|row| from_row!(Storage(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn list_for_user<'c, D: AsDb<'c>>(
+        mut conn: D,
+        email: &str,
+    ) -> Result<Vec<Storage>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "storage".*
+            FROM "storage"
+            WHERE "owner"=$1
+            "#,
+            email
+        )
+        .map(|row| from_row!(Storage(row)))
+        .fetch_all(conn.as_db())
+        .await?)
+    }
+
+    pub(crate) async fn get_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<Storage> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "storage".*
+            FROM "storage"
+            WHERE "id"=$1 AND "owner"=$2
+            "#,
+            id,
+            email
+        )
+        .map(|row| from_row!(Storage(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Checks that the storage credentials allow writing, listing and deleting
+    /// files.
+    pub(crate) async fn verify(&self, config: &Config) -> Result {
+        if self.name.is_empty() {
+            return Err(Error::InvalidData {
+                message: "Storage name cannot be empty".to_string(),
+            });
+        }
+
+        if self.access_key_id.is_empty() || self.secret_access_key.is_empty() {
+            return Err(Error::InvalidData {
+                message: "Storage credentials are required".to_string(),
+            });
+        }
+
+        let client = AwsClient::from_storage(self, config).await?;
+        client.verify(self).await
+    }
+
+    pub(crate) async fn create_or_update(&self, conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            INSERT INTO "storage" (
+                "id",
+                "name",
+                "access_key_id",
+                "secret_access_key",
+                "bucket",
+                "region",
+                "path",
+                "endpoint",
+                "public_url",
+                "owner"
+            )
+            VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10)
+            ON CONFLICT("id") DO UPDATE SET
+                "name"="excluded"."name",
+                "access_key_id"="excluded"."access_key_id",
+                "secret_access_key"="excluded"."secret_access_key",
+                "bucket"="excluded"."bucket",
+                "region"="excluded"."region",
+                "path"="excluded"."path",
+                "endpoint"="excluded"."endpoint",
+                "public_url"="excluded"."public_url"
+            "#,
+            &self.id,
+            &self.name,
+            &self.access_key_id,
+            &self.secret_access_key,
+            &self.bucket,
+            &self.region,
+            self.path.as_deref(),
+            self.endpoint.as_deref(),
+            self.public_url.as_deref(),
+            &self._owner,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Counts the catalogs stored in the storage.
+    #[instrument(skip(conn))]
+    pub(crate) async fn catalog_count(conn: &mut DbConnection<'_>, id: &str) -> Result<i64> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM "catalog"
+            WHERE "storage"=$1
+            "#,
+            id
+        )
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Deletes the storage. Storage that is still used by a catalog cannot be
+    /// deleted.
+    #[instrument(skip(conn))]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, id: &str) -> Result {
+        let catalogs = Self::catalog_count(conn, id).await?;
+
+        if catalogs > 0 {
+            return Err(Error::InvalidData {
+                message: format!("Storage is still used by {catalogs} catalogs"),
+            });
+        }
+
+        sqlx::query!(r#"DELETE FROM "storage" WHERE "id"=$1"#, id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct Catalog {
     pub(crate) id: String,
     pub(crate) name: String,
     pub(crate) storage: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct UserCatalog {
     #[serde(flatten)]
@@ -512,35 +2211,54 @@ pub(crate) struct UserCatalog {
     pub(crate) writable: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct UserCatalogWithCount {
     #[serde(flatten)]
-    catalog: models::Catalog,
+    pub(crate) catalog: models::Catalog,
     writable: bool,
     media: i64,
 }
 
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct SharedCatalog {
+    pub(crate) user: String,
+    pub(crate) catalog: String,
+    pub(crate) writable: bool,
+}
+
 impl Catalog {
     #[instrument(skip_all)]
+    /// Streams the media in the catalog. When `since` is given only the media
+    /// changed since then is included along with the media deleted.
     pub(crate) async fn stream_media(
         self,
         mut conn: DbConnection<'static>,
+        since: Option<DateTime<Utc>>,
+        page: MediaPage,
         sender: MediaViewSender,
     ) {
-        let stream = sqlx::query!(
+        let changes = match MediaChanges::since(&mut conn, &self.id, since, &page).await {
+            Ok(changes) => changes,
+            Err(e) => return sender.send_error(e).await,
+        };
+
+        let mut builder = QueryBuilder::new(
             r#"
             SELECT "media_view".*
             FROM "media_view"
-            WHERE "media_view"."catalog"=$1
-            ORDER BY "datetime" DESC
-            "#,
-            self.id
-        )
-        .try_map(|row| Ok(from_row!(MediaView(row))))
-        .fetch(&mut conn);
+                JOIN "media_item" ON "media_item"."id"="media_view"."id"
+            WHERE "media_view"."catalog"="#,
+        );
+        builder.push_bind(&self.id);
+        if let Some(since) = since {
+            builder.push(r#" AND "media_item"."updated" >= "#);
+            builder.push_bind(since);
+        }
+        page.bind_page(&mut builder);
 
-        sender.send_stream(stream).await
+        let stream = builder.build_query_as::<MediaView>().fetch(&mut conn);
+        sender.send_stream(stream, &page, changes).await
     }
 
     pub(crate) async fn list(conn: &mut DbConnection<'_>) -> Result<Vec<Catalog>> {
@@ -591,7 +2309,7 @@ impl Catalog {
             WHERE
                 "user_catalog"."user"=$1 AND
                 "catalog"."id"=$2 AND
-                ("user_catalog"."writable" OR $3)
+                ("user_catalog"."writable" OR NOT $3)
             "#,
             email,
             catalog,
@@ -636,11 +2354,158 @@ impl Catalog {
         .fetch_one(conn)
         .await?;
 
-        Ok(catalog)
+        Ok(catalog)
+    }
+
+    /// Gets a catalog that uses storage owned by the user.
+    pub(crate) async fn get_for_owner(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        catalog: &str,
+    ) -> Result<Catalog> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT "catalog".*
+            FROM "catalog" JOIN "storage" ON "storage"."id"="catalog"."storage"
+            WHERE "catalog"."id"=$1 AND "storage"."owner"=$2
+            "#,
+            catalog,
+            email
+        )
+        .map(|row| from_row!(Catalog(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn create_or_update(&self, conn: &mut DbConnection<'_>) -> Result {
+        sqlx::query!(
+            r#"
+            INSERT INTO "catalog" ("id", "name", "storage")
+            VALUES ($1,$2,$3)
+            ON CONFLICT("id") DO UPDATE SET
+                "name"="excluded"."name"
+            "#,
+            &self.id,
+            &self.name,
+            &self.storage,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the catalog. Catalogs that still contain media cannot be
+    /// deleted.
+    #[instrument(skip(conn))]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, id: &str) -> Result {
+        let media = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM "media_item"
+            WHERE "catalog"=$1
+            "#,
+            id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if media > 0 {
+            return Err(Error::InvalidData {
+                message: format!("Catalog still contains {media} media items"),
+            });
+        }
+
+        sqlx::query!(r#"DELETE FROM "catalog" WHERE "id"=$1"#, id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn list_shares(
+        &self,
+        conn: &mut DbConnection<'_>,
+    ) -> Result<Vec<SharedCatalog>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "shared_catalog"
+            WHERE "catalog"=$1
+            ORDER BY "user"
+            "#,
+            self.id
+        )
+        .map(|row| from_row!(SharedCatalog(row)))
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Shares the catalog with the given user or changes whether they can
+    /// write to it. Users without an account are created without a password
+    /// so that they can register later. Returns whether a new user was
+    /// created.
+    #[instrument(skip(self, conn), fields(catalog = self.id))]
+    pub(crate) async fn share(
+        &self,
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        writable: bool,
+    ) -> Result<(SharedCatalog, bool)> {
+        let invited = sqlx::query_scalar!(
+            r#"
+            INSERT INTO "user" ("email", "administrator", "created", "verified")
+            VALUES ($1, FALSE, CURRENT_TIMESTAMP, FALSE)
+            ON CONFLICT("email") DO NOTHING
+            RETURNING "email"
+            "#,
+            email
+        )
+        .fetch_optional(&mut *conn)
+        .await?
+        .is_some();
+
+        let shared = sqlx::query!(
+            r#"
+            INSERT INTO "shared_catalog" ("user", "catalog", "writable")
+            VALUES ($1,$2,$3)
+            ON CONFLICT("user", "catalog") DO UPDATE SET
+                "writable"="excluded"."writable"
+            RETURNING *
+            "#,
+            email,
+            self.id,
+            writable
+        )
+        .map(|row| from_row!(SharedCatalog(row)))
+        .fetch_one(conn)
+        .await?;
+
+        Ok((shared, invited))
+    }
+
+    #[instrument(skip(self, conn), fields(catalog = self.id))]
+    pub(crate) async fn unshare(&self, conn: &mut DbConnection<'_>, email: &str) -> Result {
+        let result = sqlx::query!(
+            r#"
+            DELETE FROM "shared_catalog"
+            WHERE "catalog"=$1 AND "user"=$2
+            "#,
+            self.id,
+            email
+        )
+        .execute(conn)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Err(Error::NotFound);
+        }
+
+        Ok(())
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct Person {
     pub(crate) id: String,
     pub(crate) name: String,
@@ -712,9 +2577,136 @@ impl Person {
             }
         }
     }
+
+    pub(crate) async fn get_writable_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<Person> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "person"
+            WHERE "id"=$1 AND "catalog" IN (
+                SELECT "user_catalog"."catalog"
+                FROM "user_catalog"
+                WHERE "user_catalog"."user"=$2 AND "user_catalog"."writable"
+            )
+            "#,
+            id,
+            email
+        )
+        .map(|row| from_row!(Person(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    #[instrument(skip(self, conn), fields(person = self.id))]
+    pub(crate) async fn rename(&mut self, conn: &mut DbConnection<'_>, name: &str) -> Result {
+        if name.is_empty() {
+            return Err(Error::InvalidData {
+                message: "Person name cannot be empty".to_string(),
+            });
+        }
+
+        let conflicts = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM "person"
+            WHERE "catalog"=$1 AND LOWER("name")=$2 AND "id"!=$3
+            "#,
+            self.catalog,
+            name.to_lowercase(),
+            self.id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if conflicts > 0 {
+            return Err(Error::InvalidData {
+                message: format!("A person named {name} already exists, merge them instead"),
+            });
+        }
+
+        sqlx::query!(
+            r#"UPDATE "person" SET "name"=$2 WHERE "id"=$1"#,
+            self.id,
+            name
+        )
+        .execute(conn)
+        .await?;
+
+        name.clone_into(&mut self.name);
+
+        Ok(())
+    }
+
+    /// Merges the given people into this person. Media that included any of
+    /// the merged people will include this person instead. Where the media
+    /// already includes this person without a known location the location of
+    /// the merged person is kept. Saved searches that referenced the merged
+    /// people are updated to reference this person.
+    #[instrument(skip(self, conn), fields(person = self.id))]
+    pub(crate) async fn merge(&self, conn: &mut DbConnection<'_>, people: &[String]) -> Result {
+        let sources = sqlx::query_scalar!(
+            r#"
+            SELECT "id"
+            FROM "person"
+            WHERE "catalog"=$1 AND "id"=ANY($2)
+            "#,
+            self.catalog,
+            people
+        )
+        .fetch_all(&mut *conn)
+        .await?;
+
+        if sources.len() != people.iter().unique().count() {
+            return Err(Error::NotFound);
+        }
+
+        if sources.contains(&self.id) {
+            return Err(Error::InvalidData {
+                message: "A person cannot be merged with themselves".to_string(),
+            });
+        }
+
+        // Each person is moved separately as a single insert cannot update the
+        // same conflicting row twice.
+        for source in sources.iter() {
+            sqlx::query!(
+                r#"
+                INSERT INTO "media_person" ("catalog", "media", "person", "location")
+                SELECT "catalog", "media", $2, "location"
+                FROM "media_person"
+                WHERE "person"=$1
+                ON CONFLICT("media", "person") DO UPDATE SET
+                    "location"=COALESCE("media_person"."location", "excluded"."location")
+                "#,
+                source,
+                self.id
+            )
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        let mut searches = SavedSearch::list_for_catalog(&mut *conn, &self.catalog).await?;
+        searches.retain_mut(|search| search.query.replace_people(&sources, &self.id));
+        SavedSearch::upsert(conn, &searches).await?;
+
+        Person::delete(conn, &sources).await
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, people: &[String]) -> Result {
+        sqlx::query!(r#"DELETE FROM "person" WHERE "id"=ANY($1)"#, people)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct MediaPerson {
     pub(crate) catalog: String,
     pub(crate) media: String,
@@ -723,6 +2715,30 @@ pub(crate) struct MediaPerson {
 }
 
 impl MediaPerson {
+    /// Removes the people from the media.
+    pub(crate) async fn remove(
+        conn: &mut DbConnection<'_>,
+        media: &[String],
+        people: &[String],
+    ) -> Result {
+        if people.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM "media_person"
+            WHERE "media"=ANY($1) AND "person"=ANY($2)
+            "#,
+            media,
+            people
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn replace_for_media(
         conn: &mut DbConnection<'_>,
@@ -800,7 +2816,7 @@ impl MediaPerson {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct Tag {
     pub(crate) id: String,
     pub(crate) parent: Option<String>,
@@ -852,7 +2868,7 @@ impl Tag {
                 r#"
                 SELECT "tag".*
                 FROM "tag"
-                WHERE "tag"."catalog"=$1 AND LOWER("name")=$2
+                WHERE "tag"."catalog"=$1 AND LOWER("name")=$2 AND "parent" IS NULL
                 "#,
                 catalog,
                 name.to_lowercase()
@@ -906,9 +2922,235 @@ impl Tag {
 
         Ok(current_tag)
     }
+
+    pub(crate) async fn get_writable_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<Tag> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "tag"
+            WHERE "id"=$1 AND "catalog" IN (
+                SELECT "user_catalog"."catalog"
+                FROM "user_catalog"
+                WHERE "user_catalog"."user"=$2 AND "user_catalog"."writable"
+            )
+            "#,
+            id,
+            email
+        )
+        .map(|row| from_row!(Tag(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Checks whether `descendent` is this tag or one of its descendents.
+    async fn is_ancestor_of(&self, conn: &mut DbConnection<'_>, descendent: &str) -> Result<bool> {
+        let count = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM "tag_descendent"
+            WHERE "id"=$1 AND "descendent"=$2
+            "#,
+            self.id,
+            descendent
+        )
+        .fetch_one(conn)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Renames this tag and/or moves it to a new parent.
+    #[instrument(skip(self, conn), fields(tag = self.id))]
+    pub(crate) async fn edit(
+        &mut self,
+        conn: &mut DbConnection<'_>,
+        name: &str,
+        parent: Option<&str>,
+    ) -> Result {
+        if name.is_empty() {
+            return Err(Error::InvalidData {
+                message: "Tag name cannot be empty".to_string(),
+            });
+        }
+
+        if let Some(parent) = parent {
+            let exists = sqlx::query_scalar!(
+                r#"
+                SELECT COUNT(*) AS "count!"
+                FROM "tag"
+                WHERE "id"=$1 AND "catalog"=$2
+                "#,
+                parent,
+                self.catalog
+            )
+            .fetch_one(&mut *conn)
+            .await?;
+
+            if exists == 0 {
+                return Err(Error::InvalidData {
+                    message: format!("Unknown parent tag {parent}"),
+                });
+            }
+
+            if self.is_ancestor_of(conn, parent).await? {
+                return Err(Error::InvalidData {
+                    message: "A tag cannot be moved inside itself".to_string(),
+                });
+            }
+        }
+
+        let conflicts = sqlx::query_scalar!(
+            r#"
+            SELECT COUNT(*) AS "count!"
+            FROM "tag"
+            WHERE
+                "catalog"=$1 AND
+                COALESCE("parent", "catalog")=COALESCE($2::text, "catalog") AND
+                LOWER("name")=$3 AND
+                "id"!=$4
+            "#,
+            self.catalog,
+            parent,
+            name.to_lowercase(),
+            self.id
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if conflicts > 0 {
+            return Err(Error::InvalidData {
+                message: format!("A tag named {name} already exists here"),
+            });
+        }
+
+        sqlx::query!(
+            r#"
+            UPDATE "tag"
+            SET "name"=$2, "parent"=$3
+            WHERE "id"=$1
+            "#,
+            self.id,
+            name,
+            parent
+        )
+        .execute(conn)
+        .await?;
+
+        name.clone_into(&mut self.name);
+        self.parent = parent.map(|p| p.to_owned());
+
+        Ok(())
+    }
+
+    /// Merges the given tags into this tag. Media tagged with any of the merged
+    /// tags will be tagged with this tag instead and their children are moved
+    /// beneath this tag, being merged with any existing child of the same name.
+    #[instrument(skip(self, conn), fields(tag = self.id))]
+    pub(crate) async fn merge(&self, conn: &mut DbConnection<'_>, tags: &[String]) -> Result {
+        let sources = sqlx::query!(
+            r#"
+            SELECT *
+            FROM "tag"
+            WHERE "catalog"=$1 AND "id"=ANY($2)
+            "#,
+            self.catalog,
+            tags
+        )
+        .map(|row| from_row!(Tag(row)))
+        .fetch_all(&mut *conn)
+        .await?;
+
+        if sources.len() != tags.iter().unique().count() {
+            return Err(Error::NotFound);
+        }
+
+        for source in sources.iter() {
+            if source.id == self.id {
+                return Err(Error::InvalidData {
+                    message: "A tag cannot be merged with itself".to_string(),
+                });
+            }
+
+            if source.is_ancestor_of(conn, &self.id).await? {
+                return Err(Error::InvalidData {
+                    message: format!("Cannot merge {} into one of its descendents", source.name),
+                });
+            }
+        }
+
+        let mut pending: Vec<(String, String)> = sources
+            .into_iter()
+            .map(|source| (source.id, self.id.clone()))
+            .collect();
+        let mut merged: Vec<String> = Vec::new();
+
+        while let Some((source, target)) = pending.pop() {
+            sqlx::query!(
+                r#"
+                INSERT INTO "media_tag" ("catalog", "media", "tag")
+                SELECT "catalog", "media", $2
+                FROM "media_tag"
+                WHERE "tag"=$1
+                ON CONFLICT DO NOTHING
+                "#,
+                source,
+                target
+            )
+            .execute(&mut *conn)
+            .await?;
+
+            let children = sqlx::query!(
+                r#"
+                SELECT "child"."id", "existing"."id" AS "existing?"
+                FROM "tag" AS "child"
+                    LEFT JOIN "tag" AS "existing" ON
+                        "existing"."parent"=$2 AND
+                        LOWER("existing"."name")=LOWER("child"."name")
+                WHERE "child"."parent"=$1
+                "#,
+                source,
+                target
+            )
+            .fetch_all(&mut *conn)
+            .await?;
+
+            for child in children {
+                match child.existing {
+                    Some(existing) => pending.push((child.id, existing)),
+                    None => {
+                        sqlx::query!(
+                            r#"UPDATE "tag" SET "parent"=$2 WHERE "id"=$1"#,
+                            child.id,
+                            target
+                        )
+                        .execute(&mut *conn)
+                        .await?;
+                    }
+                }
+            }
+
+            merged.push(source);
+        }
+
+        Tag::delete(conn, &merged).await
+    }
+
+    /// Deletes the given tags along with all of their descendents.
+    #[instrument(skip_all)]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, tags: &[String]) -> Result {
+        sqlx::query!(r#"DELETE FROM "tag" WHERE "id"=ANY($1)"#, tags)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct MediaAlbum {
     pub(crate) catalog: String,
     pub(crate) media: String,
@@ -924,8 +3166,15 @@ impl MediaAlbum {
     ) -> Result {
         sqlx::query!(
             r#"
-            DELETE FROM "media_album"
-            WHERE "album"=$1 AND "media"=ANY($2)
+            WITH "removed" AS (
+                DELETE FROM "media_album"
+                WHERE "album"=$1 AND "media"=ANY($2)
+                RETURNING "catalog", "album", "media"
+            )
+            INSERT INTO "media_album_removal" ("catalog", "album", "media")
+            SELECT * FROM "removed"
+            ON CONFLICT ("album", "media") DO UPDATE SET
+                "removed"=CURRENT_TIMESTAMP
             "#,
             album,
             media
@@ -952,8 +3201,15 @@ impl MediaAlbum {
 
         sqlx::query!(
             r#"
-            DELETE FROM "media_album"
-            WHERE "media"=$1 AND "album"!=ALL($2)
+            WITH "removed" AS (
+                DELETE FROM "media_album"
+                WHERE "media"=$1 AND "album"!=ALL($2)
+                RETURNING "catalog", "album", "media"
+            )
+            INSERT INTO "media_album_removal" ("catalog", "album", "media")
+            SELECT * FROM "removed"
+            ON CONFLICT ("album", "media") DO UPDATE SET
+                "removed"=CURRENT_TIMESTAMP
             "#,
             media,
             &album_ids
@@ -1003,7 +3259,7 @@ impl MediaAlbum {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct Album {
     pub(crate) id: String,
     pub(crate) parent: Option<String>,
@@ -1011,55 +3267,76 @@ pub(crate) struct Album {
     pub(crate) catalog: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct AlbumWithCount {
     #[serde(flatten)]
-    album: Album,
+    pub(crate) album: Album,
     media: i64,
 }
 
 impl Album {
     #[instrument(skip_all)]
+    /// Streams the media in the album. When `since` is given only the media
+    /// changed or added to the album since then is included along with the
+    /// media deleted from the album's catalog and removed from the album.
     pub(crate) async fn stream_media(
         self,
         mut conn: DbConnection<'static>,
         recursive: bool,
+        since: Option<DateTime<Utc>>,
+        page: MediaPage,
         sender: MediaViewSender,
     ) {
-        let stream = if recursive {
-            sqlx::query!(
-                r#"
-                SELECT "media_view".*
-                FROM "media_view"
-                WHERE "media_view"."id" IN (
-                    SELECT "media_album"."media"
-                    FROM "media_album"
-                        JOIN "album_descendent" ON "album_descendent"."descendent"="media_album"."album"
-                    WHERE "album_descendent"."id"=$1
-                )
-                ORDER BY datetime DESC
-                "#,
-                self.id
-            )
-            .try_map(|row| Ok(from_row!(MediaView(row))))
-            .fetch(&mut conn)
+        let mut changes = match MediaChanges::since(&mut conn, &self.catalog, since, &page).await {
+            Ok(changes) => changes,
+            Err(e) => return sender.send_error(e).await,
+        };
+
+        if let (Some(changes), Some(since)) = (changes.as_mut(), since) {
+            if let Err(e) = changes
+                .add_album_removals(&mut conn, &self.id, recursive, since)
+                .await
+            {
+                return sender.send_error(e).await;
+            }
+        }
+
+        let mut builder = QueryBuilder::new(
+            r#"
+            SELECT "media_view".*
+            FROM "media_view"
+            WHERE "media_view"."id" IN (
+                SELECT "media_album"."media"
+                FROM "media_album"
+                    JOIN "media_item" ON "media_item"."id"="media_album"."media"
+                WHERE "#,
+        );
+        if recursive {
+            builder.push(
+                r#""media_album"."album" IN (
+                    SELECT "descendent"
+                    FROM "album_descendent"
+                    WHERE "id"="#,
+            );
+            builder.push_bind(&self.id);
+            builder.push(")");
         } else {
-            sqlx::query!(
-                r#"
-                SELECT "media_view".*
-                FROM "media_view"
-                    JOIN "media_album" ON "media_album"."media"="media_view"."id"
-                WHERE "media_album"."album"=$1
-                ORDER BY "datetime" DESC
-                "#,
-                self.id
-            )
-            .try_map(|row| Ok(from_row!(MediaView(row))))
-            .fetch(&mut conn)
-        };
+            builder.push(r#""media_album"."album"="#);
+            builder.push_bind(&self.id);
+        }
+        if let Some(since) = since {
+            builder.push(r#" AND ("media_item"."updated" >= "#);
+            builder.push_bind(since);
+            builder.push(r#" OR "media_album"."added" >= "#);
+            builder.push_bind(since);
+            builder.push(")");
+        }
+        builder.push(")");
+        page.bind_page(&mut builder);
 
-        sender.send_stream(stream).await
+        let stream = builder.build_query_as::<MediaView>().fetch(&mut conn);
+        sender.send_stream(stream, &page, changes).await
     }
 
     pub(crate) async fn list_for_user_with_count<'c, D: AsDb<'c>>(
@@ -1219,8 +3496,55 @@ impl Album {
         Ok(())
     }
 
+    /// Moves the albums, along with their descendents, to the trash. The media
+    /// in the albums and the albums' shares are kept so that they can be
+    /// restored.
     #[instrument(skip_all)]
-    pub(crate) async fn delete(conn: &mut DbConnection<'_>, albums: &[String]) -> Result {
+    pub(crate) async fn trash(conn: &mut DbConnection<'_>, albums: &[String]) -> Result {
+        // Albums that are descendents of another album being deleted are
+        // included in that album's entry.
+        sqlx::query!(
+            r#"
+            INSERT INTO "album_trash" ("id", "catalog", "name", "albums", "media", "shares")
+            SELECT
+                "album"."id",
+                "album"."catalog",
+                "album"."name",
+                (
+                    SELECT jsonb_agg(to_jsonb("trashed"))
+                    FROM "album_descendent"
+                        JOIN "album" AS "trashed" ON "trashed"."id"="album_descendent"."descendent"
+                    WHERE "album_descendent"."id"="album"."id"
+                ),
+                (
+                    SELECT COALESCE(jsonb_agg(to_jsonb("media_album")), '[]')
+                    FROM "album_descendent"
+                        JOIN "media_album" ON "media_album"."album"="album_descendent"."descendent"
+                    WHERE "album_descendent"."id"="album"."id"
+                ),
+                (
+                    SELECT COALESCE(jsonb_agg(to_jsonb("album_share")), '[]')
+                    FROM "album_descendent"
+                        JOIN "album_share" ON "album_share"."album"="album_descendent"."descendent"
+                    WHERE "album_descendent"."id"="album"."id"
+                )
+            FROM "album"
+            WHERE
+                "album"."id"=ANY($1) AND
+                NOT EXISTS (
+                    SELECT 1
+                    FROM "album_descendent"
+                    WHERE
+                        "album_descendent"."descendent"="album"."id" AND
+                        "album_descendent"."id"<>"album"."id" AND
+                        "album_descendent"."id"=ANY($1)
+                )
+            "#,
+            albums
+        )
+        .execute(&mut *conn)
+        .await?;
+
         sqlx::query!(r#"DELETE FROM "album" WHERE "id"=ANY($1)"#, albums)
             .execute(conn)
             .await?;
@@ -1229,7 +3553,300 @@ impl Album {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+/// A deleted album that can still be restored along with its descendents.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct TrashedAlbum {
+    pub(crate) id: String,
+    pub(crate) name: String,
+    pub(crate) catalog: String,
+    pub(crate) trashed: DateTime<Utc>,
+}
+
+impl TrashedAlbum {
+    pub(crate) async fn list(conn: &mut DbConnection<'_>, catalog: &str) -> Result<Vec<Self>> {
+        Ok(sqlx::query_as!(
+            TrashedAlbum,
+            r#"
+            SELECT "id", "name", "catalog", "trashed"
+            FROM "album_trash"
+            WHERE "catalog"=$1
+            ORDER BY "trashed" DESC, "id"
+            "#,
+            catalog
+        )
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub(crate) async fn get_writable_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<Self> {
+        Ok(sqlx::query_as!(
+            TrashedAlbum,
+            r#"
+            SELECT "id", "name", "catalog", "trashed"
+            FROM "album_trash"
+            WHERE "id"=$1 AND "catalog" IN (
+                SELECT "user_catalog"."catalog"
+                FROM "user_catalog"
+                WHERE "user_catalog"."user"=$2 AND "user_catalog"."writable"
+            )
+            "#,
+            id,
+            email
+        )
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Takes albums out of the trash. An album whose parent no longer exists
+    /// is restored at the top level and media that has since been removed is
+    /// skipped.
+    #[instrument(skip_all)]
+    pub(crate) async fn restore(conn: &mut DbConnection<'_>, albums: &[String]) -> Result {
+        sqlx::query!(
+            r#"
+            WITH "restoring" AS (
+                SELECT "trashed".*
+                FROM "album_trash",
+                    jsonb_populate_recordset(NULL::"album", "album_trash"."albums") AS "trashed"
+                WHERE "album_trash"."id"=ANY($1)
+            )
+            INSERT INTO "album" ("id", "parent", "name", "catalog")
+            SELECT
+                "id",
+                CASE
+                    WHEN "parent" IN (SELECT "id" FROM "album" UNION SELECT "id" FROM "restoring")
+                    THEN "parent"
+                END,
+                "name",
+                "catalog"
+            FROM "restoring"
+            ON CONFLICT DO NOTHING
+            "#,
+            albums
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "media_album" ("catalog", "media", "album", "added")
+            SELECT "trashed"."catalog", "trashed"."media", "trashed"."album", "trashed"."added"
+            FROM "album_trash",
+                jsonb_populate_recordset(NULL::"media_album", "album_trash"."media") AS "trashed"
+            WHERE
+                "album_trash"."id"=ANY($1) AND
+                "trashed"."media" IN (SELECT "id" FROM "media_item")
+            ON CONFLICT DO NOTHING
+            "#,
+            albums
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO "album_share" ("id", "access", "album", "recursive", "password", "expiry", "created")
+            SELECT
+                "trashed"."id",
+                "trashed"."access",
+                "trashed"."album",
+                "trashed"."recursive",
+                "trashed"."password",
+                "trashed"."expiry",
+                "trashed"."created"
+            FROM "album_trash",
+                jsonb_populate_recordset(NULL::"album_share", "album_trash"."shares") AS "trashed"
+            WHERE "album_trash"."id"=ANY($1)
+            ON CONFLICT DO NOTHING
+            "#,
+            albums
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query!(r#"DELETE FROM "album_trash" WHERE "id"=ANY($1)"#, albums)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Removes albums that have been in the trash for longer than the retention
+    /// period.
+    pub(crate) async fn purge(conn: &mut DbConnection<'_>, catalog: &str) -> Result {
+        let expiry = trash_expiry(conn.config());
+
+        sqlx::query!(
+            r#"DELETE FROM "album_trash" WHERE "catalog"=$1 AND "trashed" <= $2"#,
+            catalog,
+            expiry
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// A public link to an album that can be used without logging in.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AlbumShare {
+    pub(crate) id: String,
+    #[serde(skip)]
+    pub(crate) access: String,
+    pub(crate) album: String,
+    pub(crate) recursive: bool,
+    #[serde(skip)]
+    pub(crate) password: Option<String>,
+    pub(crate) expiry: Option<DateTime<Utc>>,
+    pub(crate) created: DateTime<Utc>,
+}
+
+impl AlbumShare {
+    /// Whether a password must be given to access the shared media.
+    pub(crate) fn protected(&self) -> bool {
+        self.password.is_some()
+    }
+
+    #[instrument(skip(conn, password))]
+    pub(crate) async fn create(
+        conn: &mut DbConnection<'_>,
+        album: &str,
+        recursive: bool,
+        expiry: Option<DateTime<Utc>>,
+        password: Option<&str>,
+    ) -> Result<AlbumShare> {
+        let id = long_id("L");
+
+        let (access, password_hash) = match password {
+            Some(password) => (
+                long_id("L"),
+                Some(password::hash_password(conn.config(), password).await?),
+            ),
+            None => (id.clone(), None),
+        };
+
+        Ok(sqlx::query!(
+            r#"
+            INSERT INTO "album_share" ("id", "access", "album", "recursive", "password", "expiry")
+            VALUES ($1,$2,$3,$4,$5,$6)
+            RETURNING *
+            "#,
+            id,
+            access,
+            album,
+            recursive,
+            password_hash,
+            expiry,
+        )
+        .map(|row| from_row!(AlbumShare(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn list_for_album(
+        conn: &mut DbConnection<'_>,
+        album: &str,
+    ) -> Result<Vec<AlbumShare>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "album_share"
+            WHERE "album"=$1
+            ORDER BY "created"
+            "#,
+            album
+        )
+        .map(|row| from_row!(AlbumShare(row)))
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Gets a share by its link, whether or not it has expired.
+    pub(crate) async fn get(conn: &mut DbConnection<'_>, id: &str) -> Result<AlbumShare> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "album_share"
+            WHERE "id"=$1
+            "#,
+            id
+        )
+        .map(|row| from_row!(AlbumShare(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Gets an unexpired share from the credential used to access its media.
+    pub(crate) async fn get_for_access(
+        conn: &mut DbConnection<'_>,
+        access: &str,
+    ) -> Result<AlbumShare> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "album_share"
+            WHERE
+                "access"=$1 AND
+                ("expiry" IS NULL OR "expiry" > CURRENT_TIMESTAMP)
+            "#,
+            access
+        )
+        .map(|row| from_row!(AlbumShare(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    /// Checks the password for a protected share and returns the credential
+    /// for accessing its media.
+    #[instrument(skip(conn, password))]
+    pub(crate) async fn unlock(
+        conn: &mut DbConnection<'_>,
+        id: &str,
+        password: &str,
+    ) -> Result<String> {
+        let share = Self::get(conn, id).await?;
+
+        if share.expiry.is_some_and(|expiry| expiry <= Utc::now()) {
+            return Err(Error::NotFound);
+        }
+
+        match share.password {
+            Some(ref hash) if password::check(password, hash).await => Ok(share.access),
+            None => Ok(share.access),
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    pub(crate) async fn get_album(&self, conn: &mut DbConnection<'_>) -> Result<Album> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "album"
+            WHERE "id"=$1
+            "#,
+            self.album
+        )
+        .map(|row| from_row!(Album(row)))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, id: &str) -> Result {
+        sqlx::query!(r#"DELETE FROM "album_share" WHERE "id"=$1"#, id)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Clone, Debug, ToSchema)]
 pub(crate) struct SavedSearch {
     pub(crate) id: String,
     pub(crate) name: String,
@@ -1238,11 +3855,11 @@ pub(crate) struct SavedSearch {
     pub(crate) catalog: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct SavedSearchWithCount {
     #[serde(flatten)]
-    search: models::SavedSearch,
+    pub(crate) search: models::SavedSearch,
     media: i64,
 }
 
@@ -1252,38 +3869,25 @@ impl SavedSearch {
         self,
         mut conn: DbConnection<'_>,
         since: Option<DateTime<Utc>>,
+        page: MediaPage,
         sender: MediaViewSender,
     ) {
-        let stream = if let Some(since) = since {
-            sqlx::query!(
-                r#"
-                SELECT "media_view".*
-                FROM "media_view"
-                    JOIN "media_search" ON "media_search"."media"="media_view"."id"
-                WHERE "media_search"."search"=$1 AND "media_search"."added" > $2
-                ORDER BY "datetime" DESC
-                "#,
-                self.id,
-                since
-            )
-            .try_map(|row| Ok(from_row!(MediaView(row))))
-            .fetch(&mut conn)
-        } else {
-            sqlx::query!(
-                r#"
-                SELECT "media_view".*
-                FROM "media_view"
-                    JOIN "media_search" ON "media_search"."media"="media_view"."id"
-                WHERE "media_search"."search"=$1
-                ORDER BY "datetime" DESC
-                "#,
-                self.id
-            )
-            .try_map(|row| Ok(from_row!(MediaView(row))))
-            .fetch(&mut conn)
-        };
+        let mut builder = QueryBuilder::new(
+            r#"
+            SELECT "media_view".*
+            FROM "media_view"
+                JOIN "media_search" ON "media_search"."media"="media_view"."id"
+            WHERE "media_search"."search"="#,
+        );
+        builder.push_bind(&self.id);
+        if let Some(since) = since {
+            builder.push(r#" AND "media_search"."added" > "#);
+            builder.push_bind(since);
+        }
+        page.bind_page(&mut builder);
 
-        sender.send_stream(stream).await
+        let stream = builder.build_query_as::<MediaView>().fetch(&mut conn);
+        sender.send_stream(stream, &page, None).await
     }
 
     pub(crate) async fn clean_subscriptions(conn: &mut DbConnection<'_>) -> Result {
@@ -1518,10 +4122,11 @@ impl SavedSearch {
         Ok(())
     }
 
-    pub(crate) async fn update_for_catalog(conn: &mut DbConnection<'_>, catalog: &str) -> Result {
-        let was_public = MediaItem::list_public(conn, catalog).await?;
-
-        let searches = sqlx::query!(
+    pub(crate) async fn list_for_catalog(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+    ) -> Result<Vec<SavedSearch>> {
+        Ok(sqlx::query!(
             r#"
             SELECT *
             FROM "saved_search"
@@ -1530,8 +4135,14 @@ impl SavedSearch {
             catalog
         )
         .try_map(|row| Ok(from_row!(SavedSearch(row))))
-        .fetch_all(&mut *conn)
-        .await?;
+        .fetch_all(conn)
+        .await?)
+    }
+
+    pub(crate) async fn update_for_catalog(conn: &mut DbConnection<'_>, catalog: &str) -> Result {
+        let was_public = MediaItem::list_public(conn, catalog).await?;
+
+        let searches = SavedSearch::list_for_catalog(&mut *conn, catalog).await?;
 
         for search in searches {
             search.update(conn).await?;
@@ -1558,7 +4169,39 @@ impl SavedSearch {
             alternates_to_update.push((media_file, media_file_store, alternates));
         }
 
-        models::AlternateFile::sync_for_media_files(conn, alternates_to_update).await
+        models::AlternateFile::sync_for_media_files(conn, alternates_to_update).await
+    }
+
+    pub(crate) async fn get_writable_for_user(
+        conn: &mut DbConnection<'_>,
+        email: &str,
+        id: &str,
+    ) -> Result<SavedSearch> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT *
+            FROM "saved_search"
+            WHERE "id"=$1 AND "catalog" IN (
+                SELECT "user_catalog"."catalog"
+                FROM "user_catalog"
+                WHERE "user_catalog"."user"=$2 AND "user_catalog"."writable"
+            )
+            "#,
+            id,
+            email
+        )
+        .try_map(|row| Ok(from_row!(SavedSearch(row))))
+        .fetch_one(conn)
+        .await?)
+    }
+
+    #[instrument(skip_all)]
+    pub(crate) async fn delete(conn: &mut DbConnection<'_>, searches: &[String]) -> Result {
+        sqlx::query!(r#"DELETE FROM "saved_search" WHERE "id"=ANY($1)"#, searches)
+            .execute(conn)
+            .await?;
+
+        Ok(())
     }
 
     pub(crate) async fn get_for_user(
@@ -1668,6 +4311,30 @@ pub(crate) struct MediaTag {
 }
 
 impl MediaTag {
+    /// Removes the tags from the media.
+    pub(crate) async fn remove(
+        conn: &mut DbConnection<'_>,
+        media: &[String],
+        tags: &[String],
+    ) -> Result {
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        sqlx::query!(
+            r#"
+            DELETE FROM "media_tag"
+            WHERE "media"=ANY($1) AND "tag"=ANY($2)
+            "#,
+            media,
+            tags
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn replace_for_media(
         conn: &mut DbConnection<'_>,
@@ -1731,7 +4398,7 @@ impl MediaTag {
     }
 }
 
-#[derive(Serialize, PartialEq, Default, Clone, Debug)]
+#[derive(Serialize, PartialEq, Default, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MediaMetadata {
     pub filename: Option<String>,
@@ -1825,6 +4492,19 @@ impl MediaMetadata {
     }
 }
 
+fn trash_expiry(config: &Config) -> DateTime<Utc> {
+    Utc::now() - Duration::from_std(config.trash_retention).unwrap_or(Duration::days(30))
+}
+
+/// A deleted media item that can still be restored.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TrashedMedia {
+    pub(crate) id: String,
+    pub(crate) file_name: Option<String>,
+    pub(crate) trashed: DateTime<Utc>,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct MediaItem {
     pub id: String,
@@ -1877,17 +4557,22 @@ impl MediaItem {
         .await?)
     }
 
+    /// Lists the deleted media that has been in the trash for longer than the
+    /// retention period.
     pub(crate) async fn list_deleted(
         conn: &mut DbConnection<'_>,
         catalog: &str,
     ) -> Result<Vec<MediaItem>> {
+        let expiry = trash_expiry(conn.config());
+
         let media = sqlx::query!(
             r#"
             SELECT *
             FROM "media_item"
-            WHERE "deleted" AND "catalog"=$1
+            WHERE "deleted" AND "catalog"=$1 AND "trashed" <= $2
             "#,
-            catalog
+            catalog,
+            expiry
         )
         .map(|row| from_row!(MediaItem(row)))
         .fetch_all(conn)
@@ -1926,9 +4611,15 @@ impl MediaItem {
     pub(crate) async fn mark_deleted(conn: &mut DbConnection<'_>, media: &[String]) -> Result {
         sqlx::query!(
             r#"
-            UPDATE "media_item"
-            SET "deleted"=TRUE
-            WHERE "id"=ANY($1)
+            WITH "deleted" AS (
+                UPDATE "media_item"
+                SET "deleted"=TRUE, "trashed"=CURRENT_TIMESTAMP
+                WHERE "id"=ANY($1) AND NOT "deleted"
+                RETURNING "id", "catalog"
+            )
+            INSERT INTO "media_tombstone" ("id", "catalog")
+            SELECT "id", "catalog" FROM "deleted"
+            ON CONFLICT DO NOTHING
             "#,
             media
         )
@@ -1938,12 +4629,104 @@ impl MediaItem {
         Ok(())
     }
 
+    /// Lists the names of the tags and people on each media item.
+    pub(crate) async fn list_relation_names(
+        conn: &mut DbConnection<'_>,
+        media: &[String],
+    ) -> Result<HashMap<String, (Vec<String>, Vec<String>)>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                "media_item"."id",
+                ARRAY(
+                    SELECT "tag"."name"
+                    FROM "media_tag"
+                        JOIN "tag" ON "tag"."id"="media_tag"."tag"
+                    WHERE "media_tag"."media"="media_item"."id"
+                    ORDER BY "tag"."name"
+                ) AS "tags!",
+                ARRAY(
+                    SELECT "person"."name"
+                    FROM "media_person"
+                        JOIN "person" ON "person"."id"="media_person"."person"
+                    WHERE "media_person"."media"="media_item"."id"
+                    ORDER BY "person"."name"
+                ) AS "people!"
+            FROM "media_item"
+            WHERE "media_item"."id"=ANY($1)
+            "#,
+            media
+        )
+        .map(|row| (row.id, (row.tags, row.people)))
+        .fetch_all(conn)
+        .await?
+        .into_iter()
+        .collect())
+    }
+
+    /// Takes media out of the trash. Returns the catalogs of the restored
+    /// media.
+    pub(crate) async fn restore(
+        conn: &mut DbConnection<'_>,
+        media: &[String],
+    ) -> Result<HashSet<String>> {
+        let catalogs = sqlx::query_scalar!(
+            r#"
+            WITH "restored" AS (
+                UPDATE "media_item"
+                SET "deleted"=FALSE, "trashed"=NULL
+                WHERE "id"=ANY($1) AND "deleted"
+                RETURNING "id", "catalog"
+            ), "tombstone" AS (
+                DELETE FROM "media_tombstone"
+                WHERE "id" IN (SELECT "id" FROM "restored")
+            )
+            SELECT DISTINCT "catalog" FROM "restored"
+            "#,
+            media
+        )
+        .fetch_all(conn)
+        .await?;
+
+        Ok(catalogs.into_iter().collect())
+    }
+
+    /// Lists the media in the catalog's trash, most recently deleted first.
+    pub(crate) async fn list_trash(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+    ) -> Result<Vec<TrashedMedia>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                "media_item"."id",
+                COALESCE("media_item"."filename", "media_file"."file_name") AS "file_name",
+                "media_item"."trashed" AS "trashed!"
+            FROM "media_item"
+                LEFT JOIN "media_file" ON "media_file"."id"="media_item"."media_file"
+            WHERE "media_item"."catalog"=$1 AND "media_item"."deleted"
+            ORDER BY "media_item"."trashed" DESC, "media_item"."id"
+            "#,
+            catalog
+        )
+        .map(|row| TrashedMedia {
+            id: row.id,
+            file_name: row.file_name,
+            trashed: row.trashed,
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn list_prunable(
         conn: &mut DbConnection<'_>,
         catalog: &str,
     ) -> Result<Vec<(MediaItem, MediaItemStore)>> {
-        // Lists the items with no media_files.
+        let expiry = trash_expiry(conn.config());
+
+        // Lists the items with no media_files or that have expired from the
+        // trash.
         let items = sqlx::query!(
             r#"
             SELECT "media_item".*
@@ -1957,10 +4740,14 @@ impl MediaItem {
                         "media_item"."created" < (CURRENT_TIMESTAMP - interval '1 week')
                     )
                     OR
-                    "media_item"."deleted"
+                    (
+                        "media_item"."deleted" AND
+                        "media_item"."trashed" <= $2
+                    )
                 )
             "#,
-            catalog
+            catalog,
+            expiry
         )
         .map(|row| from_row!(MediaItem(row)))
         .fetch_all(conn)
@@ -2033,6 +4820,8 @@ impl MediaItem {
                 "latest_media_file"."uploaded" AS "media_file_uploaded",
                 "latest_media_file"."file_name" AS "media_file_file_name",
                 "latest_media_file"."file_size" AS "media_file_file_size",
+                "latest_media_file"."sha256" AS "media_file_sha256",
+                "latest_media_file"."phash" AS "media_file_phash",
                 "latest_media_file"."mimetype" AS "media_file_mimetype",
                 "latest_media_file"."width" AS "media_file_width",
                 "latest_media_file"."height" AS "media_file_height",
@@ -2347,12 +5136,52 @@ impl MediaItem {
     }
 }
 
+/// Media items in a catalog that have identical files.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct DuplicateMedia {
+    pub(crate) sha256: String,
+    pub(crate) media: Vec<String>,
+}
+
+/// A file uploaded for a media item, either the current file or one that the
+/// media item can be reverted to.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct MediaFileVersion {
+    #[serde(flatten)]
+    pub(crate) file: MediaViewFile,
+    pub(crate) current: bool,
+}
+
+/// The largest distance between perceptual hashes that is recorded in
+/// `similar_media_file`.
+pub(crate) const MAX_SIMILARITY_DISTANCE: i64 = 16;
+
+/// A media item whose image looks similar to another. The distance is the
+/// number of bits that differ between the perceptual hashes.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct SimilarMedia {
+    pub(crate) media: String,
+    pub(crate) distance: i32,
+}
+
+/// A pair of media items in a catalog whose images look similar.
+#[derive(Serialize, Clone, Debug, ToSchema)]
+pub(crate) struct SimilarMediaPair {
+    pub(crate) media: String,
+    pub(crate) similar: String,
+    pub(crate) distance: i32,
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct MediaFile {
     pub(crate) id: String,
     pub(crate) uploaded: DateTime<Utc>,
     pub(crate) file_name: String,
     pub(crate) file_size: i64,
+    /// The hex encoded SHA-256 of the file. Unknown for older files.
+    pub(crate) sha256: Option<String>,
+    /// A perceptual hash of the decoded image, used to find similar media.
+    pub(crate) phash: Option<i64>,
     pub(crate) mimetype: Mime,
     pub(crate) width: i32,
     pub(crate) height: i32,
@@ -2371,7 +5200,13 @@ fn make_safe(name: &str) -> String {
 }
 
 impl MediaFile {
-    pub(crate) fn new(media_item: &str, file_name: &str, file_size: i64, mimetype: &Mime) -> Self {
+    pub(crate) fn new(
+        media_item: &str,
+        file_name: &str,
+        file_size: i64,
+        sha256: &str,
+        mimetype: &Mime,
+    ) -> Self {
         Self {
             id: short_id("I"),
             uploaded: Utc::now(),
@@ -2379,6 +5214,8 @@ impl MediaFile {
             stored: None,
             file_name: make_safe(file_name),
             file_size,
+            sha256: Some(sha256.to_owned()),
+            phash: None,
             mimetype: mimetype.to_owned(),
             width: 0,
             height: 0,
@@ -2396,6 +5233,8 @@ impl MediaFile {
         uploaded: Option<DateTime<Utc>>,
         file_name: Option<String>,
         file_size: Option<i64>,
+        sha256: Option<String>,
+        phash: Option<i64>,
         mimetype: Option<String>,
         width: Option<i32>,
         height: Option<i32>,
@@ -2457,6 +5296,8 @@ impl MediaFile {
                 stored,
                 file_name,
                 file_size,
+                sha256,
+                phash,
                 mimetype: from_mime(&mimetype)?,
                 width,
                 height,
@@ -2528,6 +5369,302 @@ impl MediaFile {
         Ok(())
     }
 
+    /// Finds a media item in the catalog with a file that has the given hash.
+    /// Only the current file of each media item and any newer files waiting
+    /// to be processed are considered.
+    pub(crate) async fn find_duplicate(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+        sha256: &str,
+    ) -> Result<Option<String>> {
+        Ok(sqlx::query_scalar!(
+            r#"
+            SELECT "media_item"."id"
+            FROM "media_file"
+                JOIN "media_item" ON "media_item"."id"="media_file"."media_item"
+                LEFT JOIN "media_file" AS "current_file" ON "current_file"."id"="media_item"."media_file"
+            WHERE
+                "media_item"."catalog"=$1 AND
+                NOT "media_item"."deleted" AND
+                "media_file"."sha256"=$2 AND
+                (
+                    "current_file"."id" IS NULL OR
+                    "media_file"."id"="current_file"."id" OR
+                    "media_file"."uploaded" > COALESCE("current_file"."reverted", "current_file"."uploaded")
+                )
+            ORDER BY "media_item"."created"
+            LIMIT 1
+            "#,
+            catalog,
+            sha256
+        )
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Lists the groups of media items in the catalog that have identical
+    /// files, considering the same files as `find_duplicate`.
+    pub(crate) async fn list_duplicates(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+    ) -> Result<Vec<DuplicateMedia>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                "media_file"."sha256" AS "sha256!",
+                ARRAY_AGG(DISTINCT "media_item"."id") AS "media!"
+            FROM "media_file"
+                JOIN "media_item" ON "media_item"."id"="media_file"."media_item"
+                LEFT JOIN "media_file" AS "current_file" ON "current_file"."id"="media_item"."media_file"
+            WHERE
+                "media_item"."catalog"=$1 AND
+                NOT "media_item"."deleted" AND
+                "media_file"."sha256" IS NOT NULL AND
+                (
+                    "current_file"."id" IS NULL OR
+                    "media_file"."id"="current_file"."id" OR
+                    "media_file"."uploaded" > COALESCE("current_file"."reverted", "current_file"."uploaded")
+                )
+            GROUP BY "media_file"."sha256"
+            HAVING COUNT(DISTINCT "media_item"."id") > 1
+            ORDER BY "media_file"."sha256"
+            "#,
+            catalog
+        )
+        .map(|row| DuplicateMedia {
+            sha256: row.sha256,
+            media: row.media,
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Lists the files kept for the media item, most recently uploaded or
+    /// reverted to first.
+    pub(crate) async fn list_versions(
+        conn: &mut DbConnection<'_>,
+        media_item: &str,
+    ) -> Result<Vec<MediaFileVersion>> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                "media_file"."id",
+                "media_file"."file_size",
+                "media_file"."mimetype",
+                "media_file"."width",
+                "media_file"."height",
+                "media_file"."duration",
+                "media_file"."frame_rate",
+                "media_file"."bit_rate",
+                "media_file"."uploaded",
+                "media_file"."file_name",
+                COALESCE("media_file_alternates"."alternates", '[]'::json) AS "alternates!",
+                "media_file"."id" IS NOT DISTINCT FROM "media_item"."media_file" AS "current!"
+            FROM "media_file"
+                JOIN "media_item" ON "media_item"."id"="media_file"."media_item"
+                LEFT JOIN "media_file_alternates" ON "media_file_alternates"."media_file"="media_file"."id"
+            WHERE "media_file"."media_item"=$1
+            ORDER BY COALESCE("media_file"."reverted", "media_file"."uploaded") DESC
+            "#,
+            media_item
+        )
+        .fetch_all(conn)
+        .await?;
+
+        let mut versions = Vec::new();
+        for row in rows {
+            if let Some(file) = MediaViewFile::from_maybe(
+                Some(row.id),
+                Some(row.file_size),
+                Some(row.mimetype),
+                Some(row.width),
+                Some(row.height),
+                row.duration,
+                row.frame_rate,
+                row.bit_rate,
+                Some(row.uploaded),
+                Some(row.file_name),
+                Some(row.alternates),
+            )? {
+                versions.push(MediaFileVersion {
+                    file,
+                    current: row.current,
+                });
+            }
+        }
+
+        Ok(versions)
+    }
+
+    /// Makes the file the latest file for its media item. Only files that
+    /// have been fully processed can be reverted to.
+    pub(crate) async fn revert(
+        conn: &mut DbConnection<'_>,
+        media_item: &str,
+        media_file: &str,
+    ) -> Result {
+        sqlx::query_scalar!(
+            r#"
+            UPDATE "media_file"
+            SET "reverted"=CURRENT_TIMESTAMP
+            WHERE
+                "id"=$1 AND
+                "media_item"=$2 AND
+                NOT "needs_metadata" AND
+                "id" NOT IN (
+                    SELECT "media_file"
+                    FROM "alternate_file"
+                    WHERE "stored" IS NULL AND "required"
+                )
+            RETURNING "id"
+            "#,
+            media_file,
+            media_item
+        )
+        .fetch_optional(conn)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+        Ok(())
+    }
+
+    /// Records which files of the other media items in the catalog are
+    /// similar to this file. Called once the file's perceptual hash is known.
+    pub(crate) async fn record_similar(&self, conn: &mut DbConnection<'_>) -> Result {
+        let Some(phash) = self.phash else {
+            return Ok(());
+        };
+
+        sqlx::query!(
+            r#"
+            WITH "pairs" AS (
+                SELECT
+                    "other_file"."id",
+                    BIT_COUNT(($1 # "other_file"."phash")::bit(64))::integer AS "distance"
+                FROM "media_item"
+                    JOIN "media_item" AS "other" ON
+                        "other"."catalog"="media_item"."catalog" AND
+                        "other"."id"<>"media_item"."id"
+                    JOIN "media_file" AS "other_file" ON "other_file"."media_item"="other"."id"
+                WHERE
+                    "media_item"."id"=$2 AND
+                    BIT_COUNT(($1 # "other_file"."phash")::bit(64)) <= $3
+            )
+            INSERT INTO "similar_media_file" ("media_file", "similar", "distance")
+            SELECT $4, "id", "distance" FROM "pairs"
+            UNION ALL
+            SELECT "id", $4, "distance" FROM "pairs"
+            ON CONFLICT ("media_file", "similar") DO UPDATE SET
+                "distance"="excluded"."distance"
+            "#,
+            phash,
+            self.media_item,
+            MAX_SIMILARITY_DISTANCE,
+            self.id,
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Returns the file if its perceptual hash was computed before similar
+    /// files were recorded, clearing the flag so it is only returned once.
+    pub(crate) async fn take_similar_pending(
+        conn: &mut DbConnection<'_>,
+        media_file: &str,
+    ) -> Result<Option<MediaFile>> {
+        Ok(sqlx::query!(
+            r#"
+            UPDATE "media_file"
+            SET "similar_pending"=FALSE
+            WHERE "id"=$1 AND "similar_pending"
+            RETURNING *
+            "#,
+            media_file
+        )
+        .try_map(|row| Ok(from_row!(MediaFile(row))))
+        .fetch_optional(conn)
+        .await?)
+    }
+
+    /// Lists the media items in the same catalog whose current files are
+    /// within `distance` bits of the given media item's current file, closest
+    /// first. Distances above `MAX_SIMILARITY_DISTANCE` are not recorded.
+    pub(crate) async fn list_similar(
+        conn: &mut DbConnection<'_>,
+        media_item: &str,
+        distance: i64,
+    ) -> Result<Vec<SimilarMedia>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                "other"."id" AS "media",
+                "similar_media_file"."distance"
+            FROM "media_item"
+                JOIN "similar_media_file" ON "similar_media_file"."media_file"="media_item"."media_file"
+                JOIN "media_file" AS "other_file" ON "other_file"."id"="similar_media_file"."similar"
+                JOIN "media_item" AS "other" ON
+                    "other"."id"="other_file"."media_item" AND
+                    "other"."media_file"="other_file"."id"
+            WHERE
+                "media_item"."id"=$1 AND
+                "other"."catalog"="media_item"."catalog" AND
+                NOT "other"."deleted" AND
+                "similar_media_file"."distance" <= $2::bigint
+            ORDER BY 2, "other"."created"
+            "#,
+            media_item,
+            distance
+        )
+        .map(|row| SimilarMedia {
+            media: row.media,
+            distance: row.distance,
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
+    /// Lists the pairs of media items in the catalog whose current files are
+    /// within `distance` bits of each other, closest first.
+    pub(crate) async fn list_similar_pairs(
+        conn: &mut DbConnection<'_>,
+        catalog: &str,
+        distance: i64,
+    ) -> Result<Vec<SimilarMediaPair>> {
+        Ok(sqlx::query!(
+            r#"
+            SELECT
+                "media_item"."id" AS "media",
+                "other"."id" AS "similar",
+                "similar_media_file"."distance"
+            FROM "media_item"
+                JOIN "similar_media_file" ON "similar_media_file"."media_file"="media_item"."media_file"
+                JOIN "media_file" AS "other_file" ON "other_file"."id"="similar_media_file"."similar"
+                JOIN "media_item" AS "other" ON
+                    "other"."id"="other_file"."media_item" AND
+                    "other"."media_file"="other_file"."id"
+            WHERE
+                "media_item"."catalog"=$1 AND
+                "other"."catalog"=$1 AND
+                "other"."id">"media_item"."id" AND
+                NOT "media_item"."deleted" AND
+                NOT "other"."deleted" AND
+                "similar_media_file"."distance" <= $2::bigint
+            ORDER BY 3, 1, 2
+            "#,
+            catalog,
+            distance
+        )
+        .map(|row| SimilarMediaPair {
+            media: row.media,
+            similar: row.similar,
+            distance: row.distance,
+        })
+        .fetch_all(conn)
+        .await?)
+    }
+
     #[instrument(skip_all)]
     pub(crate) async fn list_for_items(
         conn: &mut DbConnection<'_>,
@@ -2641,6 +5778,9 @@ impl MediaFile {
                 (
                     "media_file"."stored" IS NULL OR
                     "media_file"."needs_metadata" OR
+                    "media_file"."sha256" IS NULL OR
+                    "media_file"."phash" IS NULL OR
+                    "media_file"."similar_pending" OR
                     "media_file"."id" IN (
                         SELECT "media_file"
                         FROM "alternate_file"
@@ -2660,18 +5800,35 @@ impl MediaFile {
         conn: &mut DbConnection<'_>,
         catalog: &str,
     ) -> Result<Vec<(MediaFile, MediaFileStore)>> {
+        let history = i64::from(conn.config().media_file_history);
+
+        // Files older than the current file are kept until there are more
+        // than the configured number of them.
         let files = sqlx::query!(
             r#"
+            WITH "previous" AS (
+                SELECT
+                    "media_file"."id",
+                    ROW_NUMBER() OVER (
+                        PARTITION BY "media_file"."media_item"
+                        ORDER BY COALESCE("media_file"."reverted", "media_file"."uploaded") DESC
+                    ) AS "position"
+                FROM "media_file"
+                    JOIN "media_item" ON "media_item"."id"="media_file"."media_item"
+                    JOIN "media_file" AS "current_file" ON "current_file"."id"="media_item"."media_file"
+                WHERE
+                    "media_item"."catalog"=$1 AND
+                    "media_file"."id" <> "current_file"."id" AND
+                    COALESCE("media_file"."reverted", "media_file"."uploaded") <
+                        COALESCE("current_file"."reverted", "current_file"."uploaded")
+            )
             SELECT "media_file".*
             FROM "media_file"
-                JOIN "media_item" ON "media_item"."id"="media_file"."media_item"
-                JOIN "media_file" AS "current_file" ON "current_file"."id"="media_item"."media_file"
-            WHERE
-                "media_item"."catalog"=$1 AND
-                "media_file"."id" <> "current_file"."id" AND
-                "media_file"."uploaded" < "current_file"."uploaded"
+                JOIN "previous" ON "previous"."id"="media_file"."id"
+            WHERE "previous"."position" > $2
             "#,
-            catalog
+            catalog,
+            history
         )
         .try_map(|row| Ok(from_row!(MediaFile(row))))
         .fetch_all(conn)
@@ -2722,11 +5879,11 @@ impl MediaFile {
 
     pub(crate) async fn get_for_user_media(
         conn: &mut DbConnection<'_>,
-        email: Option<&str>,
+        credentials: MediaCredentials<'_>,
         media: &str,
         file: &str,
     ) -> Result<(MediaFile, MediaFileStore)> {
-        let email = email.unwrap_or_default().to_owned();
+        let email = credentials.email.unwrap_or_default().to_owned();
 
         let (media_file, catalog) = sqlx::query!(
             r#"
@@ -2738,12 +5895,18 @@ impl MediaFile {
                 "media_item"."id"=$1 AND
                 "media_file"."id"=$2 AND
                 (
+                    $5 OR
                     "media_item"."id" IN (
                         SELECT "media_search"."media"
                         FROM "saved_search"
                             JOIN "media_search" ON "media_search"."search"="saved_search"."id"
                         WHERE "saved_search"."shared"
                     ) OR
+                    "media_item"."id" IN (
+                        SELECT "media"
+                        FROM "album_share_media"
+                        WHERE "access"=$4
+                    ) OR
                     "media_item"."catalog" IN (
                         SELECT "catalog"
                         FROM "user_catalog"
@@ -2753,7 +5916,9 @@ impl MediaFile {
             "#,
             media,
             file,
-            email
+            email,
+            credentials.share,
+            credentials.signed,
         )
         .try_map(|row| Ok((from_row!(MediaFile(row)), row.catalog)))
         .fetch_one(conn)
@@ -2827,6 +5992,8 @@ impl MediaFile {
             let mut uploaded = Vec::<DateTime<Utc>>::new();
             let mut file_name = Vec::<String>::new();
             let mut file_size = Vec::<i64>::new();
+            let mut sha256 = Vec::<Option<String>>::new();
+            let mut phash = Vec::<Option<i64>>::new();
             let mut mimetype = Vec::<String>::new();
             let mut width = Vec::<i32>::new();
             let mut height = Vec::<i32>::new();
@@ -2866,6 +6033,8 @@ impl MediaFile {
                 uploaded.push(media_file.uploaded);
                 file_name.push(media_file.file_name.clone());
                 file_size.push(media_file.file_size);
+                sha256.push(media_file.sha256.clone());
+                phash.push(media_file.phash);
                 mimetype.push(media_file.mimetype.to_string());
                 width.push(media_file.width);
                 height.push(media_file.height);
@@ -2940,7 +6109,9 @@ impl MediaFile {
                     "altitude",
                     "aperture",
                     "focal_length",
-                    "taken"
+                    "taken",
+                    "sha256",
+                    "phash"
                 )
                 SELECT * FROM UNNEST(
                     $1::text[],
@@ -2979,7 +6150,9 @@ impl MediaFile {
                     $33::real[],
                     $34::real[],
                     $35::real[],
-                    $36::timestamp[]
+                    $36::timestamp[],
+                    $37::text[],
+                    $38::bigint[]
                 )
                 ON CONFLICT (id) DO UPDATE SET
                     "uploaded"="excluded"."uploaded",
@@ -3017,7 +6190,9 @@ impl MediaFile {
                     "altitude"="excluded"."altitude",
                     "aperture"="excluded"."aperture",
                     "focal_length"="excluded"."focal_length",
-                    "taken"="excluded"."taken"
+                    "taken"="excluded"."taken",
+                    "sha256"="excluded"."sha256",
+                    "phash"="excluded"."phash"
                 "#,
                 &id,
                 &uploaded,
@@ -3054,7 +6229,9 @@ impl MediaFile {
                 &altitude as &[Option<f32>],
                 &aperture as &[Option<f32>],
                 &focal_length as &[Option<f32>],
-                &taken as &[Option<NaiveDateTime>]
+                &taken as &[Option<NaiveDateTime>],
+                &sha256 as &[Option<String>],
+                &phash as &[Option<i64>]
             )
             .execute(&mut *conn)
             .await?;
@@ -3282,13 +6459,13 @@ impl AlternateFile {
 
     pub(crate) async fn list_for_user_media(
         conn: &mut DbConnection<'_>,
-        email: Option<&str>,
+        credentials: MediaCredentials<'_>,
         item: &str,
         file: &str,
         mimetype: &Mime,
         alternate_type: AlternateFileType,
     ) -> Result<Vec<(AlternateFile, FilePath)>> {
-        let email = email.unwrap_or_default().to_owned();
+        let email = credentials.email.unwrap_or_default().to_owned();
 
         let files = sqlx::query!(
             r#"
@@ -3302,12 +6479,18 @@ impl AlternateFile {
                 "alternate_file"."mimetype"=$3 AND
                 "alternate_file"."type"=$4 AND
                 (
+                    $7 OR
                     "media_item"."public" OR
                     "media_search"."search" IN (
                         SELECT "id"
                         FROM "saved_search"
                         WHERE "shared"
                     ) OR
+                    "media_item"."id" IN (
+                        SELECT "media"
+                        FROM "album_share_media"
+                        WHERE "access"=$6
+                    ) OR
                     "media_item"."catalog" IN (
                         SELECT "catalog"
                         FROM "user_catalog"
@@ -3319,7 +6502,9 @@ impl AlternateFile {
             file,
             &mimetype.to_string(),
             &alternate_type.to_string(),
-            email
+            email,
+            credentials.share,
+            credentials.signed,
         )
         .try_map(|row| {
             let file_path = FilePath {
@@ -3469,22 +6654,24 @@ impl AlternateFile {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub(crate) struct MediaViewFileAlternate {
     #[serde(rename = "type")]
     pub(crate) type_: AlternateFileType,
     #[serde(with = "crate::shared::mime")]
+    #[schema(value_type = String)]
     pub(crate) mimetype: Mime,
     pub(crate) width: i32,
     pub(crate) height: i32,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MediaViewFile {
     pub(crate) id: String,
     pub(crate) file_size: i64,
     #[serde(with = "crate::shared::mime")]
+    #[schema(value_type = String)]
     pub(crate) mimetype: Mime,
     pub(crate) width: i32,
     pub(crate) height: i32,
@@ -3552,7 +6739,7 @@ impl MediaViewFile {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MediaView {
     pub(crate) id: String,
@@ -3569,7 +6756,7 @@ pub(crate) struct MediaView {
 
 impl MediaView {
     pub(crate) fn amend_for_access(&mut self, access: MediaAccess) {
-        if access == MediaAccess::PublicMedia {
+        if matches!(access, MediaAccess::PublicMedia | MediaAccess::SharedAlbum) {
             self.metadata.longitude = None;
             self.metadata.latitude = None;
             self.metadata.altitude = None;
@@ -3610,21 +6797,21 @@ impl<'r> FromRow<'r, PgRow> for MediaView {
     }
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub(crate) struct AlbumRelation {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) id: Option<String>,
     pub(crate) name: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub(crate) struct TagRelation {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) id: Option<String>,
     pub(crate) name: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Copy, Debug, sqlx::Type)]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, sqlx::Type, ToSchema)]
 #[sqlx(type_name = "location")]
 pub(crate) struct Location {
     pub(crate) left: f32,
@@ -3654,7 +6841,7 @@ where
     Option::<Location>::deserialize(deserializer).or(Ok(None))
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub(crate) struct PersonRelation {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) id: Option<String>,
@@ -3663,14 +6850,14 @@ pub(crate) struct PersonRelation {
     pub(crate) location: Option<Location>,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, ToSchema)]
 pub(crate) struct SearchRelation {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) id: Option<String>,
     pub(crate) name: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 #[serde(transparent)]
 pub(crate) struct MaybeVec<T>(Vec<T>);
 
@@ -3686,7 +6873,7 @@ impl<T: DeserializeOwned> TryFrom<Option<Value>> for MaybeVec<T> {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct Relations {
     pub(crate) albums: Vec<AlbumRelation>,
@@ -3721,7 +6908,7 @@ impl Relations {
     }
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone, Debug, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MediaRelations {
     #[serde(flatten)]
@@ -3736,6 +6923,7 @@ impl MediaRelations {
         conn: &mut DbConnection<'_>,
         email: Option<&str>,
         search: Option<&str>,
+        share: Option<&str>,
         media: &[String],
     ) -> Result<Vec<MediaRelations>> {
         let email = email.unwrap_or_default().to_owned();
@@ -3758,7 +6946,12 @@ impl MediaRelations {
                         "saved_search"."id"=$1 OR
                         $1 IS NULL
                     )
-                ) AS "in_public_search"
+                ) AS "in_public_search",
+                "media_view"."id" IN (
+                    SELECT "media"
+                    FROM "album_share_media"
+                    WHERE "access"=$4
+                ) AS "in_shared_album"
             FROM "media_view"
                 LEFT JOIN "user_catalog" ON "user_catalog"."catalog"="media_view"."catalog" AND "user_catalog"."user"=$2
                 LEFT JOIN "album_relation" ON "album_relation"."media"="media_view"."id"
@@ -3769,43 +6962,59 @@ impl MediaRelations {
             "#,
             search,
             email,
-            media
+            media,
+            share,
         ).try_map(|row| Ok((
             from_row!(MediaView(row)),
             row.writable,
             from_row!(Relations(row)),
-            row.in_public_search.unwrap_or_default()
+            row.in_public_search.unwrap_or_default(),
+            row.in_shared_album.unwrap_or_default(),
         ))).fetch_all(conn).await?;
 
         Ok(media
             .into_iter()
-            .filter_map(|(mut media, writable, mut relations, in_public_search)| {
-                let access = match (writable, media.public, search.is_some(), in_public_search) {
-                    (Some(true), _, _, _) => MediaAccess::WritableCatalog,
-                    (Some(false), _, _, _) => MediaAccess::ReadableCatalog,
-                    (_, _, true, true) => MediaAccess::PublicSearch,
-                    (_, true, _, _) => MediaAccess::PublicMedia,
-                    _ => return None,
-                };
+            .filter_map(
+                |(mut media, writable, mut relations, in_public_search, in_shared_album)| {
+                    let access = match (
+                        writable,
+                        media.public,
+                        search.is_some(),
+                        in_public_search,
+                        in_shared_album,
+                    ) {
+                        (Some(true), _, _, _, _) => MediaAccess::WritableCatalog,
+                        (Some(false), _, _, _, _) => MediaAccess::ReadableCatalog,
+                        (_, _, true, true, _) => MediaAccess::PublicSearch,
+                        (_, _, _, _, true) => MediaAccess::SharedAlbum,
+                        (_, true, _, _, _) => MediaAccess::PublicMedia,
+                        _ => return None,
+                    };
 
-                media.amend_for_access(access);
+                    media.amend_for_access(access);
 
-                if matches!(access, MediaAccess::PublicSearch | MediaAccess::PublicMedia) {
-                    relations.tags.iter_mut().for_each(|r| r.id = None);
-                    relations.albums = vec![];
-                    relations.searches = vec![];
+                    if matches!(
+                        access,
+                        MediaAccess::PublicSearch
+                            | MediaAccess::PublicMedia
+                            | MediaAccess::SharedAlbum
+                    ) {
+                        relations.tags.iter_mut().for_each(|r| r.id = None);
+                        relations.albums = vec![];
+                        relations.searches = vec![];
 
-                    if access == MediaAccess::PublicMedia {
-                        relations.people = vec![];
+                        if matches!(access, MediaAccess::PublicMedia | MediaAccess::SharedAlbum) {
+                            relations.people = vec![];
+                        }
                     }
-                }
 
-                Some(MediaRelations {
-                    media,
-                    access,
-                    relations,
-                })
-            })
+                    Some(MediaRelations {
+                        media,
+                        access,
+                        relations,
+                    })
+                },
+            )
             .collect())
     }
 }
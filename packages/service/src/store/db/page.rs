@@ -0,0 +1,269 @@
+//! Sorting and cursor based pagination of streamed media.
+use std::{fmt, num::NonZeroU32};
+
+use chrono::{DateTime, Utc};
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use serde_json::{from_slice, from_value, to_vec, Value};
+use sqlx::QueryBuilder;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::store::db::{models::MediaView, SqlxDatabase};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) enum MediaSort {
+    #[default]
+    Datetime,
+    Created,
+    Rating,
+    Filename,
+}
+
+impl MediaSort {
+    /// The expression sorted on. Missing values sort before everything else.
+    fn expression(&self) -> &'static str {
+        match self {
+            MediaSort::Datetime => r#""media_view"."datetime""#,
+            MediaSort::Created => r#""media_view"."created""#,
+            MediaSort::Rating => r#"COALESCE("media_view"."rating", -1)"#,
+            MediaSort::Filename => r#"COALESCE("media_view"."filename", '')"#,
+        }
+    }
+
+    fn value(&self, media: &MediaView) -> SortValue {
+        match self {
+            MediaSort::Datetime => SortValue::Timestamp(media.datetime),
+            MediaSort::Created => SortValue::Timestamp(media.created),
+            MediaSort::Rating => SortValue::Integer(media.metadata.rating.unwrap_or(-1)),
+            MediaSort::Filename => {
+                SortValue::Text(media.metadata.filename.clone().unwrap_or_default())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub(crate) enum SortOrder {
+    #[serde(rename = "asc")]
+    Ascending,
+    #[default]
+    #[serde(rename = "desc")]
+    Descending,
+}
+
+impl SortOrder {
+    fn keyword(&self) -> &'static str {
+        match self {
+            SortOrder::Ascending => "ASC",
+            SortOrder::Descending => "DESC",
+        }
+    }
+
+    fn comparison(&self) -> &'static str {
+        match self {
+            SortOrder::Ascending => ">",
+            SortOrder::Descending => "<",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum SortValue {
+    Timestamp(DateTime<Utc>),
+    Integer(i32),
+    Text(String),
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedCursor {
+    sort: MediaSort,
+    order: SortOrder,
+    value: Value,
+    id: String,
+}
+
+/// Marks the position of the last item of a page. Clients treat it as an
+/// opaque string.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct PageCursor {
+    sort: MediaSort,
+    order: SortOrder,
+    value: SortValue,
+    id: String,
+}
+
+fn invalid_cursor<E>(_: E) -> String {
+    "invalid cursor".to_string()
+}
+
+impl TryFrom<String> for PageCursor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let bytes = BASE64URL_NOPAD
+            .decode(value.as_bytes())
+            .map_err(invalid_cursor)?;
+        let encoded: EncodedCursor = from_slice(&bytes).map_err(invalid_cursor)?;
+
+        let value = match encoded.sort {
+            MediaSort::Datetime | MediaSort::Created => {
+                SortValue::Timestamp(from_value(encoded.value).map_err(invalid_cursor)?)
+            }
+            MediaSort::Rating => {
+                SortValue::Integer(from_value(encoded.value).map_err(invalid_cursor)?)
+            }
+            MediaSort::Filename => {
+                SortValue::Text(from_value(encoded.value).map_err(invalid_cursor)?)
+            }
+        };
+
+        Ok(PageCursor {
+            sort: encoded.sort,
+            order: encoded.order,
+            value,
+            id: encoded.id,
+        })
+    }
+}
+
+impl fmt::Display for PageCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = match &self.value {
+            SortValue::Timestamp(timestamp) => Value::from(timestamp.to_rfc3339()),
+            SortValue::Integer(integer) => Value::from(*integer),
+            SortValue::Text(text) => Value::from(text.as_str()),
+        };
+
+        let encoded = EncodedCursor {
+            sort: self.sort,
+            order: self.order,
+            value,
+            id: self.id.clone(),
+        };
+
+        f.write_str(&BASE64URL_NOPAD.encode(&to_vec(&encoded).map_err(|_| fmt::Error)?))
+    }
+}
+
+/// Selects a page of a media stream. Without a limit every item is returned.
+#[derive(Debug, Default, Clone, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub(crate) struct MediaPage {
+    #[serde(default)]
+    #[param(inline)]
+    sort: MediaSort,
+    #[serde(default)]
+    #[param(inline)]
+    order: SortOrder,
+    #[param(value_type = Option<u32>, minimum = 1)]
+    limit: Option<NonZeroU32>,
+    /// The cursor from the end of the previous page. The sort and order of
+    /// the previous page are used regardless of the other parameters.
+    #[param(value_type = Option<String>)]
+    cursor: Option<PageCursor>,
+}
+
+impl MediaPage {
+    pub(crate) fn is_first(&self) -> bool {
+        self.cursor.is_none()
+    }
+
+    pub(crate) fn limit(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.get() as usize)
+    }
+
+    fn sort(&self) -> (MediaSort, SortOrder) {
+        match self.cursor {
+            Some(ref cursor) => (cursor.sort, cursor.order),
+            None => (self.sort, self.order),
+        }
+    }
+
+    /// The cursor that starts the page after the given media.
+    pub(crate) fn cursor_after(&self, media: &MediaView) -> PageCursor {
+        let (sort, order) = self.sort();
+
+        PageCursor {
+            sort,
+            order,
+            value: sort.value(media),
+            id: media.id.clone(),
+        }
+    }
+
+    /// Adds the condition skipping previous pages followed by the ordering
+    /// and limit. Must follow a `WHERE` clause on a query that selects from
+    /// `media_view`.
+    pub(crate) fn bind_page(&self, builder: &mut QueryBuilder<SqlxDatabase>) {
+        let (sort, order) = self.sort();
+
+        if let Some(ref cursor) = self.cursor {
+            builder.push(format!(
+                r#" AND ({}, "media_view"."id") {} ("#,
+                sort.expression(),
+                order.comparison()
+            ));
+            match cursor.value {
+                SortValue::Timestamp(timestamp) => builder.push_bind(timestamp),
+                SortValue::Integer(integer) => builder.push_bind(integer),
+                SortValue::Text(ref text) => builder.push_bind(text.clone()),
+            };
+            builder.push(", ");
+            builder.push_bind(cursor.id.clone());
+            builder.push(")");
+        }
+
+        builder.push(format!(
+            r#" ORDER BY {} {}, "media_view"."id" {}"#,
+            sort.expression(),
+            order.keyword(),
+            order.keyword()
+        ));
+
+        if let Some(limit) = self.limit {
+            // One more than needed reveals whether there is another page.
+            builder.push(" LIMIT ");
+            builder.push_bind(i64::from(limit.get()) + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::{MediaSort, PageCursor, SortOrder, SortValue};
+
+    #[test]
+    fn cursors() {
+        let cursors = [
+            PageCursor {
+                sort: MediaSort::Created,
+                order: SortOrder::Ascending,
+                value: SortValue::Timestamp(Utc.with_ymd_and_hms(2024, 2, 3, 4, 5, 6).unwrap()),
+                id: "M:1".to_string(),
+            },
+            PageCursor {
+                sort: MediaSort::Rating,
+                order: SortOrder::Descending,
+                value: SortValue::Integer(-1),
+                id: "M:2".to_string(),
+            },
+            PageCursor {
+                sort: MediaSort::Filename,
+                order: SortOrder::Descending,
+                value: SortValue::Text("2024-02-03T04:05:06Z".to_string()),
+                id: "M:3".to_string(),
+            },
+        ];
+
+        for cursor in cursors {
+            assert_eq!(PageCursor::try_from(cursor.to_string()), Ok(cursor));
+        }
+
+        assert!(PageCursor::try_from("garbage!".to_string()).is_err());
+        assert!(PageCursor::try_from("e30".to_string()).is_err());
+    }
+}
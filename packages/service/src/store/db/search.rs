@@ -2,9 +2,10 @@ use serde::{Deserialize, Serialize};
 use serde_plain::derive_display_from_serialize;
 use sqlx::QueryBuilder;
 use tracing::instrument;
+use utoipa::ToSchema;
 
 use crate::store::{
-    db::{DbConnection, SqlxDatabase},
+    db::{page::MediaPage, DbConnection, SqlxDatabase},
     models::{MediaView, MediaViewSender},
 };
 
@@ -42,7 +43,7 @@ where
     );
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(untagged)]
 pub(crate) enum SqlValue {
     String(String),
@@ -62,7 +63,7 @@ impl SqlValue {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "lowercase", tag = "operator", content = "value")]
 pub(crate) enum Operator {
     Empty,
@@ -76,7 +77,7 @@ pub(crate) enum Operator {
     Matches(String),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, ToSchema)]
 #[serde(rename_all = "lowercase")]
 pub(crate) enum Modifier {
     Length,
@@ -114,7 +115,7 @@ impl Modifier {
     }
 }
 
-#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, ToSchema)]
 pub(crate) enum Join {
     #[default]
     #[serde(rename = "&&")]
@@ -123,7 +124,7 @@ pub(crate) enum Join {
     Or,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum MediaField {
     Title,
@@ -181,7 +182,7 @@ impl Field for MediaField {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum TagField {
     Id,
@@ -238,7 +239,7 @@ impl RelationField for TagField {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum PersonField {
     Id,
@@ -284,7 +285,7 @@ impl RelationField for PersonField {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) enum AlbumField {
     Id,
@@ -341,7 +342,7 @@ impl RelationField for AlbumField {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct FieldQuery<F> {
     #[serde(default, skip_serializing_if = "is_false")]
@@ -476,13 +477,14 @@ where
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone, ToSchema)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub(crate) enum RelationCompoundItem<F>
 where
     F: Field,
 {
     Field(FieldQuery<F>),
+    #[schema(value_type = Object)]
     Compound(CompoundQuery<Self>),
 }
 
@@ -506,7 +508,7 @@ fn is_and(join: &Join) -> bool {
     matches!(join, Join::And)
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct RelationQuery<F>
 where
     F: RelationField,
@@ -526,13 +528,14 @@ where
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub(crate) enum CompoundItem {
     Field(FieldQuery<MediaField>),
     Tag(RelationQuery<TagField>),
     Person(RelationQuery<PersonField>),
     Album(RelationQuery<AlbumField>),
+    #[schema(no_recursion)]
     Compound(CompoundQuery<Self>),
 }
 
@@ -548,7 +551,74 @@ impl Filterable for CompoundItem {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl<F> RelationCompoundItem<F>
+where
+    F: Field,
+{
+    fn references(&mut self) -> Vec<&mut String> {
+        match self {
+            RelationCompoundItem::Field(f) => match (f.field.field_type(), &mut f.operator) {
+                (FieldType::Reference, Operator::Equal(SqlValue::String(id))) => vec![id],
+                _ => Vec::new(),
+            },
+            RelationCompoundItem::Compound(f) => {
+                f.queries.iter_mut().flat_map(|q| q.references()).collect()
+            }
+        }
+    }
+}
+
+impl CompoundItem {
+    fn person_references(&mut self) -> Vec<&mut String> {
+        match self {
+            CompoundItem::Person(f) => f
+                .query
+                .queries
+                .iter_mut()
+                .flat_map(|q| q.references())
+                .collect(),
+            CompoundItem::Compound(f) => f
+                .queries
+                .iter_mut()
+                .flat_map(|q| q.person_references())
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl CompoundQuery<CompoundItem> {
+    fn person_references(&mut self) -> Vec<&mut String> {
+        self.queries
+            .iter_mut()
+            .flat_map(|q| q.person_references())
+            .collect()
+    }
+
+    /// Returns true if this query matches against any of the given people.
+    pub(crate) fn references_people(&mut self, people: &[String]) -> bool {
+        self.person_references()
+            .into_iter()
+            .any(|id| people.contains(id))
+    }
+
+    /// Replaces any references to the given people with a reference to a
+    /// different person. Returns true if the query was changed.
+    pub(crate) fn replace_people(&mut self, people: &[String], replacement: &str) -> bool {
+        let mut changed = false;
+
+        for id in self.person_references() {
+            if people.contains(id) {
+                replacement.clone_into(id);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, ToSchema)]
 pub(crate) struct CompoundQuery<Q> {
     #[serde(default, skip_serializing_if = "is_false")]
     pub(crate) invert: bool,
@@ -573,6 +643,7 @@ impl CompoundQuery<CompoundItem> {
         self,
         mut conn: DbConnection<'static>,
         catalog: String,
+        page: MediaPage,
         sender: MediaViewSender,
     ) {
         let mut builder =
@@ -580,10 +651,10 @@ impl CompoundQuery<CompoundItem> {
         builder.push_bind(&catalog);
         builder.push(" AND ");
         self.bind_filter(&catalog, &mut builder);
-        builder.push(r#" ORDER BY "datetime" DESC"#);
+        page.bind_page(&mut builder);
 
         let stream = builder.build_query_as::<MediaView>().fetch(&mut conn);
-        sender.send_stream(stream).await
+        sender.send_stream(stream, &page, None).await
     }
 }
 
@@ -629,7 +700,7 @@ where
 
 #[cfg(test)]
 mod tests {
-    use super::CompoundItem;
+    use super::{CompoundItem, SearchQuery};
 
     fn attempt_parse(json: &str) {
         serde_json::from_str::<CompoundItem>(json).unwrap();
@@ -891,4 +962,55 @@ mod tests {
             }"#,
         );
     }
+
+    #[test]
+    fn replace_people() {
+        let mut query: SearchQuery = serde_json::from_str(
+            r#"{
+                "queries": [
+                    {
+                        "type": "person",
+                        "join": "||",
+                        "queries": [
+                            {
+                                "type": "field",
+                                "field": "id",
+                                "operator": "equal",
+                                "value": "P:JDkiNRe5vR"
+                            },
+                            {
+                                "type": "field",
+                                "field": "name",
+                                "operator": "equal",
+                                "value": "P:JDkiNRe5vR"
+                            }
+                        ]
+                    },
+                    {
+                        "type": "tag",
+                        "queries": [
+                            {
+                                "type": "field",
+                                "field": "id",
+                                "operator": "equal",
+                                "value": "P:JDkiNRe5vR"
+                            }
+                        ]
+                    }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let people = vec!["P:JDkiNRe5vR".to_owned()];
+        assert!(query.references_people(&people));
+        assert!(query.replace_people(&people, "P:i9hZrZVwPP"));
+        assert!(!query.references_people(&people));
+        assert!(!query.replace_people(&people, "P:i9hZrZVwPP"));
+
+        let json = serde_json::to_value(&query).unwrap();
+        assert_eq!(json["queries"][0]["queries"][0]["value"], "P:i9hZrZVwPP");
+        assert_eq!(json["queries"][0]["queries"][1]["value"], "P:JDkiNRe5vR");
+        assert_eq!(json["queries"][1]["queries"][0]["value"], "P:JDkiNRe5vR");
+    }
 }
@@ -8,7 +8,6 @@ use std::{
 use async_trait::async_trait;
 use mime::Mime;
 use pixelbin_shared::{Config, Ignorable};
-use tempfile::TempPath;
 use tokio::fs;
 use tracing::{debug, instrument};
 
@@ -67,8 +66,8 @@ impl DiskStore {
         local_path
     }
 
-    #[instrument(level = "trace", skip(self, temp_file), err)]
-    pub(crate) async fn copy_from_temp(&self, temp_file: TempPath, path: &FilePath) -> Result {
+    #[instrument(level = "trace", skip(self), err)]
+    pub(crate) async fn copy_from_temp(&self, source: &Path, path: &FilePath) -> Result {
         if self.testing {
             debug!("Not pushing in testing mode.");
             return Ok(());
@@ -80,10 +79,8 @@ impl DiskStore {
             fs::create_dir_all(parent).await?;
         }
 
-        let source = temp_file.to_path_buf();
-
-        if fs::hard_link(&source, &target).await.is_err() {
-            fs::copy(&source, &target)
+        if fs::hard_link(source, &target).await.is_err() {
+            fs::copy(source, &target)
                 .await
                 .map_err(|e| crate::Error::Unknown {
                     message: e.to_string(),
@@ -4,7 +4,7 @@ use std::{
     sync::{Arc, Mutex, Weak},
 };
 
-use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::sync::{Mutex as AsyncMutex, OwnedSemaphorePermit, Semaphore};
 
 use crate::{
     store::{models, path::MediaItemStore},
@@ -55,6 +55,7 @@ impl<T> Deref for ResourceGuard<T> {
 #[derive(Default)]
 struct Inner {
     media_items: HashMap<String, Weak<ResourceLock<MediaItemLock>>>,
+    uploads: HashMap<String, Weak<AsyncMutex<()>>>,
 }
 
 #[derive(Clone)]
@@ -75,6 +76,22 @@ impl Locks {
         self.expensive_tasks.clone().acquire_owned().await.unwrap()
     }
 
+    /// Serializes writes to a chunked upload's temporary file.
+    pub(crate) fn upload(&self, id: &str) -> Arc<AsyncMutex<()>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        if let Some(lock) = inner.uploads.get(id).and_then(|w| w.upgrade()) {
+            lock
+        } else {
+            inner.uploads.retain(|_, lock| lock.strong_count() > 0);
+
+            let lock = Arc::new(AsyncMutex::new(()));
+            inner.uploads.insert(id.to_owned(), Arc::downgrade(&lock));
+
+            lock
+        }
+    }
+
     pub(crate) fn media_item(
         &self,
         store: &Store,
@@ -1,4 +1,8 @@
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    io::ErrorKind,
+    time::{Duration, SystemTime},
+};
 
 use chrono::{DateTime, Utc};
 use futures::join;
@@ -12,7 +16,7 @@ use crate::{
     metadata::{alternates_for_media_file, METADATA_FILE},
     shared::json::FromDb,
     store::{
-        db::{functions::from_row, Isolation},
+        db::{functions::from_row, DbConnection, Isolation},
         file::{DiskStore, FileStore},
         models,
         path::{CatalogStore, FilePath, MediaFileStore, ResourceList, ResourcePath},
@@ -20,9 +24,43 @@ use crate::{
     Result, Store, Task,
 };
 
+/// Deletes the data of uploads that are no longer in progress.
+async fn clean_uploads(store: &Store, conn: &mut DbConnection<'_>) -> Result {
+    let in_progress = models::MediaUpload::clean(conn).await?;
+
+    let mut entries = match fs::read_dir(models::MediaUpload::directory(store.config())).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    // Skips recently written files in case the upload was started after the
+    // list of uploads was read.
+    let cutoff = SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+
+    while let Some(entry) = entries.next_entry().await? {
+        let abandoned = entry
+            .file_name()
+            .to_str()
+            .is_none_or(|name| !in_progress.contains(name));
+
+        if abandoned && entry.metadata().await?.modified()? < cutoff {
+            fs::remove_file(entry.path()).await.warn();
+        }
+    }
+
+    Ok(())
+}
+
 pub(super) async fn clean_queues(store: Store) -> Result {
     let mut conn = store.connect().await?;
-    models::SavedSearch::clean_subscriptions(&mut conn).await
+    clean_uploads(&store, &mut conn).await?;
+    models::SavedSearch::clean_subscriptions(&mut conn).await?;
+    models::User::clean_verifications(&mut conn).await?;
+    models::User::clean_sessions(&mut conn).await?;
+    models::User::clean_challenges(&mut conn).await?;
+    models::IdempotencyKey::clean(&mut conn).await?;
+    models::OidcLogin::clean(&mut conn).await
 }
 
 pub(super) async fn server_startup(store: Store) -> Result {
@@ -1,4 +1,4 @@
-use std::{cmp, collections::HashMap};
+use std::{cmp, collections::HashMap, slice};
 
 use pixelbin_shared::IgnorableFuture;
 use tokio::fs;
@@ -9,7 +9,7 @@ use crate::{
         encode_alternate_image, encode_alternate_video, parse_media, parse_metadata, FileMetadata,
         METADATA_FILE,
     },
-    shared::file_exists,
+    shared::{file_exists, file_sha256},
     store::{
         db::{models, Isolation},
         file::{DiskStore, FileStore},
@@ -54,6 +54,48 @@ async fn extract_metadata(store: &Store, op_cache: MediaFileOpCache) -> Result {
     conn.commit().await
 }
 
+/// Hashes files stored before uploads were hashed.
+#[instrument(skip(store, op_cache), err)]
+async fn store_sha256(store: &Store, op_cache: MediaFileOpCache) -> Result {
+    trace!("Computing SHA-256");
+
+    let sha256 = file_sha256(&op_cache.ensure_local().await?).await?;
+
+    let mut conn = store.isolated(Isolation::Committed).await?;
+    let (mut media_file, _) = models::MediaFile::get(&mut conn, &op_cache.media_file.id).await?;
+    media_file.sha256 = Some(sha256);
+    models::MediaFile::upsert(&mut conn, &[media_file]).await?;
+    conn.commit().await
+}
+
+#[instrument(skip(store, op_cache), err)]
+async fn store_perceptual_hash(store: &Store, op_cache: MediaFileOpCache) -> Result {
+    trace!("Computing perceptual hash");
+
+    let phash = op_cache.perceptual_hash().await?;
+
+    let mut conn = store.isolated(Isolation::Committed).await?;
+    let (mut media_file, _) = models::MediaFile::get(&mut conn, &op_cache.media_file.id).await?;
+    media_file.phash = Some(phash);
+    models::MediaFile::upsert(&mut conn, slice::from_ref(&media_file)).await?;
+    media_file.record_similar(&mut conn).await?;
+    conn.commit().await
+}
+
+/// Records similar files for hashes computed before similar files were
+/// recorded.
+#[instrument(skip(store), err)]
+async fn record_pending_similar(store: &Store, media_file_id: &str) -> Result {
+    let mut conn = store.isolated(Isolation::Committed).await?;
+    if let Some(media_file) =
+        models::MediaFile::take_similar_pending(&mut conn, media_file_id).await?
+    {
+        trace!("Recording similar files");
+        media_file.record_similar(&mut conn).await?;
+    }
+    conn.commit().await
+}
+
 #[instrument(skip(store), err)]
 pub(super) async fn upload_media_file(mut store: Store, media_file_id: &str) -> Result {
     trace!("Uploading media file");
@@ -182,6 +224,16 @@ pub(super) async fn process_media_file(mut store: Store, media_file_id: &str) ->
         extract_metadata(&store, op_cache.clone()).warn().await;
     }
 
+    if media_file.sha256.is_none() {
+        store_sha256(&store, op_cache.clone()).warn().await;
+    }
+
+    if media_file.phash.is_none() {
+        store_perceptual_hash(&store, op_cache.clone()).warn().await;
+    } else {
+        record_pending_similar(&store, media_file_id).warn().await;
+    }
+
     let mut modified = Vec::<models::AlternateFile>::new();
 
     for mut alternate_file in
@@ -279,5 +331,7 @@ pub(super) async fn prune_deleted_media(store: Store, catalog: &str) -> Result {
         models::SavedSearch::update_for_catalog(&mut conn, catalog).await?;
     }
 
+    models::TrashedAlbum::purge(&mut conn, catalog).await?;
+
     Ok(())
 }
@@ -9,7 +9,7 @@ use tokio::{
 };
 
 use crate::{
-    metadata::{crop_image, load_source_image, resize_image},
+    metadata::{crop_image, load_source_image, perceptual_hash, resize_image},
     shared::file_exists,
     store::{
         file::{DiskStore, FileStore},
@@ -182,6 +182,11 @@ impl MediaFileOpCache {
             .await
     }
 
+    pub(super) async fn perceptual_hash(&self) -> Result<i64> {
+        let image = self.decode().await?;
+        Ok(perceptual_hash(image).await)
+    }
+
     pub(super) async fn resize(&self, size: i32) -> Result<DynamicImage> {
         self.resize
             .perform(size, || async {
@@ -14,6 +14,7 @@ use serde::{
     de::{Error as _, Unexpected},
     Deserialize, Deserializer, Serialize,
 };
+use utoipa::ToSchema;
 
 use crate::{Error, Result};
 
@@ -56,6 +57,11 @@ mod mimes {
 
 const DEFAULT_API_PORT: u16 = 8283;
 const DEFAULT_WEB_PORT: u16 = 3000;
+const DEFAULT_SESSION_LIFETIME: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const DEFAULT_MEDIA_FILE_HISTORY: u32 = 3;
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 4 * 1024 * 1024 * 1024;
 
 fn duration_from_secs<'de, D>(deserializer: D) -> result::Result<Duration, D::Error>
 where
@@ -76,10 +82,11 @@ pub struct RateLimit {
     pub status: Option<u16>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ThumbnailConfig {
     #[serde(with = "mimes")]
+    #[schema(value_type = Vec<String>)]
     pub alternate_types: Vec<Mime>,
     pub sizes: Vec<u32>,
 }
@@ -105,6 +112,42 @@ pub enum MailServer {
     },
 }
 
+/// Cost parameters used when hashing passwords with Argon2id.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Argon2Config {
+    /// Memory to use in KiB.
+    pub memory_cost: u32,
+    /// Number of iterations.
+    pub time_cost: u32,
+    /// Degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Argon2Config {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+/// An OpenID Connect provider that users can log in with.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OidcConfig {
+    /// The provider's issuer URL, used to discover its endpoints. It must use
+    /// https unless testing is enabled.
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    /// Creates accounts for unknown users when they first log in.
+    #[serde(default)]
+    pub auto_provision: bool,
+}
+
 #[derive(Clone, Debug)]
 pub struct Config {
     /// The hostname of the opentelemetry endpoint to use.
@@ -143,6 +186,34 @@ pub struct Config {
 
     pub max_workers: usize,
 
+    /// How long a login session lasts without being used.
+    pub session_lifetime: Duration,
+
+    /// Settings for hashing new passwords.
+    pub argon2: Argon2Config,
+
+    /// An optional OpenID Connect provider to allow logins from.
+    pub oidc: Option<OidcConfig>,
+
+    /// The secret used to sign media URLs. When not set a random secret is
+    /// used and signed URLs stop working when the server restarts.
+    pub url_signing_key: Option<String>,
+
+    /// How long the response to a request with an idempotency key is kept
+    /// for replaying to retries.
+    pub idempotency_window: Duration,
+
+    /// How long deleted media and albums stay in the trash before they are
+    /// removed for good.
+    pub trash_retention: Duration,
+
+    /// The number of previous files kept for each media item so that the
+    /// item can be reverted to them.
+    pub media_file_history: u32,
+
+    /// The largest file in bytes that can be uploaded in chunks.
+    pub max_upload_size: u64,
+
     /// Disables writing to remote stores for testing purposes.
     pub testing: bool,
 }
@@ -189,6 +260,14 @@ struct ParsedConfig {
     thumbnails: Option<ThumbnailConfig>,
     rate_limits: Option<Vec<RateLimit>>,
     max_workers: Option<usize>,
+    session_lifetime: Option<u64>,
+    argon2: Option<Argon2Config>,
+    oidc: Option<OidcConfig>,
+    url_signing_key: Option<String>,
+    idempotency_window: Option<u64>,
+    trash_retention: Option<u64>,
+    media_file_history: Option<u32>,
+    max_upload_size: Option<u64>,
     #[serde(default)]
     testing: bool,
 }
@@ -314,6 +393,25 @@ impl Config {
                 ]
             }),
             max_workers: parsed.max_workers.unwrap_or(1),
+            session_lifetime: parsed
+                .session_lifetime
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_SESSION_LIFETIME),
+            argon2: parsed.argon2.unwrap_or_default(),
+            oidc: parsed.oidc,
+            url_signing_key: parsed.url_signing_key,
+            idempotency_window: parsed
+                .idempotency_window
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_IDEMPOTENCY_WINDOW),
+            trash_retention: parsed
+                .trash_retention
+                .map(Duration::from_secs)
+                .unwrap_or(DEFAULT_TRASH_RETENTION),
+            media_file_history: parsed
+                .media_file_history
+                .unwrap_or(DEFAULT_MEDIA_FILE_HISTORY),
+            max_upload_size: parsed.max_upload_size.unwrap_or(DEFAULT_MAX_UPLOAD_SIZE),
             testing: parsed.testing,
         })
     }
@@ -11,10 +11,10 @@ export const action = safeAction(
     let email = getFormString(formData, "email");
     let password = getFormString(formData, "password");
 
-    await login(requestContext, email, password);
+    let challenge = await login(requestContext, email, password);
 
     return Response.json(
-      {},
+      { challenge },
       {
         headers: {
           "Set-Cookie": await requestContext.commit(),
@@ -0,0 +1,25 @@
+import { getRequestContext } from "@/modules/RequestContext";
+import { getFormString, safeAction } from "@/modules/actions";
+import { loginTotp } from "@/modules/api";
+
+import type { Route } from "./+types/loginTotp";
+
+export const action = safeAction(
+  async ({ request, context }: Route.ActionArgs) => {
+    let requestContext = await getRequestContext(request, context);
+    let formData = await request.formData();
+    let challenge = getFormString(formData, "challenge");
+    let code = getFormString(formData, "code");
+
+    await loginTotp(requestContext, challenge, code);
+
+    return Response.json(
+      {},
+      {
+        headers: {
+          "Set-Cookie": await requestContext.commit(),
+        },
+      },
+    );
+  },
+);
@@ -0,0 +1,27 @@
+import { redirect } from "react-router";
+
+import { safeLoader } from "@/modules/actions";
+import { oidcLogin } from "@/modules/api";
+import { getRequestContext } from "@/modules/RequestContext";
+
+import { Route } from "./+types/oidc";
+
+export const loader = safeLoader(
+  async ({ request, context }: Route.LoaderArgs) => {
+    let requestContext = await getRequestContext(request, context);
+
+    let url = new URL(request.url);
+    let challenge = await oidcLogin(requestContext, url.searchParams);
+
+    // The login dialog asks for the one-time password.
+    let target = challenge
+      ? `/?${new URLSearchParams({ challenge })}`
+      : "/";
+
+    return redirect(target, {
+      headers: {
+        "Set-Cookie": await requestContext.commit(),
+      },
+    });
+  },
+);
@@ -0,0 +1,22 @@
+import { redirect } from "react-router";
+
+import { safeLoader } from "@/modules/actions";
+import { verifyEmail } from "@/modules/api";
+import { getRequestContext } from "@/modules/RequestContext";
+
+import { Route } from "./+types/verify";
+
+export const loader = safeLoader(
+  async ({ request, context }: Route.LoaderArgs) => {
+    let requestContext = await getRequestContext(request, context);
+
+    let url = new URL(request.url);
+    let token = url.searchParams.get("token");
+
+    if (token) {
+      await verifyEmail(requestContext, token);
+    }
+
+    return redirect("/");
+  },
+);
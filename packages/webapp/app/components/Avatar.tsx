@@ -1,10 +1,12 @@
-import { useFetcher } from "react-router";
+import { useFetcher, useSearchParams } from "react-router";
 import md5 from "md5";
-import { FormEvent, useCallback, useState } from "react";
+import { FormEvent, useCallback, useEffect, useState } from "react";
 
 import Button from "./Button";
 import Dialog from "./Dialog";
 import TextField from "./TextField";
+import { useServerConfig } from "@/modules/hooks";
+import { showToast } from "@/modules/toast";
 
 import "styles/components/Avatar.scss";
 
@@ -18,13 +20,55 @@ function avatarSources(email: string): string[] {
 }
 
 function Login() {
-  let fetcher = useFetcher();
+  let config = useServerConfig();
+  let fetcher = useFetcher<{ challenge?: string | null }>();
+  let [searchParams, setSearchParams] = useSearchParams();
   let [dialogShown, setDialogShown] = useState(false);
 
   let [email, setEmail] = useState("");
   let [password, setPassword] = useState("");
+  let [challenge, setChallenge] = useState<string | null>(null);
+  let [code, setCode] = useState("");
+
+  // A challenge comes either from a password login or from a single sign-on
+  // redirect.
+  useEffect(() => {
+    if (fetcher.data?.challenge) {
+      setChallenge(fetcher.data.challenge);
+    }
+  }, [fetcher.data]);
+
+  useEffect(() => {
+    let redirected = searchParams.get("challenge");
+    if (redirected) {
+      setChallenge(redirected);
+      setDialogShown(true);
+      setSearchParams(
+        (params) => {
+          params.delete("challenge");
+          return params;
+        },
+        { replace: true },
+      );
+    }
+  }, [searchParams, setSearchParams]);
 
   let performLogin = useCallback(() => {
+    if (challenge) {
+      if (code == "") {
+        return;
+      }
+
+      void fetcher.submit(
+        { challenge, code },
+        {
+          action: "/login/totp",
+          method: "POST",
+        },
+      );
+      return;
+    }
+
     if (email == "") {
       return;
     }
@@ -36,11 +80,13 @@ function Login() {
         method: "POST",
       },
     );
-  }, [fetcher, email, password]);
+  }, [fetcher, email, password, challenge, code]);
 
   let closed = useCallback(() => {
     setEmail("");
     setPassword("");
+    setChallenge(null);
+    setCode("");
     setDialogShown(false);
   }, []);
 
@@ -52,14 +98,23 @@ function Login() {
     [performLogin],
   );
 
+  let oidcLogin = useCallback(() => {
+    window.location.assign(`${config.apiUrl}api/oidc/login`);
+  }, [config]);
+
   let footer = (
     <>
+      {config.oidcLogin && !challenge && (
+        <Button onClick={oidcLogin} label="Single sign-on" />
+      )}
       <Button onClick={() => setDialogShown(false)} label="Cancel" />
       <Button
         onClick={performLogin}
         type="primary"
         label="Login"
-        disabled={email == "" && fetcher.state == "idle"}
+        disabled={
+          (challenge ? code == "" : email == "") && fetcher.state == "idle"
+        }
       />
     </>
   );
@@ -76,6 +131,133 @@ function Login() {
         onClosed={closed}
         label="Login"
         footer={footer}
+      >
+        <form onSubmit={formSubmit}>
+          {challenge ? (
+            <TextField
+              autofocus
+              name="code"
+              autocomplete="one-time-code"
+              label="One-time password:"
+              value={code}
+              onChange={setCode}
+            />
+          ) : (
+            <>
+              <TextField
+                autofocus
+                type="email"
+                name="email"
+                autocomplete="email"
+                label="Email Address:"
+                value={email}
+                onChange={setEmail}
+              />
+              <TextField
+                type="password"
+                name="password"
+                autocomplete="password"
+                label="Password:"
+                value={password}
+                onChange={setPassword}
+              />
+            </>
+          )}
+        </form>
+      </Dialog>
+    </>
+  );
+}
+
+function Register() {
+  let config = useServerConfig();
+  let [dialogShown, setDialogShown] = useState(false);
+  let [submitting, setSubmitting] = useState(false);
+
+  let [email, setEmail] = useState("");
+  let [fullname, setFullname] = useState("");
+  let [password, setPassword] = useState("");
+
+  let performRegister = useCallback(() => {
+    if (email == "" || password == "") {
+      return;
+    }
+
+    setSubmitting(true);
+    fetch(`${config.apiUrl}api/register`, {
+      method: "POST",
+      headers: {
+        "Content-Type": "application/json",
+      },
+      body: JSON.stringify({ email, password, fullname: fullname || null }),
+    })
+      .then(async (response) => {
+        if (!response.ok) {
+          let { message } = (await response
+            .json()
+            .catch(() => ({}))) as { message?: string };
+
+          return showToast(
+            "danger",
+            `Sign up failed: ${message ?? response.statusText}`,
+            { duration: 5000 },
+          );
+        } else {
+          setDialogShown(false);
+          return showToast(
+            "success",
+            `Check your email to verify your account`,
+            { duration: 5000 },
+          );
+        }
+      })
+      .catch((error) => {
+        return showToast("danger", `Sign up failed: ${error}`, {
+          duration: 5000,
+        });
+      })
+      .finally(() => setSubmitting(false));
+  }, [config, email, fullname, password]);
+
+  let closed = useCallback(() => {
+    setEmail("");
+    setFullname("");
+    setPassword("");
+    setDialogShown(false);
+  }, []);
+
+  let formSubmit = useCallback(
+    (event: FormEvent) => {
+      event.preventDefault();
+      performRegister();
+    },
+    [performRegister],
+  );
+
+  let footer = (
+    <>
+      <Button onClick={() => setDialogShown(false)} label="Cancel" />
+      <Button
+        onClick={performRegister}
+        type="primary"
+        label="Sign up"
+        disabled={email == "" || password == "" || submitting}
+      />
+    </>
+  );
+
+  return (
+    <>
+      <Button
+        className="sl-theme-light"
+        onClick={() => setDialogShown(true)}
+        label="Sign up"
+      />
+      <Dialog
+        show={dialogShown}
+        onClosed={closed}
+        label="Sign up"
+        footer={footer}
       >
         <form onSubmit={formSubmit}>
           <TextField
@@ -87,10 +269,17 @@ function Login() {
             value={email}
             onChange={setEmail}
           />
+          <TextField
+            name="name"
+            autocomplete="name"
+            label="Name:"
+            value={fullname}
+            onChange={setFullname}
+          />
           <TextField
             type="password"
             name="password"
-            autocomplete="password"
+            autocomplete="new-password"
             label="Password:"
             value={password}
             onChange={setPassword}
@@ -121,5 +310,12 @@ function Menu({ email }: { email: string }) {
 }
 
 export default function Avatar({ email }: { email: string | undefined }) {
-  return email ? <Menu email={email} /> : <Login />;
+  return email ? (
+    <Menu email={email} />
+  ) : (
+    <>
+      <Register />
+      <Login />
+    </>
+  );
 }
@@ -163,7 +163,12 @@ function fetchMedia(
       }
 
       let jsonStream = new NdjsonStream<
-        { media: ApiMediaView[] } | { error: string }
+        | { media: ApiMediaView[] }
+        | { deleted: string[] }
+        | { removed: string[] }
+        | { since: string }
+        | { cursor: string | null }
+        | { error: string }
       >();
       let reader = response.body
         .pipeThrough(jsonStream, {
@@ -180,7 +185,7 @@ function fetchMedia(
         if ("media" in value) {
           grouper.addMedia(value.media.map(deserializeMediaView));
           setContext(grouper.context);
-        } else {
+        } else if ("error" in value) {
           console.error(value.error);
           break;
         }
@@ -207,6 +207,7 @@ export interface ApiConfig {
   webappChangeset?: string;
   apiUrl: string;
   thumbnails: ThumbnailConfig;
+  oidcLogin: boolean;
 }
 
 export async function config(context: RequestContext): Promise<ApiConfig> {
@@ -218,11 +219,14 @@ export async function config(context: RequestContext): Promise<ApiConfig> {
   return toJson(response);
 }
 
+/**
+ * Returns a challenge if the user must also provide a one-time password.
+ */
 export async function login(
   context: RequestContext,
   email: string,
   password: string,
-) {
+): Promise<string | null> {
   let response = await apiCall(
     "/api/login",
     "login",
@@ -240,6 +244,68 @@ export async function login(
   if (result.token) {
     context.set("token", result.token);
   }
+
+  return result.challenge ?? null;
+}
+
+export async function loginTotp(
+  context: RequestContext,
+  challenge: string,
+  code: string,
+) {
+  let response = await apiCall(
+    "/api/login/totp",
+    "loginTotp",
+    POST,
+    json({
+      challenge,
+      code,
+    }),
+    forwardedRequest(context),
+    (init) => ({ ...init, cache: "no-store" }),
+  );
+
+  let result: LoginResponse = await response.json();
+
+  if (result.token) {
+    context.set("token", result.token);
+  }
+}
+
+/**
+ * Returns a challenge if the user must also provide a one-time password.
+ */
+export async function oidcLogin(
+  context: RequestContext,
+  params: URLSearchParams,
+): Promise<string | null> {
+  let response = await apiCall(
+    `/api/oidc/callback?${params}`,
+    "oidcLogin",
+    forwardedRequest(context),
+    (init) => ({ ...init, cache: "no-store" }),
+  );
+
+  let result: LoginResponse = await response.json();
+
+  if (result.token) {
+    context.set("token", result.token);
+  }
+
+  return result.challenge ?? null;
+}
+
+export async function verifyEmail(context: RequestContext, token: string) {
+  await apiCall(
+    "/api/register/verify",
+    "verifyEmail",
+    POST,
+    json({
+      token,
+    }),
+    forwardedRequest(context),
+    (init) => ({ ...init, cache: "no-store" }),
+  );
 }
 
 export async function verifySubscription(
@@ -113,6 +113,7 @@ export interface LoginRequest {
 
 export interface LoginResponse {
   token: string | null;
+  challenge?: string;
 }
 
 export type State = User & {
@@ -20,8 +20,11 @@ export default [
   route("/api/config", "api/config.ts"),
   route("/search/subscribe", "api/subscribe.ts"),
   route("/search/unsubscribe", "api/unsubscribe.ts"),
+  route("/register/verify", "api/verify.ts"),
   route("/media/*", "api/media.ts"),
   route("/login", "actions/login.ts"),
+  route("/login/totp", "actions/loginTotp.ts"),
+  route("/login/oidc/callback", "api/oidc.ts"),
   route("/logout", "actions/logout.ts"),
   route("/markPublic", "actions/markPublic.ts"),
   route("/*", "routes/notfound.tsx"),